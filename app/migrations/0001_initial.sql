@@ -0,0 +1,44 @@
+CREATE TABLE peers (
+  address TEXT PRIMARY KEY NOT NULL,
+  first_seen TEXT NOT NULL,
+  last_seen TEXT NOT NULL
+);
+
+CREATE TABLE connections (
+  id INTEGER PRIMARY KEY NOT NULL,
+  client_addr TEXT NOT NULL,
+  target_addr TEXT NOT NULL REFERENCES peers(address),
+  network TEXT NOT NULL,
+  opened_at TEXT NOT NULL,
+  closed_at TEXT,
+  bytes_inbound INTEGER NOT NULL DEFAULT 0,
+  bytes_outbound INTEGER NOT NULL DEFAULT 0,
+  messages_inbound INTEGER NOT NULL DEFAULT 0,
+  messages_outbound INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX idx_connections_target_addr ON connections(target_addr);
+CREATE INDEX idx_connections_opened_at ON connections(opened_at);
+
+CREATE TABLE handshakes (
+  connection_id INTEGER PRIMARY KEY NOT NULL REFERENCES connections(id),
+  outcome TEXT NOT NULL DEFAULT 'pending',
+  peer_version INTEGER,
+  peer_services INTEGER,
+  peer_user_agent TEXT,
+  verack_inbound_at TEXT,
+  verack_outbound_at TEXT,
+  completed_at TEXT
+);
+
+CREATE TABLE messages (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  direction TEXT NOT NULL,
+  command TEXT NOT NULL,
+  payload_len INTEGER NOT NULL,
+  description TEXT NOT NULL,
+  timestamp TEXT NOT NULL
+);
+
+CREATE INDEX idx_messages_connection_id ON messages(connection_id);
@@ -0,0 +1,65 @@
+-- Handshakes are tracked from the moment a connection is accepted, so they
+-- may exist without an established connection (e.g. SOCKS5 or connect
+-- failures) and can no longer reference the connections table.
+CREATE TABLE handshakes_new (
+  connection_id INTEGER PRIMARY KEY NOT NULL,
+  client_addr TEXT NOT NULL,
+  target_addr TEXT,
+  outcome TEXT NOT NULL,
+  failure_reason TEXT,
+  failure_detail TEXT,
+  started_at TEXT NOT NULL,
+  completed_at TEXT,
+  failed_at TEXT,
+
+  local_version INTEGER,
+  local_services INTEGER,
+  local_timestamp INTEGER,
+  local_user_agent TEXT,
+  local_start_height INTEGER,
+  local_relay INTEGER,
+  local_nonce INTEGER,
+  local_addr_from TEXT,
+  local_addr_recv TEXT,
+  local_version_at TEXT,
+  local_verack_at TEXT,
+  local_wtxidrelay INTEGER NOT NULL DEFAULT 0,
+  local_sendaddrv2 INTEGER NOT NULL DEFAULT 0,
+  local_sendcmpct_announce INTEGER,
+  local_sendcmpct_version INTEGER,
+
+  remote_version INTEGER,
+  remote_services INTEGER,
+  remote_timestamp INTEGER,
+  remote_user_agent TEXT,
+  remote_start_height INTEGER,
+  remote_relay INTEGER,
+  remote_nonce INTEGER,
+  remote_addr_from TEXT,
+  remote_addr_recv TEXT,
+  remote_version_at TEXT,
+  remote_verack_at TEXT,
+  remote_wtxidrelay INTEGER NOT NULL DEFAULT 0,
+  remote_sendaddrv2 INTEGER NOT NULL DEFAULT 0,
+  remote_sendcmpct_announce INTEGER,
+  remote_sendcmpct_version INTEGER
+);
+
+INSERT INTO handshakes_new (
+  connection_id, client_addr, target_addr, outcome, failure_reason,
+  started_at, completed_at, failed_at, local_verack_at,
+  remote_version, remote_services, remote_user_agent, remote_verack_at
+)
+SELECT
+  h.connection_id, c.client_addr, c.target_addr, h.outcome,
+  CASE WHEN h.outcome = 'failed' THEN 'disconnected_before_verack' END,
+  c.opened_at, h.completed_at, CASE WHEN h.outcome = 'failed' THEN c.closed_at END,
+  h.verack_inbound_at, h.peer_version, h.peer_services, h.peer_user_agent, h.verack_outbound_at
+FROM handshakes h
+JOIN connections c ON c.id = h.connection_id;
+
+DROP TABLE handshakes;
+ALTER TABLE handshakes_new RENAME TO handshakes;
+
+CREATE INDEX idx_handshakes_outcome ON handshakes(outcome);
+CREATE INDEX idx_handshakes_target_addr ON handshakes(target_addr);
@@ -0,0 +1,16 @@
+-- Messages are stamped when the proxy read them and when it finished
+-- forwarding them to the other side.
+ALTER TABLE messages RENAME COLUMN timestamp TO received_at;
+ALTER TABLE messages ADD COLUMN forwarded_at TEXT;
+
+CREATE TABLE ping_rtts (
+  id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  direction TEXT NOT NULL,
+  nonce INTEGER NOT NULL,
+  ping_at TEXT NOT NULL,
+  pong_at TEXT NOT NULL,
+  rtt_us INTEGER NOT NULL
+);
+
+CREATE INDEX idx_ping_rtts_connection_id ON ping_rtts(connection_id);
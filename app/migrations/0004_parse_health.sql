@@ -0,0 +1,14 @@
+-- Latest parse health of each direction of a connection
+CREATE TABLE parse_health (
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  direction TEXT NOT NULL,
+  state TEXT NOT NULL,
+  desyncs INTEGER NOT NULL DEFAULT 0,
+  bytes_skipped INTEGER NOT NULL DEFAULT 0,
+  checksum_mismatches INTEGER NOT NULL DEFAULT 0,
+  oversize_payloads INTEGER NOT NULL DEFAULT 0,
+  unknown_commands INTEGER NOT NULL DEFAULT 0,
+  invalid_payloads INTEGER NOT NULL DEFAULT 0,
+  updated_at TEXT NOT NULL,
+  PRIMARY KEY (connection_id, direction)
+);
@@ -0,0 +1,18 @@
+-- Transport of each connection, NULL until the client sent enough bytes to tell
+ALTER TABLE connections ADD COLUMN transport TEXT;
+
+-- Final packet statistics of each direction of a connection
+CREATE TABLE packet_stats (
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  direction TEXT NOT NULL,
+  packets INTEGER NOT NULL,
+  bytes INTEGER NOT NULL,
+  first_at TEXT,
+  last_at TEXT,
+  size_le_64 INTEGER NOT NULL,
+  size_le_256 INTEGER NOT NULL,
+  size_le_1024 INTEGER NOT NULL,
+  size_le_4096 INTEGER NOT NULL,
+  size_gt_4096 INTEGER NOT NULL,
+  PRIMARY KEY (connection_id, direction)
+);
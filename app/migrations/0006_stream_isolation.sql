@@ -0,0 +1,2 @@
+-- Credentials the client sent with its SOCKS5 request, identifying its isolated stream
+ALTER TABLE handshakes ADD COLUMN stream_isolation TEXT;
@@ -0,0 +1,12 @@
+-- Failed connects to requested targets, which never got a connections row
+CREATE TABLE connect_failures (
+  connection_id INTEGER PRIMARY KEY NOT NULL,
+  client_addr TEXT NOT NULL,
+  target_addr TEXT NOT NULL,
+  network TEXT NOT NULL,
+  error TEXT NOT NULL,
+  detail TEXT NOT NULL,
+  failed_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_connect_failures_target_addr ON connect_failures(target_addr);
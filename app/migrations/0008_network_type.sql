@@ -0,0 +1,2 @@
+-- Network the peer is reached on, NULL for peers recorded before it was tracked
+ALTER TABLE peers ADD COLUMN network_type TEXT;
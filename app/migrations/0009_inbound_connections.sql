@@ -0,0 +1,2 @@
+-- Whether the peer connected to the node through the transparent listener
+ALTER TABLE connections ADD COLUMN inbound INTEGER NOT NULL DEFAULT 0;
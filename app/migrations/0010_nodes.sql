@@ -0,0 +1,7 @@
+-- Label of the node whose proxy saw the connection, rows from before are the default node
+ALTER TABLE connections ADD COLUMN node TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE handshakes ADD COLUMN node TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE connect_failures ADD COLUMN node TEXT NOT NULL DEFAULT 'default';
+
+CREATE INDEX idx_connections_node ON connections(node);
+CREATE INDEX idx_handshakes_node ON handshakes(node);
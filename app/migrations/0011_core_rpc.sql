@@ -0,0 +1,31 @@
+-- Bitcoin Core's view of proxied connections, from the latest getpeerinfo
+CREATE TABLE core_peers (
+  connection_id INTEGER PRIMARY KEY NOT NULL,
+  node TEXT NOT NULL,
+  peer_id INTEGER NOT NULL,
+  connection_type TEXT,
+  ban_score INTEGER,
+  starting_height INTEGER,
+  synced_headers INTEGER,
+  synced_blocks INTEGER,
+  updated_at TEXT NOT NULL
+);
+
+-- Latest state of each node, from its network, chain, traffic and mempool RPCs
+CREATE TABLE node_status (
+  node TEXT PRIMARY KEY NOT NULL,
+  version INTEGER NOT NULL,
+  subversion TEXT NOT NULL,
+  chain TEXT NOT NULL,
+  blocks INTEGER NOT NULL,
+  headers INTEGER NOT NULL,
+  verification_progress REAL NOT NULL,
+  initial_block_download INTEGER NOT NULL,
+  connections_in INTEGER NOT NULL,
+  connections_out INTEGER NOT NULL,
+  total_bytes_recv INTEGER NOT NULL,
+  total_bytes_sent INTEGER NOT NULL,
+  mempool_size INTEGER NOT NULL,
+  mempool_bytes INTEGER NOT NULL,
+  updated_at TEXT NOT NULL
+);
@@ -0,0 +1,15 @@
+-- Events parsed from bitcoind's debug.log, with the proxied connection they were matched to
+CREATE TABLE log_events (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  node TEXT NOT NULL,
+  connection_id INTEGER,
+  peer_id INTEGER,
+  address TEXT,
+  kind TEXT NOT NULL,
+  height INTEGER,
+  message TEXT NOT NULL,
+  logged_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_log_events_node ON log_events(node);
+CREATE INDEX idx_log_events_connection_id ON log_events(connection_id);
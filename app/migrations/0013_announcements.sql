@@ -0,0 +1,26 @@
+-- Blocks and transactions announced by peers, by inv, headers or cmpctblock
+CREATE TABLE announcements (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  node TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  hash TEXT NOT NULL,
+  via TEXT NOT NULL,
+  announced_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_announcements_hash ON announcements(node, hash);
+CREATE INDEX idx_announcements_connection_id ON announcements(connection_id);
+
+-- Blocks and transactions the node accepted, as notified over ZMQ
+CREATE TABLE acceptances (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  node TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  hash TEXT NOT NULL,
+  wtxid TEXT,
+  accepted_at TEXT NOT NULL,
+  UNIQUE (node, kind, hash)
+);
+
+CREATE INDEX idx_acceptances_wtxid ON acceptances(node, wtxid);
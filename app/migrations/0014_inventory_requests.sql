@@ -0,0 +1,14 @@
+-- Blocks and transactions the node requested from peers with getdata
+CREATE TABLE inventory_requests (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  node TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  hash TEXT NOT NULL,
+  requested_at TEXT NOT NULL,
+  -- When the peer answered with notfound
+  not_found_at TEXT
+);
+
+CREATE INDEX idx_inventory_requests_hash ON inventory_requests(node, hash);
+CREATE INDEX idx_inventory_requests_connection_id ON inventory_requests(connection_id, hash);
@@ -0,0 +1,26 @@
+-- Transactions delivered by peers with tx messages
+CREATE TABLE tx_deliveries (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  node TEXT NOT NULL,
+  txid TEXT NOT NULL,
+  wtxid TEXT NOT NULL,
+  vsize INTEGER NOT NULL,
+  -- Only known when the values of all inputs are
+  fee INTEGER,
+  fee_rate REAL,
+  received_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_tx_deliveries_txid ON tx_deliveries(node, txid);
+CREATE INDEX idx_tx_deliveries_connection_id ON tx_deliveries(connection_id);
+
+-- Fee filters peers sent, in sat/kvB
+CREATE TABLE fee_filters (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  fee_rate INTEGER NOT NULL,
+  received_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_fee_filters_connection_id ON fee_filters(connection_id);
@@ -0,0 +1,5 @@
+-- Inbound peers are keyed by IP, the ephemeral port they connected from is kept apart
+ALTER TABLE connections ADD COLUMN peer_port INTEGER;
+
+-- Address the proxy connected to the node from, which the node reports as the inbound peer's
+ALTER TABLE connections ADD COLUMN local_addr TEXT;
@@ -0,0 +1,3 @@
+-- Announcements are also looked up by hash across all nodes, which (node, hash) can't serve
+DROP INDEX idx_announcements_hash;
+CREATE INDEX idx_announcements_hash ON announcements(hash, node);
@@ -0,0 +1,20 @@
+-- Notfound replies are kept even without a request, e.g. one the parser missed,
+-- so requested_at becomes optional. Items are also looked up by hash across all nodes.
+CREATE TABLE inventory_requests_new (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  connection_id INTEGER NOT NULL REFERENCES connections(id),
+  node TEXT NOT NULL,
+  kind TEXT NOT NULL,
+  hash TEXT NOT NULL,
+  -- NULL for notfound replies to requests that weren't seen
+  requested_at TEXT,
+  -- When the peer answered with notfound
+  not_found_at TEXT
+);
+
+INSERT INTO inventory_requests_new SELECT * FROM inventory_requests;
+DROP TABLE inventory_requests;
+ALTER TABLE inventory_requests_new RENAME TO inventory_requests;
+
+CREATE INDEX idx_inventory_requests_hash ON inventory_requests(hash, node);
+CREATE INDEX idx_inventory_requests_connection_id ON inventory_requests(connection_id, hash);
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+
+use crate::event::ConnectionId;
+
+/// Whether an announced or accepted item is a block or a transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ItemKind {
+    Block,
+    Tx,
+}
+
+/// The message a peer announced an item with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum AnnouncedVia {
+    Inv,
+    Headers,
+    Cmpctblock,
+}
+
+/// A block or transaction, by its hash in the usual reversed hex
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Item {
+    pub kind: ItemKind,
+    /// Block hash, txid, or wtxid for peers relaying by wtxid
+    pub hash: String,
+}
+
+/// A peer announced blocks or transactions to the node in one message
+#[derive(Debug, Clone)]
+pub struct ItemsAnnounced {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub via: AnnouncedVia,
+    pub items: Vec<Item>,
+    /// When the proxy received the announcement
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The node requested blocks or transactions from a peer with getdata
+#[derive(Debug, Clone)]
+pub struct ItemsRequested {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub items: Vec<Item>,
+    /// When the proxy received the request from the node
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A peer answered a request with notfound
+#[derive(Debug, Clone)]
+pub struct ItemsNotFound {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub items: Vec<Item>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The node accepted a block or a transaction to its mempool
+#[derive(Debug, Clone)]
+pub struct ItemAccepted {
+    pub node: String,
+    pub kind: ItemKind,
+    /// Block hash or txid
+    pub hash: String,
+    /// Only known when the whole transaction was received
+    pub wtxid: Option<String>,
+    pub accepted_at: DateTime<Utc>,
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Path to the SQLite database file, created if missing
+    #[serde(default = "default_database_path")]
+    pub database_path: PathBuf,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            database_path: default_database_path(),
+        }
+    }
+}
+
+fn default_database_path() -> PathBuf {
+    PathBuf::from("nodescope.db")
+}
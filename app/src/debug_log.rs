@@ -0,0 +1,55 @@
+use chrono::{DateTime, Utc};
+
+use crate::event::ConnectionId;
+
+/// What a recognised line of bitcoind's debug.log reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum LogEventKind {
+    PeerConnected,
+    /// The peer's version message, with its user agent
+    PeerVersion,
+    PeerDisconnected,
+    Misbehaving,
+    /// The node chose the peer to make room for others
+    Evicted,
+    UpdateTip,
+    LeftInitialBlockDownload,
+    MempoolAccepted,
+    MempoolRejected,
+    AddrmanAdded,
+}
+
+impl LogEventKind {
+    /// Log category the event belongs to, also for lines logged without one
+    pub fn category(&self) -> &'static str {
+        match self {
+            LogEventKind::PeerConnected
+            | LogEventKind::PeerVersion
+            | LogEventKind::PeerDisconnected
+            | LogEventKind::Misbehaving
+            | LogEventKind::Evicted => "net",
+            LogEventKind::UpdateTip | LogEventKind::LeftInitialBlockDownload => "validation",
+            LogEventKind::MempoolAccepted | LogEventKind::MempoolRejected => "mempool",
+            LogEventKind::AddrmanAdded => "addrman",
+        }
+    }
+}
+
+/// An event from a node's debug.log
+#[derive(Debug, Clone)]
+pub struct LogEvent {
+    pub node: String,
+    /// The proxied connection of the peer, when it could be matched
+    pub connection_id: Option<ConnectionId>,
+    /// Peer id in Bitcoin Core
+    pub peer_id: Option<i64>,
+    /// Only logged with `-logips`
+    pub address: Option<String>,
+    pub kind: LogEventKind,
+    /// Height of the new tip for UpdateTip
+    pub height: Option<i64>,
+    /// The log line without its timestamp and tags
+    pub message: String,
+    pub logged_at: DateTime<Utc>,
+}
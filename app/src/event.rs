@@ -0,0 +1,424 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use bitcoin::p2p::message::NetworkMessage;
+use chrono::{DateTime, Utc};
+use tokio::sync::{broadcast, mpsc};
+
+use crate::announcement::{ItemsAnnounced, ItemsNotFound, ItemsRequested};
+use crate::handshake::Handshake;
+use crate::network_type::NetworkType;
+use crate::parse_health::ParseHealth;
+use crate::relay::{FeeFilterReceived, TxReceived};
+use crate::transport::{PacketStats, Transport};
+
+/// Number of events a slow subscriber may fall behind before it starts lagging
+const EVENT_BUS_CAPACITY: usize = 4096;
+
+/// Number of events waiting to be stored before events other than lifecycle ones are dropped
+pub const EVENT_QUEUE_CAPACITY: usize = 65_536;
+
+/// Identifier assigned by the proxy to every accepted connection
+pub type ConnectionId = u64;
+
+/// Represents a direction of traffic flow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum Direction {
+    Inbound,  // Client -> Bitcoin Core
+    Outbound, // Bitcoin Core -> Client
+}
+
+impl Direction {
+    /// Lowercase name, as stored and used in metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::Inbound => "inbound",
+            Direction::Outbound => "outbound",
+        }
+    }
+}
+
+impl std::fmt::Display for Direction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Direction::Inbound => write!(f, "→"),
+            Direction::Outbound => write!(f, "←"),
+        }
+    }
+}
+
+/// Statistics for a connection
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionStats {
+    pub bytes_inbound: u64,
+    pub bytes_outbound: u64,
+    pub messages_inbound: u64,
+    pub messages_outbound: u64,
+}
+
+/// A connection was established between a client and its target
+///
+/// The client is always the node and the target its peer, also for inbound
+/// connections where the peer connected to the proxy.
+#[derive(Debug, Clone)]
+pub struct ConnectionOpened {
+    pub connection_id: ConnectionId,
+    /// Label of the node whose proxy accepted the connection
+    pub node: String,
+    pub client_addr: String,
+    /// Only the IP of inbound peers, which connect from ephemeral ports
+    pub target_addr: String,
+    /// Port an inbound peer connected from
+    pub peer_port: Option<u16>,
+    /// Address the proxy connected to the node from, for inbound connections,
+    /// which is the peer's address as far as the node knows
+    pub local_addr: Option<String>,
+    /// None while the proxy auto-detects the network from the first message
+    pub network: Option<bitcoin::Network>,
+    pub network_type: NetworkType,
+    pub inbound: bool,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Why the proxy couldn't connect to the target a client requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ConnectError {
+    Refused,
+    HostUnreachable,
+    NetworkUnreachable,
+    TimedOut,
+    Other,
+}
+
+impl ConnectError {
+    /// Snake case name, as stored and used in metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectError::Refused => "refused",
+            ConnectError::HostUnreachable => "host_unreachable",
+            ConnectError::NetworkUnreachable => "network_unreachable",
+            ConnectError::TimedOut => "timed_out",
+            ConnectError::Other => "other",
+        }
+    }
+}
+
+impl From<std::io::ErrorKind> for ConnectError {
+    fn from(kind: std::io::ErrorKind) -> Self {
+        match kind {
+            std::io::ErrorKind::ConnectionRefused => ConnectError::Refused,
+            std::io::ErrorKind::HostUnreachable => ConnectError::HostUnreachable,
+            std::io::ErrorKind::NetworkUnreachable => ConnectError::NetworkUnreachable,
+            std::io::ErrorKind::TimedOut => ConnectError::TimedOut,
+            _ => ConnectError::Other,
+        }
+    }
+}
+
+/// The proxy couldn't connect to the target a client requested
+#[derive(Debug, Clone)]
+pub struct ConnectionFailed {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub client_addr: String,
+    pub target_addr: String,
+    pub network: Option<bitcoin::Network>,
+    pub error: ConnectError,
+    pub detail: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A Bitcoin P2P message was parsed from a connection
+#[derive(Debug, Clone)]
+pub struct MessageSeen {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub direction: Direction,
+    pub command: String,
+    pub payload_len: usize,
+    pub description: String,
+    pub message: Arc<NetworkMessage>,
+    /// When the proxy read the last bytes of the message
+    pub received_at: DateTime<Utc>,
+    /// When the proxy finished writing the message to the other side, if it did
+    pub forwarded_at: Option<DateTime<Utc>>,
+}
+
+impl MessageSeen {
+    /// Time the message spent inside the proxy
+    pub fn forward_latency(&self) -> Option<chrono::Duration> {
+        self.forwarded_at.map(|at| at - self.received_at)
+    }
+}
+
+/// A ping was answered by a pong carrying the same nonce
+#[derive(Debug, Clone)]
+pub struct PingMeasured {
+    pub connection_id: ConnectionId,
+    /// Direction of the ping, the pong travelled the other way
+    pub direction: Direction,
+    pub nonce: u64,
+    /// When the proxy finished forwarding the ping
+    pub ping_at: DateTime<Utc>,
+    /// When the proxy received the matching pong
+    pub pong_at: DateTime<Utc>,
+}
+
+impl PingMeasured {
+    /// Round-trip time as seen from the proxy
+    pub fn rtt(&self) -> chrono::Duration {
+        self.pong_at - self.ping_at
+    }
+}
+
+/// The client's first bytes revealed which transport a connection uses
+#[derive(Debug, Clone)]
+pub struct TransportDetected {
+    pub connection_id: ConnectionId,
+    pub transport: Transport,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// The magic of the first message revealed the network of an auto-detecting connection
+#[derive(Debug, Clone)]
+pub struct NetworkDetected {
+    pub connection_id: ConnectionId,
+    /// Custom signets are reported as signet
+    pub network: bitcoin::Network,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// A connection was closed, carrying its final statistics
+#[derive(Debug, Clone)]
+pub struct ConnectionClosed {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub stats: ConnectionStats,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Events published by the proxy for every observed connection
+#[derive(Debug, Clone)]
+pub enum P2pEvent {
+    ConnectionOpened(ConnectionOpened),
+    /// Connecting to the requested target failed, so no connection was opened
+    ConnectionFailed(ConnectionFailed),
+    MessageSeen(MessageSeen),
+    ConnectionClosed(ConnectionClosed),
+    /// Latest state of a connection's handshake, published on every change
+    HandshakeUpdated(Box<Handshake>),
+    PingMeasured(PingMeasured),
+    /// Latest parse health of one direction, published when anything goes wrong or a
+    /// message with an unknown command is seen
+    ParseHealthUpdated(ParseHealth),
+    TransportDetected(TransportDetected),
+    NetworkDetected(NetworkDetected),
+    /// Final packet statistics of one direction, published before the connection is closed
+    PacketStatsRecorded(PacketStats),
+    ItemsAnnounced(ItemsAnnounced),
+    ItemsRequested(ItemsRequested),
+    ItemsNotFound(ItemsNotFound),
+    TxReceived(TxReceived),
+    FeeFilterReceived(FeeFilterReceived),
+}
+
+impl P2pEvent {
+    /// The connection this event belongs to
+    pub fn connection_id(&self) -> ConnectionId {
+        match self {
+            P2pEvent::ConnectionOpened(e) => e.connection_id,
+            P2pEvent::ConnectionFailed(e) => e.connection_id,
+            P2pEvent::MessageSeen(e) => e.connection_id,
+            P2pEvent::ConnectionClosed(e) => e.connection_id,
+            P2pEvent::HandshakeUpdated(e) => e.connection_id,
+            P2pEvent::PingMeasured(e) => e.connection_id,
+            P2pEvent::ParseHealthUpdated(e) => e.connection_id,
+            P2pEvent::TransportDetected(e) => e.connection_id,
+            P2pEvent::NetworkDetected(e) => e.connection_id,
+            P2pEvent::PacketStatsRecorded(e) => e.connection_id,
+            P2pEvent::ItemsAnnounced(e) => e.connection_id,
+            P2pEvent::ItemsRequested(e) => e.connection_id,
+            P2pEvent::ItemsNotFound(e) => e.connection_id,
+            P2pEvent::TxReceived(e) => e.connection_id,
+            P2pEvent::FeeFilterReceived(e) => e.connection_id,
+        }
+    }
+
+    /// Whether the event changes the state of a connection rather than adding to its traffic
+    ///
+    /// Every other event of a connection refers to the row its opening creates, and there are
+    /// only a few of these per connection, so they are never dropped.
+    pub fn is_lifecycle(&self) -> bool {
+        matches!(
+            self,
+            P2pEvent::ConnectionOpened(_)
+                | P2pEvent::ConnectionFailed(_)
+                | P2pEvent::ConnectionClosed(_)
+                | P2pEvent::HandshakeUpdated(_)
+                | P2pEvent::TransportDetected(_)
+                | P2pEvent::NetworkDetected(_)
+                | P2pEvent::PacketStatsRecorded(_)
+        )
+    }
+}
+
+/// Fan-out point for P2P events, cheap to clone
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<P2pEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_BUS_CAPACITY);
+        Self { sender }
+    }
+
+    /// Publish an event to all current subscribers
+    pub fn publish(&self, event: P2pEvent) {
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.sender.send(event);
+    }
+
+    /// Subscribe to all events published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<P2pEvent> {
+        self.sender.subscribe()
+    }
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Ordered queue of events for a single consumer, cheap to clone
+///
+/// Unlike the bus it never loses lifecycle events. Other events are dropped and counted while
+/// `capacity` events are waiting, so a slow consumer can't use unbounded memory.
+#[derive(Clone)]
+pub struct EventQueue {
+    sender: mpsc::UnboundedSender<P2pEvent>,
+    state: Arc<QueueState>,
+}
+
+/// Receiving end of an [`EventQueue`]
+pub struct QueueReceiver {
+    receiver: mpsc::UnboundedReceiver<P2pEvent>,
+    state: Arc<QueueState>,
+}
+
+struct QueueState {
+    capacity: usize,
+    queued: AtomicUsize,
+    dropped: AtomicU64,
+}
+
+impl EventQueue {
+    pub fn new(capacity: usize) -> (Self, QueueReceiver) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let state = Arc::new(QueueState {
+            capacity,
+            queued: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let receiver = QueueReceiver {
+            receiver,
+            state: state.clone(),
+        };
+        (Self { sender, state }, receiver)
+    }
+
+    /// Queue an event, unless the queue is full and the event isn't a lifecycle one
+    pub fn push(&self, event: P2pEvent) {
+        if !event.is_lifecycle() && self.state.queued.load(Ordering::Relaxed) >= self.state.capacity
+        {
+            self.state.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        self.state.queued.fetch_add(1, Ordering::Relaxed);
+        // Sending only fails once the consumer stopped, when nothing is recorded anymore
+        let _ = self.sender.send(event);
+    }
+}
+
+impl QueueReceiver {
+    /// Wait for the next event, `None` once every queue handle was dropped
+    pub async fn recv(&mut self) -> Option<P2pEvent> {
+        let event = self.receiver.recv().await?;
+        self.state.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(event)
+    }
+
+    /// Take the next event if one is waiting
+    pub fn try_recv(&mut self) -> Option<P2pEvent> {
+        let event = self.receiver.try_recv().ok()?;
+        self.state.queued.fetch_sub(1, Ordering::Relaxed);
+        Some(event)
+    }
+
+    /// Number of events dropped since the last call
+    pub fn take_dropped(&self) -> u64 {
+        self.state.dropped.swap(0, Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_event_bus_fan_out() {
+        let bus = EventBus::new();
+        let mut first = bus.subscribe();
+        let mut second = bus.subscribe();
+
+        bus.publish(P2pEvent::ConnectionClosed(ConnectionClosed {
+            connection_id: 7,
+            node: "mainnet".to_string(),
+            stats: ConnectionStats::default(),
+            timestamp: Utc::now(),
+        }));
+
+        assert_eq!(first.recv().await.unwrap().connection_id(), 7);
+        assert_eq!(second.recv().await.unwrap().connection_id(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_event_queue_keeps_lifecycle_events_when_full() {
+        let (queue, mut receiver) = EventQueue::new(2);
+        let ping = |connection_id| {
+            P2pEvent::PingMeasured(PingMeasured {
+                connection_id,
+                direction: Direction::Inbound,
+                nonce: 1,
+                ping_at: Utc::now(),
+                pong_at: Utc::now(),
+            })
+        };
+
+        queue.push(ping(1));
+        queue.push(ping(2));
+        queue.push(ping(3));
+        queue.push(P2pEvent::ConnectionClosed(ConnectionClosed {
+            connection_id: 4,
+            node: "mainnet".to_string(),
+            stats: ConnectionStats::default(),
+            timestamp: Utc::now(),
+        }));
+
+        let mut received = Vec::new();
+        while let Some(event) = receiver.try_recv() {
+            received.push(event.connection_id());
+        }
+        assert_eq!(received, vec![1, 2, 4]);
+        assert_eq!(receiver.take_dropped(), 1);
+        assert_eq!(receiver.take_dropped(), 0);
+
+        // Room is made again once the consumer caught up
+        queue.push(ping(5));
+        assert_eq!(receiver.recv().await.unwrap().connection_id(), 5);
+    }
+}
@@ -0,0 +1,203 @@
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, Row};
+
+use crate::event::ConnectionId;
+
+/// Outcome of the version handshake of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum HandshakeOutcome {
+    Pending,
+    Completed,
+    Failed,
+    /// The handshake happened inside a BIP324 encrypted transport and couldn't be observed
+    Encrypted,
+}
+
+/// Why a handshake didn't complete
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum HandshakeFailureReason {
+    /// The client's SOCKS5 negotiation failed
+    Socks5Error,
+    /// The proxy couldn't connect to the requested target
+    ConnectFailed,
+    /// No verack was exchanged within the handshake timeout
+    Timeout,
+    /// One side closed the connection before the handshake completed
+    DisconnectedBeforeVerack,
+}
+
+impl HandshakeFailureReason {
+    /// Snake case name, as stored and used in metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HandshakeFailureReason::Socks5Error => "socks5_error",
+            HandshakeFailureReason::ConnectFailed => "connect_failed",
+            HandshakeFailureReason::Timeout => "timeout",
+            HandshakeFailureReason::DisconnectedBeforeVerack => "disconnected_before_verack",
+        }
+    }
+}
+
+/// Fields of a `version` message sent by one side of a connection
+#[derive(Debug, Clone)]
+pub struct VersionInfo {
+    pub protocol_version: u32,
+    pub services: u64,
+    pub timestamp: i64,
+    pub user_agent: String,
+    pub start_height: i32,
+    pub relay: bool,
+    pub nonce: u64,
+    pub addr_from: String,
+    pub addr_recv: String,
+    pub seen_at: Option<DateTime<Utc>>,
+}
+
+/// Send compact blocks negotiation (BIP152)
+#[derive(Debug, Clone, Copy)]
+pub struct SendCmpctInfo {
+    pub announce: bool,
+    pub version: u64,
+}
+
+/// Everything one side of a connection sent during the handshake
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeSide {
+    pub version: Option<VersionInfo>,
+    pub verack_at: Option<DateTime<Utc>>,
+    pub wtxid_relay: bool,
+    pub send_addr_v2: bool,
+    pub send_cmpct: Option<SendCmpctInfo>,
+}
+
+/// Version handshake of a connection, from accept to verack or failure
+///
+/// The local side is the proxy client, the remote side is the target.
+#[derive(Debug, Clone)]
+pub struct Handshake {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub client_addr: String,
+    pub target_addr: Option<String>,
+    /// SOCKS5 username the client authenticated with, which Bitcoin Core
+    /// randomizes per connection to isolate its Tor circuits
+    pub stream_isolation: Option<String>,
+    pub outcome: HandshakeOutcome,
+    pub failure_reason: Option<HandshakeFailureReason>,
+    pub failure_detail: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub completed_at: Option<DateTime<Utc>>,
+    pub failed_at: Option<DateTime<Utc>>,
+    pub local: HandshakeSide,
+    pub remote: HandshakeSide,
+}
+
+impl Handshake {
+    pub fn new(
+        connection_id: ConnectionId,
+        node: String,
+        client_addr: String,
+        started_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            connection_id,
+            node,
+            client_addr,
+            target_addr: None,
+            stream_isolation: None,
+            outcome: HandshakeOutcome::Pending,
+            failure_reason: None,
+            failure_detail: None,
+            started_at,
+            completed_at: None,
+            failed_at: None,
+            local: HandshakeSide::default(),
+            remote: HandshakeSide::default(),
+        }
+    }
+
+    /// Time from accepting the connection until the handshake completed
+    pub fn duration(&self) -> Option<chrono::Duration> {
+        self.completed_at.map(|at| at - self.started_at)
+    }
+}
+
+impl FromRow<'_, SqliteRow> for Handshake {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            connection_id: row.try_get::<i64, _>("connection_id")? as ConnectionId,
+            node: row.try_get("node")?,
+            client_addr: row.try_get("client_addr")?,
+            target_addr: row.try_get("target_addr")?,
+            stream_isolation: row.try_get("stream_isolation")?,
+            outcome: row.try_get("outcome")?,
+            failure_reason: row.try_get("failure_reason")?,
+            failure_detail: row.try_get("failure_detail")?,
+            started_at: row.try_get("started_at")?,
+            completed_at: row.try_get("completed_at")?,
+            failed_at: row.try_get("failed_at")?,
+            local: side_from_row(row, "local")?,
+            remote: side_from_row(row, "remote")?,
+        })
+    }
+}
+
+/// Read the `{prefix}_*` columns of one handshake side
+fn side_from_row(row: &SqliteRow, prefix: &str) -> sqlx::Result<HandshakeSide> {
+    let column = |name: &str| format!("{prefix}_{name}");
+
+    // Rows migrated from older schemas may only have some of the version fields
+    let version = match row.try_get::<Option<i64>, _>(column("version").as_str())? {
+        Some(protocol_version) => Some(VersionInfo {
+            protocol_version: protocol_version as u32,
+            services: row
+                .try_get::<Option<i64>, _>(column("services").as_str())?
+                .unwrap_or_default() as u64,
+            timestamp: row
+                .try_get::<Option<i64>, _>(column("timestamp").as_str())?
+                .unwrap_or_default(),
+            user_agent: row
+                .try_get::<Option<String>, _>(column("user_agent").as_str())?
+                .unwrap_or_default(),
+            start_height: row
+                .try_get::<Option<i32>, _>(column("start_height").as_str())?
+                .unwrap_or_default(),
+            relay: row
+                .try_get::<Option<bool>, _>(column("relay").as_str())?
+                .unwrap_or_default(),
+            nonce: row
+                .try_get::<Option<i64>, _>(column("nonce").as_str())?
+                .unwrap_or_default() as u64,
+            addr_from: row
+                .try_get::<Option<String>, _>(column("addr_from").as_str())?
+                .unwrap_or_default(),
+            addr_recv: row
+                .try_get::<Option<String>, _>(column("addr_recv").as_str())?
+                .unwrap_or_default(),
+            seen_at: row.try_get(column("version_at").as_str())?,
+        }),
+        None => None,
+    };
+
+    let send_cmpct = match (
+        row.try_get::<Option<bool>, _>(column("sendcmpct_announce").as_str())?,
+        row.try_get::<Option<i64>, _>(column("sendcmpct_version").as_str())?,
+    ) {
+        (Some(announce), Some(version)) => Some(SendCmpctInfo {
+            announce,
+            version: version as u64,
+        }),
+        _ => None,
+    };
+
+    Ok(HandshakeSide {
+        version,
+        verack_at: row.try_get(column("verack_at").as_str())?,
+        wtxid_relay: row.try_get(column("wtxidrelay").as_str())?,
+        send_addr_v2: row.try_get(column("sendaddrv2").as_str())?,
+        send_cmpct,
+    })
+}
@@ -1,9 +1,163 @@
+mod announcement;
+mod config;
+mod debug_log;
+mod event;
+mod handshake;
+mod metrics;
+mod network_type;
+mod parse_health;
+mod registry;
+mod relay;
+mod rpc;
+mod shutdown;
+mod store;
+mod transport;
+
+pub use announcement::*;
+pub use config::StorageConfig;
+pub use debug_log::*;
+pub use event::*;
+pub use handshake::*;
+pub use metrics::*;
+pub use network_type::*;
+pub use parse_health::*;
+pub use registry::*;
+pub use relay::*;
+pub use rpc::*;
+pub use shutdown::*;
+pub use store::*;
+pub use transport::*;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Time connections get to close after the grace period, before storage is closed anyway
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
 #[derive(Clone)]
-pub struct NodeScopeApp;
+pub struct NodeScopeApp {
+    events: EventBus,
+    /// Events waiting to be written to the store
+    recording: EventQueue,
+    store: Store,
+    metrics: Metrics,
+    connections: ConnectionRegistry,
+    connection_counter: Arc<AtomicU64>,
+    shutdown: Shutdown,
+    /// Task writing published events to the store, taken when closing
+    recorder: Arc<Mutex<Option<JoinHandle<()>>>>,
+}
 
 impl NodeScopeApp {
-    #[allow(clippy::new_without_default)]
-    pub fn new() -> Self {
-        Self {}
+    /// Open the storage and start recording published events
+    pub async fn init(config: StorageConfig) -> anyhow::Result<Self> {
+        let store = Store::open(&config).await?;
+        let next_connection_id = store.max_connection_id().await?.map_or(0, |id| id + 1);
+
+        let events = EventBus::new();
+        let (recording, queued) = EventQueue::new(EVENT_QUEUE_CAPACITY);
+        let shutdown = Shutdown::new();
+        let recorder = tokio::spawn(store.clone().record_events(queued, shutdown.clone()));
+
+        Ok(Self {
+            events,
+            recording,
+            store,
+            metrics: Metrics::new(),
+            connections: ConnectionRegistry::default(),
+            connection_counter: Arc::new(AtomicU64::new(next_connection_id)),
+            shutdown,
+            recorder: Arc::new(Mutex::new(Some(recorder))),
+        })
+    }
+
+    /// Allocate an id for a new connection, unique across restarts
+    pub fn next_connection_id(&self) -> ConnectionId {
+        self.connection_counter.fetch_add(1, Ordering::SeqCst)
+    }
+
+    /// Publish a P2P event to every subscriber and queue it for storage
+    pub fn publish(&self, event: P2pEvent) {
+        self.recording.push(event.clone());
+        self.events.publish(event);
+    }
+
+    /// Subscribe to the stream of P2P events
+    pub fn subscribe(&self) -> broadcast::Receiver<P2pEvent> {
+        self.events.subscribe()
+    }
+
+    /// Persistent storage backing the app
+    pub fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Connections that are currently open, with live statistics
+    pub fn connections(&self) -> &ConnectionRegistry {
+        &self.connections
+    }
+
+    /// Prometheus metrics of the proxy
+    pub fn metrics(&self) -> &Metrics {
+        &self.metrics
+    }
+
+    /// Shutdown state shared by all subsystems
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Give open connections `grace` to finish, then close the rest
+    ///
+    /// Listeners are expected to have stopped accepting already.
+    pub async fn drain_connections(&self, grace: Duration) {
+        self.shutdown.drain();
+        let open = self.connections.len();
+        if open > 0 {
+            info!(
+                "Waiting up to {}s for {} open connections to close",
+                grace.as_secs(),
+                open
+            );
+        }
+        if tokio::time::timeout(grace, self.connections.all_closed())
+            .await
+            .is_ok()
+        {
+            self.shutdown.close();
+            return;
+        }
+
+        warn!(
+            "Closing {} connections still open after the grace period",
+            self.connections.len()
+        );
+        self.shutdown.close();
+        if tokio::time::timeout(CLOSE_TIMEOUT, self.connections.all_closed())
+            .await
+            .is_err()
+        {
+            warn!(
+                "{} connections didn't close in time",
+                self.connections.len()
+            );
+        }
+    }
+
+    /// Write every event published so far and close the storage
+    pub async fn close(&self) {
+        self.shutdown.stop();
+        let recorder = self.recorder.lock().expect("recorder lock poisoned").take();
+        if let Some(recorder) = recorder
+            && let Err(e) = recorder.await
+        {
+            warn!("Event recording stopped unexpectedly: {}", e);
+        }
+        self.store.close().await;
     }
 }
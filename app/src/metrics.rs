@@ -0,0 +1,479 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use bitcoin::p2p::message::NetworkMessage;
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+use crate::event::{ConnectError, Direction};
+use crate::handshake::HandshakeFailureReason;
+use crate::transport::{PACKET_SIZE_BUCKETS, Transport};
+
+/// Prometheus metrics of all proxied connections
+#[derive(Clone)]
+pub struct Metrics {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    registry: Registry,
+    connections_opened: IntCounterVec,
+    connections_closed: IntCounterVec,
+    connections_failed: IntCounterVec,
+    connect_failures: IntCounterVec,
+    active_connections: IntGaugeVec,
+    transports: IntCounterVec,
+    bytes: IntCounterVec,
+    message_bytes: IntCounterVec,
+    messages: IntCounterVec,
+    parse_errors: IntCounterVec,
+    unknown_commands: IntCounterVec,
+    skipped_bytes: IntCounterVec,
+    inspection_dropped_bytes: IntCounterVec,
+    handshake_duration: HistogramVec,
+    ping_rtt: HistogramVec,
+    encrypted_packet_size: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new_custom(Some("nodescope".to_string()), None)
+            .expect("valid metrics prefix");
+
+        let inner = Inner {
+            connections_opened: counter(
+                &registry,
+                "connections_opened_total",
+                "Connections established to a target",
+                &["node", "network"],
+            ),
+            connections_closed: counter(
+                &registry,
+                "connections_closed_total",
+                "Established connections that were closed",
+                &["node", "network"],
+            ),
+            connections_failed: counter(
+                &registry,
+                "connections_failed_total",
+                "Connections whose handshake failed, by reason",
+                &["node", "network", "reason"],
+            ),
+            connect_failures: counter(
+                &registry,
+                "connect_failures_total",
+                "Failed connects to requested targets, by error",
+                &["node", "network", "error"],
+            ),
+            active_connections: gauge(
+                &registry,
+                "active_connections",
+                "Connections currently established, counted once their network is detected",
+                &["node", "network"],
+            ),
+            transports: counter(
+                &registry,
+                "connections_by_transport_total",
+                "Connections by the transport detected from the client's first bytes",
+                &["node", "network", "transport"],
+            ),
+            bytes: counter(
+                &registry,
+                "bytes_total",
+                "Bytes forwarded, parsed or not",
+                &["node", "network", "direction"],
+            ),
+            message_bytes: counter(
+                &registry,
+                "message_bytes_total",
+                "Bytes of parsed messages, including headers",
+                &["node", "network", "direction", "command"],
+            ),
+            messages: counter(
+                &registry,
+                "messages_total",
+                "Parsed messages",
+                &["node", "network", "direction", "command"],
+            ),
+            parse_errors: counter(
+                &registry,
+                "parse_errors_total",
+                "Problems the parser found in the stream, by kind",
+                &["node", "network", "direction", "kind"],
+            ),
+            unknown_commands: counter(
+                &registry,
+                "unknown_commands_total",
+                "Valid messages with a command the parser doesn't know, e.g. a newer one",
+                &["node", "network", "direction"],
+            ),
+            skipped_bytes: counter(
+                &registry,
+                "parser_skipped_bytes_total",
+                "Bytes the parser skipped while looking for a valid message",
+                &["node", "network", "direction"],
+            ),
+            inspection_dropped_bytes: counter(
+                &registry,
+                "inspection_dropped_bytes_total",
+                "Forwarded bytes not inspected because the parser fell behind",
+                &["node", "network", "direction"],
+            ),
+            handshake_duration: histogram(
+                &registry,
+                "handshake_duration_seconds",
+                "Time from accept until both sides exchanged version and verack",
+                &["node", "network"],
+                vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0],
+            ),
+            ping_rtt: histogram(
+                &registry,
+                "ping_rtt_seconds",
+                "Round-trip time of pings, by direction of the ping",
+                &["node", "network", "direction"],
+                vec![
+                    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+                ],
+            ),
+            encrypted_packet_size: histogram(
+                &registry,
+                "encrypted_packet_size_bytes",
+                "Size of reads on BIP324 encrypted connections",
+                &["node", "network", "direction"],
+                PACKET_SIZE_BUCKETS
+                    .iter()
+                    .map(|bound| *bound as f64)
+                    .collect(),
+            ),
+            registry,
+        };
+
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// Metrics handle that labels everything with the given node and its network
+    ///
+    /// Without a network, each connection is labeled with the one detected for
+    /// it, see [`NodeMetrics::for_connection`].
+    pub fn for_node(&self, node: &str, network: Option<&'static str>) -> NodeMetrics {
+        let label = NetworkLabel::default();
+        if let Some(network) = network {
+            let _ = label.network.set(network);
+        }
+        NodeMetrics {
+            metrics: self.clone(),
+            node: node.into(),
+            label: Arc::new(label),
+        }
+    }
+
+    /// Render all metrics in the Prometheus text format
+    pub fn render(&self) -> anyhow::Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.inner.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Network label of connections whose network isn't known, e.g. encrypted ones
+const UNDETECTED_NETWORK: &str = "unknown";
+
+/// Records metrics of connections of a single node
+#[derive(Clone)]
+pub struct NodeMetrics {
+    metrics: Metrics,
+    node: Arc<str>,
+    label: Arc<NetworkLabel>,
+}
+
+/// Network metrics are labeled with, shared by all connections of a node with a
+/// configured network and kept per connection when it is detected
+#[derive(Default)]
+struct NetworkLabel {
+    network: OnceLock<&'static str>,
+    /// The connection was opened before its network was detected and isn't counted yet
+    pending_open: AtomicBool,
+}
+
+impl NodeMetrics {
+    /// Handle for a single connection, to label it with the network detected for it
+    pub fn for_connection(&self) -> NodeMetrics {
+        if self.label.network.get().is_some() {
+            return self.clone();
+        }
+        NodeMetrics {
+            metrics: self.metrics.clone(),
+            node: self.node.clone(),
+            label: Arc::default(),
+        }
+    }
+
+    /// The network the connection's metrics are labeled with
+    fn network(&self) -> &'static str {
+        self.label
+            .network
+            .get()
+            .copied()
+            .unwrap_or(UNDETECTED_NETWORK)
+    }
+
+    /// Count the connection as opened, or once its network is detected when it isn't known yet
+    pub fn connection_opened(&self) {
+        if self.label.network.get().is_some() {
+            self.count_opened();
+        } else {
+            self.label.pending_open.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Label the connection with the network detected from its first message
+    pub fn network_detected(&self, network: &'static str) {
+        if self.label.network.set(network).is_ok()
+            && self.label.pending_open.swap(false, Ordering::Relaxed)
+        {
+            self.count_opened();
+        }
+    }
+
+    fn count_opened(&self) {
+        let m = &self.metrics.inner;
+        m.connections_opened
+            .with_label_values(&[&*self.node, self.network()])
+            .inc();
+        m.active_connections
+            .with_label_values(&[&*self.node, self.network()])
+            .inc();
+    }
+
+    pub fn connection_closed(&self) {
+        // Closed before its network was detected
+        if self.label.pending_open.swap(false, Ordering::Relaxed) {
+            self.count_opened();
+        }
+        let m = &self.metrics.inner;
+        m.connections_closed
+            .with_label_values(&[&*self.node, self.network()])
+            .inc();
+        m.active_connections
+            .with_label_values(&[&*self.node, self.network()])
+            .dec();
+    }
+
+    pub fn connection_failed(&self, reason: HandshakeFailureReason) {
+        self.metrics
+            .inner
+            .connections_failed
+            .with_label_values(&[&*self.node, self.network(), reason.as_str()])
+            .inc();
+    }
+
+    pub fn connect_failed(&self, error: ConnectError) {
+        self.metrics
+            .inner
+            .connect_failures
+            .with_label_values(&[&*self.node, self.network(), error.as_str()])
+            .inc();
+    }
+
+    pub fn transport_detected(&self, transport: Transport) {
+        self.metrics
+            .inner
+            .transports
+            .with_label_values(&[&*self.node, self.network(), transport.as_str()])
+            .inc();
+    }
+
+    pub fn encrypted_packet(&self, direction: Direction, bytes: usize) {
+        self.metrics
+            .inner
+            .encrypted_packet_size
+            .with_label_values(&[&*self.node, self.network(), direction.as_str()])
+            .observe(bytes as f64);
+    }
+
+    pub fn bytes_forwarded(&self, direction: Direction, bytes: usize) {
+        self.metrics
+            .inner
+            .bytes
+            .with_label_values(&[&*self.node, self.network(), direction.as_str()])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn message(&self, direction: Direction, message: &NetworkMessage, bytes: usize) {
+        let m = &self.metrics.inner;
+        let labels = [
+            &*self.node,
+            self.network(),
+            direction.as_str(),
+            command_label(message),
+        ];
+        m.messages.with_label_values(&labels).inc();
+        m.message_bytes
+            .with_label_values(&labels)
+            .inc_by(bytes as u64);
+    }
+
+    /// Count a parse problem, e.g. `desync` or `checksum_mismatch`
+    pub fn parse_error(&self, direction: Direction, kind: &str) {
+        self.metrics
+            .inner
+            .parse_errors
+            .with_label_values(&[&*self.node, self.network(), direction.as_str(), kind])
+            .inc();
+    }
+
+    /// Count a valid message whose command isn't known, which isn't a parse problem
+    pub fn unknown_command(&self, direction: Direction) {
+        self.metrics
+            .inner
+            .unknown_commands
+            .with_label_values(&[&*self.node, self.network(), direction.as_str()])
+            .inc();
+    }
+
+    pub fn bytes_skipped(&self, direction: Direction, bytes: usize) {
+        self.metrics
+            .inner
+            .skipped_bytes
+            .with_label_values(&[&*self.node, self.network(), direction.as_str()])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn inspection_dropped(&self, direction: Direction, bytes: usize) {
+        self.metrics
+            .inner
+            .inspection_dropped_bytes
+            .with_label_values(&[&*self.node, self.network(), direction.as_str()])
+            .inc_by(bytes as u64);
+    }
+
+    pub fn handshake_completed(&self, duration: Duration) {
+        self.metrics
+            .inner
+            .handshake_duration
+            .with_label_values(&[&*self.node, self.network()])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn ping_rtt(&self, direction: Direction, rtt: Duration) {
+        self.metrics
+            .inner
+            .ping_rtt
+            .with_label_values(&[&*self.node, self.network(), direction.as_str()])
+            .observe(rtt.as_secs_f64());
+    }
+}
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).expect("valid metric");
+    registry
+        .register(Box::new(counter.clone()))
+        .expect("metric registered once");
+    counter
+}
+
+/// Command of a message as a label, peers can't add labels by sending made up commands
+fn command_label(message: &NetworkMessage) -> &'static str {
+    match message {
+        NetworkMessage::Unknown { .. } => "unknown",
+        message => message.cmd(),
+    }
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).expect("valid metric");
+    registry
+        .register(Box::new(gauge.clone()))
+        .expect("metric registered once");
+    gauge
+}
+
+fn histogram(
+    registry: &Registry,
+    name: &str,
+    help: &str,
+    labels: &[&str],
+    buckets: Vec<f64>,
+) -> HistogramVec {
+    let histogram = HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets), labels)
+        .expect("valid metric");
+    registry
+        .register(Box::new(histogram.clone()))
+        .expect("metric registered once");
+    histogram
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_labels_node() {
+        let metrics = Metrics::new();
+        let signet = metrics.for_node("lab", Some("signet"));
+        signet.connection_opened();
+        signet.message(Direction::Outbound, &NetworkMessage::Inv(Vec::new()), 61);
+        for command in ["sendtxrcncl", "made-up"] {
+            signet.message(
+                Direction::Inbound,
+                &NetworkMessage::Unknown {
+                    command: command.try_into().unwrap(),
+                    payload: Vec::new(),
+                },
+                24,
+            );
+        }
+        signet.connection_failed(HandshakeFailureReason::Timeout);
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(r#"nodescope_active_connections{network="signet",node="lab"} 1"#));
+        assert!(text.contains(
+            r#"nodescope_message_bytes_total{command="inv",direction="outbound",network="signet",node="lab"} 61"#
+        ));
+        assert!(text.contains(
+            r#"nodescope_messages_total{command="unknown",direction="inbound",network="signet",node="lab"} 2"#
+        ));
+        assert!(text.contains(
+            r#"nodescope_connections_failed_total{network="signet",node="lab",reason="timeout"} 1"#
+        ));
+    }
+
+    #[test]
+    fn test_labels_connections_with_detected_network() {
+        let metrics = Metrics::new();
+        let node = metrics.for_node("lab", None);
+
+        let detected = node.for_connection();
+        detected.connection_opened();
+        let text = metrics.render().unwrap();
+        assert!(!text.contains("nodescope_active_connections"));
+        detected.network_detected("signet");
+        detected.message(Direction::Outbound, &NetworkMessage::Verack, 24);
+
+        // Encrypted connections never reveal their network
+        let encrypted = node.for_connection();
+        encrypted.connection_opened();
+        encrypted.connection_closed();
+
+        let text = metrics.render().unwrap();
+        assert!(text.contains(r#"nodescope_active_connections{network="signet",node="lab"} 1"#));
+        assert!(text.contains(
+            r#"nodescope_messages_total{command="verack",direction="outbound",network="signet",node="lab"} 1"#
+        ));
+        assert!(
+            text.contains(r#"nodescope_connections_opened_total{network="unknown",node="lab"} 1"#)
+        );
+        assert!(text.contains(r#"nodescope_active_connections{network="unknown",node="lab"} 0"#));
+        assert!(!text.contains(r#"network="auto""#));
+    }
+}
@@ -0,0 +1,74 @@
+use std::net::IpAddr;
+
+/// Network a peer is reached on, as Bitcoin Core's `getpeerinfo` reports it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum NetworkType {
+    Ipv4,
+    Ipv6,
+    Onion,
+    I2p,
+    /// IPv6 addresses in fc00::/8, only reachable over a CJDNS mesh
+    Cjdns,
+}
+
+impl NetworkType {
+    /// Lowercase name, as stored and used in metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetworkType::Ipv4 => "ipv4",
+            NetworkType::Ipv6 => "ipv6",
+            NetworkType::Onion => "onion",
+            NetworkType::I2p => "i2p",
+            NetworkType::Cjdns => "cjdns",
+        }
+    }
+
+    /// Network of a host as requested by a client, None for plain DNS names
+    pub fn of_host(host: &str) -> Option<Self> {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Some(Self::of_ip(ip));
+        }
+
+        let host = host.to_ascii_lowercase();
+        if host.ends_with(".onion") {
+            Some(NetworkType::Onion)
+        } else if host.ends_with(".b32.i2p") {
+            Some(NetworkType::I2p)
+        } else {
+            None
+        }
+    }
+
+    pub fn of_ip(ip: IpAddr) -> Self {
+        match ip {
+            IpAddr::V4(_) => NetworkType::Ipv4,
+            IpAddr::V6(ip) if ip.octets()[0] == 0xfc => NetworkType::Cjdns,
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(_) => NetworkType::Ipv4,
+                None => NetworkType::Ipv6,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classifies_hosts() {
+        assert_eq!(NetworkType::of_host("1.2.3.4"), Some(NetworkType::Ipv4));
+        assert_eq!(NetworkType::of_host("2001:db8::1"), Some(NetworkType::Ipv6));
+        assert_eq!(NetworkType::of_host("fc32::1"), Some(NetworkType::Cjdns));
+        assert_eq!(
+            NetworkType::of_host("kpgvmscirrdqpekbqjsvw5teanhatztpp2gl6eee4zkowvwfxwenqaid.onion"),
+            Some(NetworkType::Onion)
+        );
+        assert_eq!(
+            NetworkType::of_host("c4gfnttsuwqomiygupdqqqyy5y5emnk5c73hrfvatri67prd7vyq.b32.i2p"),
+            Some(NetworkType::I2p)
+        );
+        assert_eq!(NetworkType::of_host("seed.bitcoin.sipa.be"), None);
+    }
+}
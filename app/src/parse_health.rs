@@ -0,0 +1,71 @@
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, Row};
+
+use crate::event::{ConnectionId, Direction};
+
+/// Whether a parser is aligned with the message boundaries of its stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "lowercase")]
+pub enum ParserState {
+    /// Every byte so far belonged to a message
+    #[default]
+    Synced,
+    /// Bytes are being skipped while scanning for the network magic
+    Desynced,
+    /// Messages are parsed again, after bytes were skipped
+    Resynced,
+}
+
+/// How well one direction of a connection could be parsed
+#[derive(Debug, Clone)]
+pub struct ParseHealth {
+    pub connection_id: ConnectionId,
+    pub direction: Direction,
+    pub state: ParserState,
+    /// Times the parser lost track of message boundaries
+    pub desyncs: u64,
+    pub bytes_skipped: u64,
+    pub checksum_mismatches: u64,
+    pub oversize_payloads: u64,
+    /// Valid messages with a command the parser doesn't know, which aren't parse problems
+    pub unknown_commands: u64,
+    /// Messages with a valid checksum whose payload couldn't be decoded
+    pub invalid_payloads: u64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl ParseHealth {
+    pub fn new(connection_id: ConnectionId, direction: Direction) -> Self {
+        Self {
+            connection_id,
+            direction,
+            state: ParserState::Synced,
+            desyncs: 0,
+            bytes_skipped: 0,
+            checksum_mismatches: 0,
+            oversize_payloads: 0,
+            unknown_commands: 0,
+            invalid_payloads: 0,
+            updated_at: Utc::now(),
+        }
+    }
+}
+
+impl FromRow<'_, SqliteRow> for ParseHealth {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        let count = |name: &str| row.try_get::<i64, _>(name).map(|n| n as u64);
+        Ok(Self {
+            connection_id: row.try_get::<i64, _>("connection_id")? as ConnectionId,
+            direction: row.try_get("direction")?,
+            state: row.try_get("state")?,
+            desyncs: count("desyncs")?,
+            bytes_skipped: count("bytes_skipped")?,
+            checksum_mismatches: count("checksum_mismatches")?,
+            oversize_payloads: count("oversize_payloads")?,
+            unknown_commands: count("unknown_commands")?,
+            invalid_payloads: count("invalid_payloads")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+
+use crate::event::{ConnectionId, ConnectionStats, Direction};
+use crate::transport::{PACKET_SIZE_BUCKETS, PacketStats};
+
+/// Traffic counters of a connection, updated by the forwarding tasks without locking
+#[derive(Debug, Default)]
+pub struct LiveStats {
+    bytes_inbound: AtomicU64,
+    bytes_outbound: AtomicU64,
+    messages_inbound: AtomicU64,
+    messages_outbound: AtomicU64,
+    packets_inbound: LivePackets,
+    packets_outbound: LivePackets,
+}
+
+/// Packet counters of one direction
+#[derive(Debug, Default)]
+struct LivePackets {
+    packets: AtomicU64,
+    /// Microseconds since the epoch, zero until the first packet
+    first_at: AtomicI64,
+    last_at: AtomicI64,
+    size_buckets: [AtomicU64; PACKET_SIZE_BUCKETS.len() + 1],
+}
+
+impl LiveStats {
+    pub fn record_bytes(&self, direction: Direction, bytes: u64) {
+        let counter = match direction {
+            Direction::Inbound => &self.bytes_inbound,
+            Direction::Outbound => &self.bytes_outbound,
+        };
+        counter.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Record a read of `bytes` from one side, counting its bytes as well
+    pub fn record_packet(&self, direction: Direction, bytes: u64, at: DateTime<Utc>) {
+        self.record_bytes(direction, bytes);
+
+        let packets = self.packets(direction);
+        let at = at.timestamp_micros();
+        packets.packets.fetch_add(1, Ordering::Relaxed);
+        let _ = packets
+            .first_at
+            .compare_exchange(0, at, Ordering::Relaxed, Ordering::Relaxed);
+        packets.last_at.store(at, Ordering::Relaxed);
+        packets.size_buckets[PacketStats::bucket(bytes)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_message(&self, direction: Direction) {
+        let counter = match direction {
+            Direction::Inbound => &self.messages_inbound,
+            Direction::Outbound => &self.messages_outbound,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Current values of all counters
+    ///
+    /// Counters are read one by one, so a snapshot taken while traffic flows
+    /// may be a few bytes apart between fields.
+    pub fn snapshot(&self) -> ConnectionStats {
+        ConnectionStats {
+            bytes_inbound: self.bytes_inbound.load(Ordering::Relaxed),
+            bytes_outbound: self.bytes_outbound.load(Ordering::Relaxed),
+            messages_inbound: self.messages_inbound.load(Ordering::Relaxed),
+            messages_outbound: self.messages_outbound.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Current packet statistics of one direction
+    pub fn packet_stats(&self, connection_id: ConnectionId, direction: Direction) -> PacketStats {
+        let packets = self.packets(direction);
+        let bytes = match direction {
+            Direction::Inbound => &self.bytes_inbound,
+            Direction::Outbound => &self.bytes_outbound,
+        };
+        let timestamp = |micros: &AtomicI64| match micros.load(Ordering::Relaxed) {
+            0 => None,
+            micros => DateTime::from_timestamp_micros(micros),
+        };
+        PacketStats {
+            connection_id,
+            direction,
+            packets: packets.packets.load(Ordering::Relaxed),
+            bytes: bytes.load(Ordering::Relaxed),
+            first_at: timestamp(&packets.first_at),
+            last_at: timestamp(&packets.last_at),
+            size_buckets: std::array::from_fn(|i| packets.size_buckets[i].load(Ordering::Relaxed)),
+        }
+    }
+
+    fn packets(&self, direction: Direction) -> &LivePackets {
+        match direction {
+            Direction::Inbound => &self.packets_inbound,
+            Direction::Outbound => &self.packets_outbound,
+        }
+    }
+}
+
+/// An open connection as seen by the registry
+#[derive(Debug, Clone)]
+pub struct LiveConnection {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub client_addr: String,
+    pub target_addr: String,
+    /// Address the proxy connected to the node from, for inbound connections
+    pub local_addr: Option<String>,
+    pub network: Option<bitcoin::Network>,
+    pub inbound: bool,
+    pub opened_at: DateTime<Utc>,
+    pub stats: Arc<LiveStats>,
+}
+
+/// Open connections and their live statistics, cheap to clone
+///
+/// The lock is only taken when connections are opened, closed or inspected,
+/// never while forwarding traffic.
+#[derive(Clone, Default)]
+pub struct ConnectionRegistry {
+    connections: Arc<RwLock<HashMap<ConnectionId, LiveConnection>>>,
+    /// Woken whenever a connection is removed
+    removed: Arc<Notify>,
+}
+
+impl ConnectionRegistry {
+    /// Add an open connection, removed again when the returned guard is dropped
+    pub fn register(&self, connection: LiveConnection) -> Registration {
+        let connection_id = connection.connection_id;
+        self.connections
+            .write()
+            .expect("connection registry lock poisoned")
+            .insert(connection_id, connection);
+        Registration {
+            registry: self.clone(),
+            connection_id,
+        }
+    }
+
+    /// An open connection
+    pub fn get(&self, connection_id: ConnectionId) -> Option<LiveConnection> {
+        self.connections
+            .read()
+            .expect("connection registry lock poisoned")
+            .get(&connection_id)
+            .cloned()
+    }
+
+    /// Live statistics of an open connection
+    pub fn stats(&self, connection_id: ConnectionId) -> Option<ConnectionStats> {
+        self.connections
+            .read()
+            .expect("connection registry lock poisoned")
+            .get(&connection_id)
+            .map(|connection| connection.stats.snapshot())
+    }
+
+    /// Live packet statistics of both directions of an open connection
+    pub fn packet_stats(&self, connection_id: ConnectionId) -> Option<Vec<PacketStats>> {
+        self.connections
+            .read()
+            .expect("connection registry lock poisoned")
+            .get(&connection_id)
+            .map(|connection| {
+                [Direction::Inbound, Direction::Outbound]
+                    .into_iter()
+                    .map(|direction| connection.stats.packet_stats(connection_id, direction))
+                    .collect()
+            })
+    }
+
+    /// All open connections, ordered by id
+    pub fn list(&self) -> Vec<LiveConnection> {
+        let mut connections: Vec<_> = self
+            .connections
+            .read()
+            .expect("connection registry lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        connections.sort_by_key(|connection| connection.connection_id);
+        connections
+    }
+
+    /// Number of open connections
+    pub fn len(&self) -> usize {
+        self.connections
+            .read()
+            .expect("connection registry lock poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Resolves once no connection is open anymore
+    pub async fn all_closed(&self) {
+        loop {
+            // Registered before checking, so a removal in between isn't missed
+            let removed = self.removed.notified();
+            if self.is_empty() {
+                return;
+            }
+            removed.await;
+        }
+    }
+}
+
+/// Keeps a connection in the registry while alive
+pub struct Registration {
+    registry: ConnectionRegistry,
+    connection_id: ConnectionId,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.registry.connections.write() {
+            connections.remove(&self.connection_id);
+        }
+        self.registry.removed.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registration_exposes_live_stats() {
+        let registry = ConnectionRegistry::default();
+        let stats = Arc::new(LiveStats::default());
+
+        let registration = registry.register(LiveConnection {
+            connection_id: 3,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:50000".to_string(),
+            target_addr: "1.2.3.4:8333".to_string(),
+            local_addr: None,
+            network: Some(bitcoin::Network::Bitcoin),
+            inbound: false,
+            opened_at: Utc::now(),
+            stats: stats.clone(),
+        });
+        stats.record_packet(Direction::Outbound, 120, Utc::now());
+        stats.record_message(Direction::Outbound);
+
+        let live = registry.stats(3).unwrap();
+        assert_eq!(live.bytes_outbound, 120);
+        assert_eq!(live.messages_outbound, 1);
+
+        let packets = registry.packet_stats(3).unwrap().remove(1);
+        assert_eq!(packets.packets, 1);
+        assert_eq!(packets.size_buckets, [0, 1, 0, 0, 0]);
+        assert_eq!(packets.first_at, packets.last_at);
+
+        drop(registration);
+        assert!(registry.stats(3).is_none());
+    }
+}
@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+
+use crate::event::ConnectionId;
+
+/// Upper bounds of the transaction size buckets in vbytes, larger ones fall into a final bucket
+pub const TX_SIZE_BUCKETS: [u64; 5] = [150, 250, 500, 1000, 10_000];
+
+/// Upper bounds of the fee rate buckets in sat/vB, higher rates fall into a final bucket
+pub const FEE_RATE_BUCKETS: [f64; 7] = [1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0];
+
+/// A peer delivered a transaction to the node
+#[derive(Debug, Clone)]
+pub struct TxReceived {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    pub txid: String,
+    pub wtxid: String,
+    pub vsize: u64,
+    /// Only known when the values of all inputs are, i.e. their transactions were relayed before
+    pub fee: Option<u64>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TxReceived {
+    /// Fee rate in sat/vB
+    pub fn fee_rate(&self) -> Option<f64> {
+        self.fee.map(|fee| fee as f64 / self.vsize.max(1) as f64)
+    }
+}
+
+/// A peer told the node the lowest fee rate of transactions to relay to it
+#[derive(Debug, Clone)]
+pub struct FeeFilterReceived {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    /// Fee rate in sat/kvB
+    pub fee_rate: i64,
+    pub timestamp: DateTime<Utc>,
+}
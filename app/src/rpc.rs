@@ -0,0 +1,98 @@
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, Row};
+
+use crate::event::ConnectionId;
+
+/// How Bitcoin Core uses a connection, from `getpeerinfo`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "kebab-case")]
+pub enum CoreConnectionType {
+    Inbound,
+    OutboundFullRelay,
+    BlockRelayOnly,
+    Manual,
+    AddrFetch,
+    Feeler,
+}
+
+impl CoreConnectionType {
+    /// Name as reported by Bitcoin Core, e.g. `block-relay-only`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CoreConnectionType::Inbound => "inbound",
+            CoreConnectionType::OutboundFullRelay => "outbound-full-relay",
+            CoreConnectionType::BlockRelayOnly => "block-relay-only",
+            CoreConnectionType::Manual => "manual",
+            CoreConnectionType::AddrFetch => "addr-fetch",
+            CoreConnectionType::Feeler => "feeler",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [
+            CoreConnectionType::Inbound,
+            CoreConnectionType::OutboundFullRelay,
+            CoreConnectionType::BlockRelayOnly,
+            CoreConnectionType::Manual,
+            CoreConnectionType::AddrFetch,
+            CoreConnectionType::Feeler,
+        ]
+        .into_iter()
+        .find(|connection_type| connection_type.as_str() == name)
+    }
+}
+
+/// Bitcoin Core's view of a proxied connection, from its latest `getpeerinfo`
+#[derive(Debug, Clone)]
+pub struct CorePeer {
+    pub connection_id: ConnectionId,
+    pub node: String,
+    /// Peer id in Bitcoin Core, as used by `disconnectnode` and the debug log
+    pub peer_id: i64,
+    /// Not reported by Bitcoin Core before v0.21
+    pub connection_type: Option<CoreConnectionType>,
+    /// Misbehavior score, legacy: only reported by Bitcoin Core before v22
+    pub ban_score: Option<i64>,
+    pub starting_height: Option<i64>,
+    /// Last header and block we have in common with the peer, -1 when none
+    pub synced_headers: Option<i64>,
+    pub synced_blocks: Option<i64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl FromRow<'_, SqliteRow> for CorePeer {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        Ok(Self {
+            connection_id: row.try_get::<i64, _>("connection_id")? as ConnectionId,
+            node: row.try_get("node")?,
+            peer_id: row.try_get("peer_id")?,
+            connection_type: row.try_get("connection_type")?,
+            ban_score: row.try_get("ban_score")?,
+            starting_height: row.try_get("starting_height")?,
+            synced_headers: row.try_get("synced_headers")?,
+            synced_blocks: row.try_get("synced_blocks")?,
+            updated_at: row.try_get("updated_at")?,
+        })
+    }
+}
+
+/// State of a node from its latest network, chain, traffic and mempool RPCs
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct NodeStatus {
+    pub node: String,
+    pub version: i64,
+    pub subversion: String,
+    pub chain: String,
+    pub blocks: i64,
+    pub headers: i64,
+    pub verification_progress: f64,
+    pub initial_block_download: bool,
+    pub connections_in: i64,
+    pub connections_out: i64,
+    pub total_bytes_recv: i64,
+    pub total_bytes_sent: i64,
+    pub mempool_size: i64,
+    pub mempool_bytes: i64,
+    pub updated_at: DateTime<Utc>,
+}
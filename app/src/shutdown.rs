@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+/// How far a graceful shutdown has progressed, phases only ever advance
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShutdownPhase {
+    Running,
+    /// Listeners stop accepting, open connections may still finish
+    Draining,
+    /// Connections still open after the grace period are closed
+    Closing,
+    /// Everything published so far is written to storage
+    Stopped,
+}
+
+/// Coordinates a graceful shutdown across subsystems, cheap to clone
+#[derive(Clone)]
+pub struct Shutdown {
+    phase: Arc<watch::Sender<ShutdownPhase>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (phase, _) = watch::channel(ShutdownPhase::Running);
+        Self {
+            phase: Arc::new(phase),
+        }
+    }
+
+    /// Stop accepting connections and let open ones drain
+    pub fn drain(&self) {
+        self.advance(ShutdownPhase::Draining);
+    }
+
+    /// Close the connections that are still open
+    pub fn close(&self) {
+        self.advance(ShutdownPhase::Closing);
+    }
+
+    /// Stop recording events once the ones published so far are stored
+    pub fn stop(&self) {
+        self.advance(ShutdownPhase::Stopped);
+    }
+
+    pub fn phase(&self) -> ShutdownPhase {
+        *self.phase.borrow()
+    }
+
+    /// Resolves once the shutdown reached the given phase
+    pub async fn reached(&self, phase: ShutdownPhase) {
+        let mut current = self.phase.subscribe();
+        // The sender lives as long as self, so waiting can't fail
+        let _ = current.wait_for(|current| *current >= phase).await;
+    }
+
+    fn advance(&self, phase: ShutdownPhase) {
+        self.phase.send_if_modified(|current| {
+            let advanced = *current < phase;
+            if advanced {
+                *current = phase;
+            }
+            advanced
+        });
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_phases_only_advance() {
+        let shutdown = Shutdown::new();
+        let draining = tokio::spawn({
+            let shutdown = shutdown.clone();
+            async move { shutdown.reached(ShutdownPhase::Draining).await }
+        });
+
+        shutdown.close();
+        shutdown.drain();
+        assert_eq!(shutdown.phase(), ShutdownPhase::Closing);
+        draining.await.unwrap();
+
+        // Later phases count as reached too
+        shutdown.reached(ShutdownPhase::Draining).await;
+    }
+}
@@ -0,0 +1,1497 @@
+mod query;
+
+pub use query::*;
+
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions};
+use sqlx::{Connection, QueryBuilder, Sqlite, Transaction};
+use tracing::{info, warn};
+
+use crate::announcement::{ItemAccepted, ItemsAnnounced, ItemsNotFound, ItemsRequested};
+use crate::config::StorageConfig;
+use crate::debug_log::LogEvent;
+use crate::event::{
+    ConnectionClosed, ConnectionFailed, ConnectionId, ConnectionOpened, MessageSeen,
+    NetworkDetected, P2pEvent, PingMeasured, QueueReceiver, TransportDetected,
+};
+use crate::handshake::{Handshake, HandshakeSide};
+use crate::parse_health::ParseHealth;
+use crate::relay::{FeeFilterReceived, TxReceived};
+use crate::rpc::{CorePeer, NodeStatus};
+use crate::shutdown::{Shutdown, ShutdownPhase};
+use crate::transport::PacketStats;
+
+/// Maximum number of events written in a single transaction
+const MAX_BATCH_SIZE: usize = 1024;
+
+/// Rows per multi-row insert, to stay below SQLite's limit of 32766 bound values
+const MAX_INSERT_ROWS: usize = 4096;
+
+/// Persistent SQLite storage for peers, connections and messages
+#[derive(Clone)]
+pub struct Store {
+    pool: SqlitePool,
+}
+
+impl Store {
+    /// Open (or create) the database at the configured path and run migrations
+    pub async fn open(config: &StorageConfig) -> anyhow::Result<Self> {
+        let options = SqliteConnectOptions::new()
+            .filename(&config.database_path)
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .connect_with(options)
+            .await
+            .context(format!("Couldn't open database {:?}", config.database_path))?;
+
+        Self::init(pool).await
+    }
+
+    /// Open a private in-memory database, used in tests
+    #[cfg(test)]
+    pub async fn in_memory() -> anyhow::Result<Self> {
+        use std::str::FromStr;
+
+        let options = SqliteConnectOptions::from_str("sqlite::memory:")?.foreign_keys(true);
+        // Every in-memory connection is its own database, so keep a single one
+        let pool = SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect_with(options)
+            .await?;
+
+        Self::init(pool).await
+    }
+
+    async fn init(pool: SqlitePool) -> anyhow::Result<Self> {
+        sqlx::migrate!("./migrations")
+            .run(&pool)
+            .await
+            .context("Couldn't run database migrations")?;
+
+        // Connections left open by a previous run can't be active anymore
+        let result = sqlx::query("UPDATE connections SET closed_at = ? WHERE closed_at IS NULL")
+            .bind(Utc::now())
+            .execute(&pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            info!(
+                "Marked {} connections from a previous run as closed",
+                result.rows_affected()
+            );
+        }
+
+        Ok(Self { pool })
+    }
+
+    /// Highest connection id stored so far
+    pub async fn max_connection_id(&self) -> anyhow::Result<Option<ConnectionId>> {
+        // Handshakes get an id on accept, even if no connection gets established
+        let max: Option<i64> = sqlx::query_scalar(
+            "SELECT MAX(id) FROM (
+               SELECT MAX(id) AS id FROM connections
+               UNION ALL SELECT MAX(connection_id) FROM handshakes
+             )",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(max.map(|id| id as ConnectionId))
+    }
+
+    /// Write queued events in transactions, until the shutdown stops it
+    ///
+    /// Events already queued when stopping are still written.
+    pub async fn record_events(self, mut events: QueueReceiver, shutdown: Shutdown) {
+        loop {
+            let first = tokio::select! {
+                biased;
+                event = events.recv() => match event {
+                    Some(event) => event,
+                    None => break,
+                },
+                _ = shutdown.reached(ShutdownPhase::Stopped) => {
+                    self.flush(&mut events).await;
+                    break;
+                }
+            };
+
+            let mut batch = vec![first];
+            while batch.len() < MAX_BATCH_SIZE {
+                match events.try_recv() {
+                    Some(event) => batch.push(event),
+                    None => break,
+                }
+            }
+
+            warn_dropped(&events);
+            if let Err(e) = self.write_batch(&batch).await {
+                warn!("Failed to record {} events: {:#}", batch.len(), e);
+            }
+        }
+    }
+
+    /// Write the events still queued
+    async fn flush(&self, events: &mut QueueReceiver) {
+        let mut batch = Vec::new();
+        while let Some(event) = events.try_recv() {
+            batch.push(event);
+        }
+
+        warn_dropped(events);
+        for chunk in batch.chunks(MAX_BATCH_SIZE) {
+            if let Err(e) = self.write_batch(chunk).await {
+                warn!("Failed to record {} events: {:#}", chunk.len(), e);
+            }
+        }
+        if !batch.is_empty() {
+            info!("Recorded {} remaining events", batch.len());
+        }
+    }
+
+    /// Record Bitcoin Core's latest view of the given connections
+    pub async fn upsert_core_peers(&self, peers: &[CorePeer]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for peer in peers {
+            sqlx::query(
+                "INSERT OR REPLACE INTO core_peers (
+                   connection_id, node, peer_id, connection_type, ban_score, starting_height,
+                   synced_headers, synced_blocks, updated_at
+                 ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(peer.connection_id as i64)
+            .bind(&peer.node)
+            .bind(peer.peer_id)
+            .bind(peer.connection_type)
+            .bind(peer.ban_score)
+            .bind(peer.starting_height)
+            .bind(peer.synced_headers)
+            .bind(peer.synced_blocks)
+            .bind(peer.updated_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the latest state of a node
+    pub async fn upsert_node_status(&self, status: &NodeStatus) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT OR REPLACE INTO node_status (
+               node, version, subversion, chain, blocks, headers, verification_progress,
+               initial_block_download, connections_in, connections_out, total_bytes_recv,
+               total_bytes_sent, mempool_size, mempool_bytes, updated_at
+             ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&status.node)
+        .bind(status.version)
+        .bind(&status.subversion)
+        .bind(&status.chain)
+        .bind(status.blocks)
+        .bind(status.headers)
+        .bind(status.verification_progress)
+        .bind(status.initial_block_download)
+        .bind(status.connections_in)
+        .bind(status.connections_out)
+        .bind(status.total_bytes_recv)
+        .bind(status.total_bytes_sent)
+        .bind(status.mempool_size)
+        .bind(status.mempool_bytes)
+        .bind(status.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Record events parsed from a node's debug.log
+    pub async fn insert_log_events(&self, events: &[LogEvent]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for event in events {
+            sqlx::query(
+                "INSERT INTO log_events (
+                   node, connection_id, peer_id, address, kind, height, message, logged_at
+                 ) VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            )
+            .bind(&event.node)
+            .bind(event.connection_id.map(|id| id as i64))
+            .bind(event.peer_id)
+            .bind(&event.address)
+            .bind(event.kind)
+            .bind(event.height)
+            .bind(&event.message)
+            .bind(event.logged_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record that the node accepted a block or transaction, keeping the first acceptance
+    ///
+    /// Topics notify about the same item, so a later notification only adds a missing wtxid.
+    pub async fn upsert_acceptance(&self, accepted: &ItemAccepted) -> anyhow::Result<()> {
+        sqlx::query(
+            "INSERT INTO acceptances (node, kind, hash, wtxid, accepted_at) VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT(node, kind, hash) DO UPDATE SET wtxid = COALESCE(wtxid, excluded.wtxid)",
+        )
+        .bind(&accepted.node)
+        .bind(accepted.kind)
+        .bind(&accepted.hash)
+        .bind(&accepted.wtxid)
+        .bind(accepted.accepted_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Close all database connections, checkpointing the write-ahead log
+    pub async fn close(&self) {
+        self.pool.close().await;
+    }
+
+    /// Write events in one transaction, skipping those that can't be written
+    async fn write_batch(&self, batch: &[P2pEvent]) -> anyhow::Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for event in batch {
+            // A savepoint per event, so a failing one doesn't roll back the rest of the batch
+            let mut savepoint = tx.begin().await?;
+            match write_event(&mut savepoint, event).await {
+                Ok(()) => savepoint.commit().await?,
+                Err(e) => {
+                    savepoint.rollback().await?;
+                    warn!(
+                        "[conn:{}] Failed to record event: {:#}",
+                        event.connection_id(),
+                        e
+                    );
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+fn warn_dropped(events: &QueueReceiver) {
+    let dropped = events.take_dropped();
+    if dropped > 0 {
+        warn!("Storage fell behind, {} events were not recorded", dropped);
+    }
+}
+
+async fn write_event(tx: &mut Transaction<'_, Sqlite>, event: &P2pEvent) -> anyhow::Result<()> {
+    match event {
+        P2pEvent::ConnectionOpened(e) => insert_connection(tx, e).await,
+        P2pEvent::ConnectionFailed(e) => insert_connect_failure(tx, e).await,
+        P2pEvent::MessageSeen(e) => insert_message(tx, e).await,
+        P2pEvent::ConnectionClosed(e) => close_connection(tx, e).await,
+        P2pEvent::HandshakeUpdated(e) => upsert_handshake(tx, e).await,
+        P2pEvent::PingMeasured(e) => insert_ping(tx, e).await,
+        P2pEvent::ParseHealthUpdated(e) => upsert_parse_health(tx, e).await,
+        P2pEvent::TransportDetected(e) => set_transport(tx, e).await,
+        P2pEvent::NetworkDetected(e) => set_network(tx, e).await,
+        P2pEvent::PacketStatsRecorded(e) => insert_packet_stats(tx, e).await,
+        P2pEvent::ItemsAnnounced(e) => insert_announcements(tx, e).await,
+        P2pEvent::ItemsRequested(e) => insert_inventory_requests(tx, e).await,
+        P2pEvent::ItemsNotFound(e) => mark_not_found(tx, e).await,
+        P2pEvent::TxReceived(e) => insert_tx_delivery(tx, e).await,
+        P2pEvent::FeeFilterReceived(e) => insert_fee_filter(tx, e).await,
+    }
+}
+
+async fn insert_connection(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &ConnectionOpened,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO peers (address, first_seen, last_seen, network_type) VALUES (?1, ?2, ?2, ?3)
+         ON CONFLICT(address) DO UPDATE SET
+           last_seen = excluded.last_seen, network_type = excluded.network_type",
+    )
+    .bind(&event.target_addr)
+    .bind(event.timestamp)
+    .bind(event.network_type)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "INSERT INTO connections
+           (id, node, client_addr, target_addr, network, inbound, peer_port, local_addr, opened_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.connection_id as i64)
+    .bind(&event.node)
+    .bind(&event.client_addr)
+    .bind(&event.target_addr)
+    .bind(network_name(event.network))
+    .bind(event.inbound)
+    .bind(event.peer_port)
+    .bind(&event.local_addr)
+    .bind(event.timestamp)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_connect_failure(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &ConnectionFailed,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO connect_failures (connection_id, node, client_addr, target_addr, network,
+           error, detail, failed_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.connection_id as i64)
+    .bind(&event.node)
+    .bind(&event.client_addr)
+    .bind(&event.target_addr)
+    .bind(network_name(event.network))
+    .bind(event.error)
+    .bind(&event.detail)
+    .bind(event.timestamp)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_message(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &MessageSeen,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO messages (connection_id, direction, command, payload_len, description,
+           received_at, forwarded_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.connection_id as i64)
+    .bind(event.direction)
+    .bind(&event.command)
+    .bind(event.payload_len as i64)
+    .bind(&event.description)
+    .bind(event.received_at)
+    .bind(event.forwarded_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_ping(tx: &mut Transaction<'_, Sqlite>, event: &PingMeasured) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO ping_rtts (connection_id, direction, nonce, ping_at, pong_at, rtt_us)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.connection_id as i64)
+    .bind(event.direction)
+    .bind(event.nonce as i64)
+    .bind(event.ping_at)
+    .bind(event.pong_at)
+    .bind(event.rtt().num_microseconds().unwrap_or(i64::MAX))
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn close_connection(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &ConnectionClosed,
+) -> anyhow::Result<()> {
+    let connection_id = event.connection_id as i64;
+
+    sqlx::query(
+        "UPDATE connections SET closed_at = ?, bytes_inbound = ?, bytes_outbound = ?,
+           messages_inbound = ?, messages_outbound = ?
+         WHERE id = ?",
+    )
+    .bind(event.timestamp)
+    .bind(event.stats.bytes_inbound as i64)
+    .bind(event.stats.bytes_outbound as i64)
+    .bind(event.stats.messages_inbound as i64)
+    .bind(event.stats.messages_outbound as i64)
+    .bind(connection_id)
+    .execute(&mut **tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE peers SET last_seen = ? WHERE address = (SELECT target_addr FROM connections WHERE id = ?)",
+    )
+    .bind(event.timestamp)
+    .bind(connection_id)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn upsert_handshake(
+    tx: &mut Transaction<'_, Sqlite>,
+    handshake: &Handshake,
+) -> anyhow::Result<()> {
+    let query = sqlx::query(
+        "INSERT OR REPLACE INTO handshakes (
+           connection_id, node, client_addr, target_addr, stream_isolation, outcome, failure_reason,
+           failure_detail, started_at, completed_at, failed_at,
+           local_version, local_services, local_timestamp, local_user_agent, local_start_height,
+           local_relay, local_nonce, local_addr_from, local_addr_recv, local_version_at,
+           local_verack_at, local_wtxidrelay, local_sendaddrv2, local_sendcmpct_announce,
+           local_sendcmpct_version,
+           remote_version, remote_services, remote_timestamp, remote_user_agent, remote_start_height,
+           remote_relay, remote_nonce, remote_addr_from, remote_addr_recv, remote_version_at,
+           remote_verack_at, remote_wtxidrelay, remote_sendaddrv2, remote_sendcmpct_announce,
+           remote_sendcmpct_version
+         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+           ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+           ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(handshake.connection_id as i64)
+    .bind(&handshake.node)
+    .bind(&handshake.client_addr)
+    .bind(&handshake.target_addr)
+    .bind(&handshake.stream_isolation)
+    .bind(handshake.outcome)
+    .bind(handshake.failure_reason)
+    .bind(&handshake.failure_detail)
+    .bind(handshake.started_at)
+    .bind(handshake.completed_at)
+    .bind(handshake.failed_at);
+
+    let query = bind_handshake_side(query, &handshake.local);
+    let query = bind_handshake_side(query, &handshake.remote);
+    query.execute(&mut **tx).await?;
+
+    Ok(())
+}
+
+async fn upsert_parse_health(
+    tx: &mut Transaction<'_, Sqlite>,
+    health: &ParseHealth,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO parse_health (
+           connection_id, direction, state, desyncs, bytes_skipped, checksum_mismatches,
+           oversize_payloads, unknown_commands, invalid_payloads, updated_at
+         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(health.connection_id as i64)
+    .bind(health.direction)
+    .bind(health.state)
+    .bind(health.desyncs as i64)
+    .bind(health.bytes_skipped as i64)
+    .bind(health.checksum_mismatches as i64)
+    .bind(health.oversize_payloads as i64)
+    .bind(health.unknown_commands as i64)
+    .bind(health.invalid_payloads as i64)
+    .bind(health.updated_at)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn set_transport(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &TransportDetected,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE connections SET transport = ? WHERE id = ?")
+        .bind(event.transport)
+        .bind(event.connection_id as i64)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+async fn set_network(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &NetworkDetected,
+) -> anyhow::Result<()> {
+    sqlx::query("UPDATE connections SET network = ? WHERE id = ?")
+        .bind(event.network.to_string())
+        .bind(event.connection_id as i64)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Name of a network as stored, `auto` while it is being detected
+fn network_name(network: Option<bitcoin::Network>) -> String {
+    network.map_or_else(|| "auto".to_string(), |network| network.to_string())
+}
+
+async fn insert_packet_stats(
+    tx: &mut Transaction<'_, Sqlite>,
+    stats: &PacketStats,
+) -> anyhow::Result<()> {
+    let [le_64, le_256, le_1024, le_4096, gt_4096] = stats.size_buckets.map(|n| n as i64);
+    sqlx::query(
+        "INSERT OR REPLACE INTO packet_stats (
+           connection_id, direction, packets, bytes, first_at, last_at,
+           size_le_64, size_le_256, size_le_1024, size_le_4096, size_gt_4096
+         ) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(stats.connection_id as i64)
+    .bind(stats.direction)
+    .bind(stats.packets as i64)
+    .bind(stats.bytes as i64)
+    .bind(stats.first_at)
+    .bind(stats.last_at)
+    .bind(le_64)
+    .bind(le_256)
+    .bind(le_1024)
+    .bind(le_4096)
+    .bind(gt_4096)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_announcements(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &ItemsAnnounced,
+) -> anyhow::Result<()> {
+    // An inv announces up to 50000 items, each gets a row
+    for items in event.items.chunks(MAX_INSERT_ROWS) {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "INSERT INTO announcements (connection_id, node, kind, hash, via, announced_at) ",
+        );
+        query.push_values(items, |mut row, item| {
+            row.push_bind(event.connection_id as i64)
+                .push_bind(&event.node)
+                .push_bind(item.kind)
+                .push_bind(&item.hash)
+                .push_bind(event.via)
+                .push_bind(event.timestamp);
+        });
+        query.build().execute(&mut **tx).await?;
+    }
+
+    Ok(())
+}
+
+async fn insert_inventory_requests(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &ItemsRequested,
+) -> anyhow::Result<()> {
+    for item in &event.items {
+        sqlx::query(
+            "INSERT INTO inventory_requests (connection_id, node, kind, hash, requested_at)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(event.connection_id as i64)
+        .bind(&event.node)
+        .bind(item.kind)
+        .bind(&item.hash)
+        .bind(event.timestamp)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Mark the latest open request of each item on the connection as not found
+///
+/// A notfound without an open request, e.g. as the request wasn't inspected, is
+/// recorded on its own.
+async fn mark_not_found(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &ItemsNotFound,
+) -> anyhow::Result<()> {
+    for item in &event.items {
+        let marked = sqlx::query(
+            "UPDATE inventory_requests SET not_found_at = ? WHERE id = (
+               SELECT id FROM inventory_requests
+               WHERE connection_id = ? AND kind = ? AND hash = ? AND not_found_at IS NULL
+               ORDER BY requested_at DESC, id DESC LIMIT 1
+             )",
+        )
+        .bind(event.timestamp)
+        .bind(event.connection_id as i64)
+        .bind(item.kind)
+        .bind(&item.hash)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected();
+        if marked > 0 {
+            continue;
+        }
+
+        sqlx::query(
+            "INSERT INTO inventory_requests (connection_id, node, kind, hash, not_found_at)
+             VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(event.connection_id as i64)
+        .bind(&event.node)
+        .bind(item.kind)
+        .bind(&item.hash)
+        .bind(event.timestamp)
+        .execute(&mut **tx)
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn insert_tx_delivery(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &TxReceived,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        "INSERT INTO tx_deliveries (connection_id, node, txid, wtxid, vsize, fee, fee_rate, received_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+    )
+    .bind(event.connection_id as i64)
+    .bind(&event.node)
+    .bind(&event.txid)
+    .bind(&event.wtxid)
+    .bind(event.vsize as i64)
+    .bind(event.fee.map(|fee| fee as i64))
+    .bind(event.fee_rate())
+    .bind(event.timestamp)
+    .execute(&mut **tx)
+    .await?;
+
+    Ok(())
+}
+
+async fn insert_fee_filter(
+    tx: &mut Transaction<'_, Sqlite>,
+    event: &FeeFilterReceived,
+) -> anyhow::Result<()> {
+    sqlx::query("INSERT INTO fee_filters (connection_id, fee_rate, received_at) VALUES (?, ?, ?)")
+        .bind(event.connection_id as i64)
+        .bind(event.fee_rate)
+        .bind(event.timestamp)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+type SqliteQuery<'q> = sqlx::query::Query<'q, Sqlite, sqlx::sqlite::SqliteArguments<'q>>;
+
+/// Bind the 15 columns of one handshake side, in table order
+fn bind_handshake_side<'q>(query: SqliteQuery<'q>, side: &'q HandshakeSide) -> SqliteQuery<'q> {
+    let version = side.version.as_ref();
+    query
+        .bind(version.map(|v| v.protocol_version as i64))
+        .bind(version.map(|v| v.services as i64))
+        .bind(version.map(|v| v.timestamp))
+        .bind(version.map(|v| v.user_agent.as_str()))
+        .bind(version.map(|v| v.start_height))
+        .bind(version.map(|v| v.relay))
+        .bind(version.map(|v| v.nonce as i64))
+        .bind(version.map(|v| v.addr_from.as_str()))
+        .bind(version.map(|v| v.addr_recv.as_str()))
+        .bind(version.and_then(|v| v.seen_at))
+        .bind(side.verack_at)
+        .bind(side.wtxid_relay)
+        .bind(side.send_addr_v2)
+        .bind(side.send_cmpct.map(|c| c.announce))
+        .bind(side.send_cmpct.map(|c| c.version as i64))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use bitcoin::p2p::message::NetworkMessage;
+
+    use super::*;
+    use crate::announcement::{AnnouncedVia, Item, ItemKind, ItemsNotFound, ItemsRequested};
+    use crate::event::{ConnectError, ConnectionStats, Direction, EventQueue};
+    use crate::handshake::{HandshakeFailureReason, HandshakeOutcome, VersionInfo};
+    use crate::network_type::NetworkType;
+    use crate::parse_health::ParserState;
+    use crate::relay::{FeeFilterReceived, TxReceived};
+    use crate::rpc::CoreConnectionType;
+    use crate::transport::Transport;
+
+    #[tokio::test]
+    async fn test_records_connection_lifecycle() {
+        let store = Store::in_memory().await.unwrap();
+        let now = Utc::now();
+
+        let mut handshake =
+            Handshake::new(1, "mainnet".to_string(), "127.0.0.1:50000".to_string(), now);
+        handshake.target_addr = Some("1.2.3.4:8333".to_string());
+        handshake.stream_isolation = Some("0".to_string());
+        handshake.outcome = HandshakeOutcome::Completed;
+        handshake.remote.version = Some(VersionInfo {
+            protocol_version: 70016,
+            services: 1033,
+            timestamp: now.timestamp(),
+            user_agent: "/Satoshi:27.0.0/".to_string(),
+            start_height: 850_000,
+            relay: true,
+            nonce: u64::MAX,
+            addr_from: "0.0.0.0:0".to_string(),
+            addr_recv: "1.2.3.4:8333".to_string(),
+            seen_at: Some(now),
+        });
+        handshake.remote.wtxid_relay = true;
+
+        store
+            .write_batch(&[
+                P2pEvent::ConnectionOpened(ConnectionOpened {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    client_addr: "127.0.0.1:50000".to_string(),
+                    target_addr: "1.2.3.4:8333".to_string(),
+                    peer_port: Some(50123),
+                    local_addr: Some("127.0.0.1:40404".to_string()),
+                    network: Some(bitcoin::Network::Bitcoin),
+                    network_type: NetworkType::Ipv4,
+                    inbound: true,
+                    timestamp: now,
+                }),
+                P2pEvent::HandshakeUpdated(Box::new(handshake)),
+                P2pEvent::MessageSeen(MessageSeen {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    direction: Direction::Outbound,
+                    command: "verack".to_string(),
+                    payload_len: 0,
+                    description: "verack: handshake complete".to_string(),
+                    message: Arc::new(NetworkMessage::Verack),
+                    received_at: now,
+                    forwarded_at: Some(now + chrono::Duration::microseconds(150)),
+                }),
+                P2pEvent::PingMeasured(PingMeasured {
+                    connection_id: 1,
+                    direction: Direction::Inbound,
+                    nonce: 42,
+                    ping_at: now,
+                    pong_at: now + chrono::Duration::milliseconds(85),
+                }),
+                P2pEvent::ParseHealthUpdated(ParseHealth {
+                    state: ParserState::Resynced,
+                    desyncs: 1,
+                    bytes_skipped: 3,
+                    ..ParseHealth::new(1, Direction::Outbound)
+                }),
+                P2pEvent::TransportDetected(TransportDetected {
+                    connection_id: 1,
+                    transport: Transport::V2Encrypted,
+                    timestamp: now,
+                }),
+                P2pEvent::PacketStatsRecorded(PacketStats {
+                    packets: 2,
+                    bytes: 48,
+                    first_at: Some(now),
+                    last_at: Some(now),
+                    size_buckets: [1, 1, 0, 0, 0],
+                    ..PacketStats::new(1, Direction::Inbound)
+                }),
+                P2pEvent::ConnectionClosed(ConnectionClosed {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    stats: ConnectionStats {
+                        bytes_inbound: 48,
+                        messages_inbound: 1,
+                        ..Default::default()
+                    },
+                    timestamp: now,
+                }),
+            ])
+            .await
+            .unwrap();
+
+        let connection = store.connection(1).await.unwrap().unwrap();
+        assert_eq!(connection.stats().bytes_inbound, 48);
+        assert!(connection.closed_at.is_some());
+        assert_eq!(connection.transport, Some(Transport::V2Encrypted));
+        assert!(connection.inbound);
+        assert_eq!(connection.peer_port, Some(50123));
+        assert_eq!(connection.local_addr.as_deref(), Some("127.0.0.1:40404"));
+
+        let peer = store
+            .peers_by_address(&["1.2.3.4:8333".to_string()], None)
+            .await
+            .unwrap()
+            .remove(0);
+        assert_eq!(peer.network_type, Some(NetworkType::Ipv4));
+
+        let packets = store.packet_stats(&[1]).await.unwrap().remove(0);
+        assert_eq!(packets.size_buckets, [1, 1, 0, 0, 0]);
+
+        let split = store
+            .peer_transports(&["1.2.3.4:8333".to_string()], None)
+            .await
+            .unwrap();
+        assert_eq!(split[0].transport, Some(Transport::V2Encrypted));
+        assert_eq!(split[0].connections, 1);
+
+        let message = store.recent_messages(&[1], 10).await.unwrap().remove(0);
+        assert_eq!(message.forward_latency_us(), Some(150));
+        assert_eq!(message.node, "mainnet");
+        let listed = store
+            .list_messages(1, &MessageFilter::default(), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(listed[0].node, "mainnet");
+
+        let ping = store.ping_rtts(&[1], 10).await.unwrap().remove(0);
+        assert_eq!(ping.nonce, 42);
+        assert_eq!(ping.rtt_us, 85_000);
+
+        let health = store.parse_health(&[1]).await.unwrap().remove(0);
+        assert_eq!(health.state, ParserState::Resynced);
+        assert_eq!(health.bytes_skipped, 3);
+
+        let handshake = store.handshakes(&[1]).await.unwrap().remove(0);
+        assert_eq!(handshake.outcome, HandshakeOutcome::Completed);
+        assert_eq!(handshake.stream_isolation.as_deref(), Some("0"));
+        let version = handshake.remote.version.unwrap();
+        assert_eq!(version.user_agent, "/Satoshi:27.0.0/");
+        assert_eq!(version.nonce, u64::MAX);
+        assert!(handshake.remote.wtxid_relay);
+        assert!(handshake.local.version.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_failed_handshake_reserves_connection_id() {
+        let store = Store::in_memory().await.unwrap();
+
+        let mut handshake = Handshake::new(
+            5,
+            "mainnet".to_string(),
+            "127.0.0.1:50000".to_string(),
+            Utc::now(),
+        );
+        handshake.outcome = HandshakeOutcome::Failed;
+        handshake.failure_reason = Some(HandshakeFailureReason::Socks5Error);
+        store
+            .write_batch(&[P2pEvent::HandshakeUpdated(Box::new(handshake))])
+            .await
+            .unwrap();
+
+        assert_eq!(store.max_connection_id().await.unwrap(), Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_records_connect_failure() {
+        let store = Store::in_memory().await.unwrap();
+
+        store
+            .write_batch(&[P2pEvent::ConnectionFailed(ConnectionFailed {
+                connection_id: 7,
+                node: "mainnet".to_string(),
+                client_addr: "127.0.0.1:50000".to_string(),
+                target_addr: "1.2.3.4:8333".to_string(),
+                network: Some(bitcoin::Network::Bitcoin),
+                error: ConnectError::from(std::io::ErrorKind::ConnectionRefused),
+                detail: "Connection refused (os error 111)".to_string(),
+                timestamp: Utc::now(),
+            })])
+            .await
+            .unwrap();
+
+        let failure = store.connect_failures(&[7]).await.unwrap().remove(0);
+        assert_eq!(failure.error, ConnectError::Refused);
+        assert!(store.connection(7).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_separates_nodes() {
+        let store = Store::in_memory().await.unwrap();
+        let opened = |connection_id, node: &str, network, target_addr: &str| {
+            P2pEvent::ConnectionOpened(ConnectionOpened {
+                connection_id,
+                node: node.to_string(),
+                client_addr: "127.0.0.1:50000".to_string(),
+                target_addr: target_addr.to_string(),
+                peer_port: None,
+                local_addr: None,
+                network,
+                network_type: NetworkType::Ipv4,
+                inbound: false,
+                timestamp: Utc::now(),
+            })
+        };
+
+        store
+            .write_batch(&[
+                opened(1, "mainnet", None, "1.2.3.4:8333"),
+                P2pEvent::NetworkDetected(NetworkDetected {
+                    connection_id: 1,
+                    network: bitcoin::Network::Bitcoin,
+                    timestamp: Utc::now(),
+                }),
+                opened(2, "signet", Some(bitcoin::Network::Signet), "5.6.7.8:38333"),
+                opened(3, "signet", Some(bitcoin::Network::Signet), "5.6.7.8:38333"),
+                opened(4, "signet", Some(bitcoin::Network::Signet), "1.2.3.4:8333"),
+            ])
+            .await
+            .unwrap();
+
+        let filter = ConnectionFilter {
+            node: Some("signet".to_string()),
+            ..Default::default()
+        };
+        let connections = store.list_connections(&filter, None, 10).await.unwrap();
+        assert_eq!(connections.len(), 3);
+        assert!(connections.iter().all(|c| c.node == "signet"));
+
+        let peers = store.list_peers(Some("mainnet"), None, 10).await.unwrap();
+        assert_eq!(peers.len(), 1);
+        assert_eq!(peers[0].address, "1.2.3.4:8333");
+        assert_eq!(peers[0].node.as_deref(), Some("mainnet"));
+        assert_eq!(
+            store
+                .list_peers(Some("signet"), None, 10)
+                .await
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(store.list_peers(None, None, 10).await.unwrap().len(), 2);
+
+        // A peer both nodes connected to is seen by each node on its own
+        let address = ["1.2.3.4:8333".to_string()];
+        let signet = store
+            .peers_by_address(&address, Some("signet"))
+            .await
+            .unwrap()
+            .remove(0);
+        assert!(signet.first_seen > peers[0].first_seen);
+        let all = store
+            .peers_by_address(&address, None)
+            .await
+            .unwrap()
+            .remove(0);
+        assert_eq!(all.first_seen, peers[0].first_seen);
+        assert_eq!(all.node, None);
+
+        let connections = store
+            .connections_by_peer(&address, Some("signet"), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(connections.len(), 1);
+        assert_eq!(connections[0].id, 4);
+        let transports = store
+            .peer_transports(&address, Some("mainnet"))
+            .await
+            .unwrap();
+        assert_eq!(transports.len(), 1);
+        assert_eq!(transports[0].connections, 1);
+
+        let nodes = store.nodes().await.unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].network, "bitcoin");
+        assert_eq!(nodes[1].node, "signet");
+        assert_eq!(nodes[1].network, "signet");
+        assert_eq!(nodes[1].connections, 3);
+    }
+
+    fn opened(connection_id: ConnectionId) -> P2pEvent {
+        P2pEvent::ConnectionOpened(ConnectionOpened {
+            connection_id,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:50000".to_string(),
+            target_addr: "1.2.3.4:8333".to_string(),
+            peer_port: None,
+            local_addr: None,
+            network: Some(bitcoin::Network::Bitcoin),
+            network_type: NetworkType::Ipv4,
+            inbound: false,
+            timestamp: Utc::now(),
+        })
+    }
+
+    fn ping(connection_id: ConnectionId, nonce: u64) -> P2pEvent {
+        P2pEvent::PingMeasured(PingMeasured {
+            connection_id,
+            direction: Direction::Inbound,
+            nonce,
+            ping_at: Utc::now(),
+            pong_at: Utc::now(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_stop_flushes_published_events() {
+        let store = Store::in_memory().await.unwrap();
+        let shutdown = Shutdown::new();
+        let (queue, events) = EventQueue::new(16);
+        let recorder = tokio::spawn(store.clone().record_events(events, shutdown.clone()));
+
+        for connection_id in 1..=3 {
+            queue.push(opened(connection_id));
+        }
+        shutdown.stop();
+        recorder.await.unwrap();
+
+        let filter = ConnectionFilter::default();
+        assert_eq!(
+            store
+                .list_connections(&filter, None, 10)
+                .await
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lagging_keeps_lifecycle_events() {
+        let store = Store::in_memory().await.unwrap();
+        let shutdown = Shutdown::new();
+        let (queue, events) = EventQueue::new(4);
+
+        // Publish far more than fits while nothing is recording yet
+        queue.push(opened(1));
+        for nonce in 0..100 {
+            queue.push(ping(1, nonce));
+        }
+        queue.push(opened(2));
+        queue.push(P2pEvent::ConnectionClosed(ConnectionClosed {
+            connection_id: 1,
+            node: "mainnet".to_string(),
+            stats: ConnectionStats::default(),
+            timestamp: Utc::now(),
+        }));
+
+        let recorder = tokio::spawn(store.clone().record_events(events, shutdown.clone()));
+        shutdown.stop();
+        recorder.await.unwrap();
+
+        let first = store.connection(1).await.unwrap().unwrap();
+        assert!(first.closed_at.is_some());
+        assert!(store.connection(2).await.unwrap().is_some());
+        assert_eq!(store.ping_rtts(&[1], 10).await.unwrap().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_failing_event_keeps_rest_of_batch() {
+        let store = Store::in_memory().await.unwrap();
+
+        // The second ping belongs to a connection that was never recorded
+        store
+            .write_batch(&[opened(1), ping(1, 1), ping(9, 2), ping(1, 3)])
+            .await
+            .unwrap();
+
+        assert!(store.connection(1).await.unwrap().is_some());
+        let nonces: Vec<_> = store
+            .ping_rtts(&[1, 9], 10)
+            .await
+            .unwrap()
+            .iter()
+            .map(|ping| ping.nonce)
+            .collect();
+        assert_eq!(nonces, vec![1, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_limits_ping_rtts_per_connection() {
+        let store = Store::in_memory().await.unwrap();
+        store
+            .write_batch(&[
+                opened(1),
+                opened(2),
+                ping(1, 1),
+                ping(2, 2),
+                ping(1, 3),
+                ping(1, 4),
+            ])
+            .await
+            .unwrap();
+
+        let nonces: Vec<_> = store
+            .ping_rtts(&[1, 2], 2)
+            .await
+            .unwrap()
+            .iter()
+            .map(|ping| (ping.connection_id, ping.nonce))
+            .collect();
+        assert_eq!(nonces, vec![(2, 2), (1, 3), (1, 4)]);
+    }
+
+    #[tokio::test]
+    async fn test_records_core_peers() {
+        let store = Store::in_memory().await.unwrap();
+        let peer = CorePeer {
+            connection_id: 4,
+            node: "mainnet".to_string(),
+            peer_id: 17,
+            connection_type: Some(CoreConnectionType::BlockRelayOnly),
+            ban_score: None,
+            starting_height: Some(850_000),
+            synced_headers: Some(850_010),
+            synced_blocks: Some(850_009),
+            updated_at: Utc::now(),
+        };
+        store
+            .upsert_core_peers(std::slice::from_ref(&peer))
+            .await
+            .unwrap();
+        store
+            .upsert_core_peers(&[CorePeer {
+                synced_blocks: Some(850_010),
+                ..peer
+            }])
+            .await
+            .unwrap();
+
+        let peer = store.core_peers(&[4]).await.unwrap().remove(0);
+        assert_eq!(peer.peer_id, 17);
+        assert_eq!(
+            peer.connection_type,
+            Some(CoreConnectionType::BlockRelayOnly)
+        );
+        assert_eq!(peer.synced_blocks, Some(850_010));
+        assert!(peer.ban_score.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_correlates_announcements_with_acceptances() {
+        let store = Store::in_memory().await.unwrap();
+        let accepted_at: chrono::DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let opened = |connection_id| {
+            P2pEvent::ConnectionOpened(ConnectionOpened {
+                connection_id,
+                node: "mainnet".to_string(),
+                client_addr: "127.0.0.1:50000".to_string(),
+                target_addr: format!("1.2.3.{}:8333", connection_id),
+                peer_port: None,
+                local_addr: None,
+                network: Some(bitcoin::Network::Bitcoin),
+                network_type: NetworkType::Ipv4,
+                inbound: false,
+                timestamp: accepted_at,
+            })
+        };
+        let announced = |connection_id, via, kind, hash: &str, offset_ms| {
+            P2pEvent::ItemsAnnounced(ItemsAnnounced {
+                connection_id,
+                node: "mainnet".to_string(),
+                via,
+                items: vec![Item {
+                    kind,
+                    hash: hash.to_string(),
+                }],
+                timestamp: accepted_at + chrono::Duration::milliseconds(offset_ms),
+            })
+        };
+
+        store
+            .write_batch(&[
+                opened(1),
+                opened(2),
+                announced(1, AnnouncedVia::Headers, ItemKind::Block, "b1", -250),
+                announced(2, AnnouncedVia::Cmpctblock, ItemKind::Block, "b1", -500),
+                // Announced by wtxid, accepted by txid
+                announced(1, AnnouncedVia::Inv, ItemKind::Tx, "w1", 100),
+                announced(2, AnnouncedVia::Inv, ItemKind::Tx, "never accepted", 0),
+            ])
+            .await
+            .unwrap();
+        for (kind, hash, wtxid) in [(ItemKind::Block, "b1", None), (ItemKind::Tx, "t1", None)] {
+            store
+                .upsert_acceptance(&ItemAccepted {
+                    node: "mainnet".to_string(),
+                    kind,
+                    hash: hash.to_string(),
+                    wtxid,
+                    accepted_at,
+                })
+                .await
+                .unwrap();
+        }
+        // A later notification of the same transaction only adds its wtxid
+        store
+            .upsert_acceptance(&ItemAccepted {
+                node: "mainnet".to_string(),
+                kind: ItemKind::Tx,
+                hash: "t1".to_string(),
+                wtxid: Some("w1".to_string()),
+                accepted_at: accepted_at + chrono::Duration::seconds(5),
+            })
+            .await
+            .unwrap();
+
+        let acceptances = store
+            .list_acceptances(&AcceptanceFilter::default(), None, 10)
+            .await
+            .unwrap();
+        assert_eq!(acceptances.len(), 2);
+        assert_eq!(acceptances[0].wtxid.as_deref(), Some("w1"));
+        assert_eq!(acceptances[0].accepted_at, accepted_at);
+
+        let block = store
+            .acceptance_announcements(&[acceptances[1].id])
+            .await
+            .unwrap();
+        let first: Vec<_> = block.iter().map(|a| a.connection_id).collect();
+        assert_eq!(first, [2, 1]);
+        assert_eq!(block[0].via, AnnouncedVia::Cmpctblock);
+        assert!((block[0].delay_ms + 500.0).abs() < 1.0);
+
+        let stats = store.propagation_stats(&[1]).await.unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats[0].kind, ItemKind::Block);
+        assert_eq!((stats[0].announced, stats[0].first), (1, 0));
+        assert!((stats[0].avg_delay_ms + 250.0).abs() < 1.0);
+        assert_eq!((stats[1].announced, stats[1].first), (1, 1));
+        assert!((stats[1].avg_delay_ms - 100.0).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_tracks_inventory_per_peer() {
+        let store = Store::in_memory().await.unwrap();
+        let start: chrono::DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let at = |ms| start + chrono::Duration::milliseconds(ms);
+        let opened = |connection_id| {
+            P2pEvent::ConnectionOpened(ConnectionOpened {
+                connection_id,
+                node: "mainnet".to_string(),
+                client_addr: "127.0.0.1:50000".to_string(),
+                target_addr: format!("1.2.3.{}:8333", connection_id),
+                peer_port: None,
+                local_addr: None,
+                network: Some(bitcoin::Network::Bitcoin),
+                network_type: NetworkType::Ipv4,
+                inbound: false,
+                timestamp: start,
+            })
+        };
+        let tx = |hash: &str| {
+            vec![Item {
+                kind: ItemKind::Tx,
+                hash: hash.to_string(),
+            }]
+        };
+
+        store
+            .write_batch(&[
+                opened(1),
+                opened(2),
+                P2pEvent::ItemsAnnounced(ItemsAnnounced {
+                    connection_id: 2,
+                    node: "mainnet".to_string(),
+                    via: AnnouncedVia::Inv,
+                    items: tx("w1"),
+                    timestamp: at(0),
+                }),
+                P2pEvent::ItemsAnnounced(ItemsAnnounced {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    via: AnnouncedVia::Inv,
+                    items: tx("w1"),
+                    timestamp: at(80),
+                }),
+                P2pEvent::ItemsRequested(ItemsRequested {
+                    connection_id: 2,
+                    node: "mainnet".to_string(),
+                    items: tx("w1"),
+                    timestamp: at(100),
+                }),
+                P2pEvent::ItemsNotFound(ItemsNotFound {
+                    connection_id: 2,
+                    node: "mainnet".to_string(),
+                    items: tx("w1"),
+                    timestamp: at(150),
+                }),
+                P2pEvent::ItemsRequested(ItemsRequested {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    items: tx("w1"),
+                    timestamp: at(200),
+                }),
+                // Answering a request that wasn't seen
+                P2pEvent::ItemsNotFound(ItemsNotFound {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    items: tx("w2"),
+                    timestamp: at(250),
+                }),
+            ])
+            .await
+            .unwrap();
+        // Accepted by txid, which finds the announcements by wtxid too
+        store
+            .upsert_acceptance(&ItemAccepted {
+                node: "mainnet".to_string(),
+                kind: ItemKind::Tx,
+                hash: "t1".to_string(),
+                wtxid: Some("w1".to_string()),
+                accepted_at: at(300),
+            })
+            .await
+            .unwrap();
+
+        let announcements = store.item_announcements(None, "t1").await.unwrap();
+        let peers: Vec<_> = announcements.iter().map(|a| a.connection_id).collect();
+        assert_eq!(peers, [2, 1]);
+        assert!(
+            store
+                .item_announcements(Some("signet"), "w1")
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        let requests = store.item_requests(Some("mainnet"), "w1").await.unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].not_found_at, Some(at(150)));
+        assert_eq!(requests[1].connection_id, 1);
+        assert_eq!(requests[1].not_found_at, None);
+
+        let requests = store.item_requests(Some("mainnet"), "w2").await.unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].connection_id, 1);
+        assert_eq!(requests[0].requested_at, None);
+        assert_eq!(requests[0].not_found_at, Some(at(250)));
+
+        let mut stats = store.inventory_stats(&[1, 2]).await.unwrap();
+        stats.sort_by_key(|stats| stats.connection_id);
+        let counts: Vec<_> = stats
+            .iter()
+            .map(|s| (s.announced, s.first_announced, s.requested, s.not_found))
+            .collect();
+        assert_eq!(counts, [(1, 0, 1, 1), (1, 1, 1, 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_summarizes_tx_relay() {
+        let store = Store::in_memory().await.unwrap();
+        let start: chrono::DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+        let opened = |connection_id| {
+            P2pEvent::ConnectionOpened(ConnectionOpened {
+                connection_id,
+                node: "mainnet".to_string(),
+                client_addr: "127.0.0.1:50000".to_string(),
+                target_addr: format!("1.2.3.{}:8333", connection_id),
+                peer_port: None,
+                local_addr: None,
+                network: Some(bitcoin::Network::Bitcoin),
+                network_type: NetworkType::Ipv4,
+                inbound: false,
+                timestamp: start,
+            })
+        };
+        let delivered = |connection_id, vsize, fee| {
+            P2pEvent::TxReceived(TxReceived {
+                connection_id,
+                node: "mainnet".to_string(),
+                txid: "t1".to_string(),
+                wtxid: "w1".to_string(),
+                vsize,
+                fee,
+                timestamp: start,
+            })
+        };
+        let items = vec![Item {
+            kind: ItemKind::Tx,
+            hash: "w1".to_string(),
+        }];
+
+        store
+            .write_batch(&[
+                opened(1),
+                opened(2),
+                opened(3),
+                P2pEvent::ItemsAnnounced(ItemsAnnounced {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    via: AnnouncedVia::Inv,
+                    items: items.clone(),
+                    timestamp: start,
+                }),
+                P2pEvent::ItemsRequested(ItemsRequested {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    items,
+                    timestamp: start,
+                }),
+                // 5 sat/vB, then the same transaction again without a known fee
+                delivered(1, 200, Some(1000)),
+                delivered(2, 200, None),
+                P2pEvent::FeeFilterReceived(FeeFilterReceived {
+                    connection_id: 2,
+                    node: "mainnet".to_string(),
+                    fee_rate: 1000,
+                    timestamp: start,
+                }),
+            ])
+            .await
+            .unwrap();
+
+        let relay = store.relay_stats(&[1, 2]).await.unwrap();
+        assert_eq!(relay.len(), 2);
+        let first = &relay[0].stats;
+        assert_eq!(
+            (
+                first.announced,
+                first.requested,
+                first.delivered,
+                first.duplicates
+            ),
+            (1, 1, 1, 0)
+        );
+        assert_eq!(first.size_buckets, [0, 1, 0, 0, 0, 0]);
+        assert_eq!(first.fee_rate_buckets, [0, 0, 1, 0, 0, 0, 0, 0]);
+        assert_eq!(
+            (relay[1].stats.duplicates, relay[1].stats.fee_known),
+            (1, 0)
+        );
+        assert!(!relay[1].wtxid_relay);
+
+        let summary = store.relay_summary(Some("mainnet")).await.unwrap();
+        assert_eq!(summary.connections, 3);
+        assert_eq!(summary.stats.delivered, 2);
+        assert_eq!(summary.stats.size_buckets[1], 2);
+        assert_eq!(summary.stats.fee_known, 1);
+        assert_eq!(
+            store
+                .relay_summary(Some("signet"))
+                .await
+                .unwrap()
+                .connections,
+            0
+        );
+
+        let filters = store.fee_filters(&[2]).await.unwrap();
+        assert_eq!(filters[0].fee_rate, 1000);
+    }
+
+    #[tokio::test]
+    async fn test_stores_every_item_of_a_large_inv() {
+        let store = Store::in_memory().await.unwrap();
+        let items = (0..MAX_INSERT_ROWS + 10)
+            .map(|n| Item {
+                kind: ItemKind::Tx,
+                hash: format!("w{}", n),
+            })
+            .collect();
+        store
+            .write_batch(&[
+                opened(1),
+                P2pEvent::ItemsAnnounced(ItemsAnnounced {
+                    connection_id: 1,
+                    node: "mainnet".to_string(),
+                    via: AnnouncedVia::Inv,
+                    items,
+                    timestamp: Utc::now(),
+                }),
+            ])
+            .await
+            .unwrap();
+
+        for hash in ["w0", &format!("w{}", MAX_INSERT_ROWS + 9)] {
+            let announcements = store.item_announcements(None, hash).await.unwrap();
+            assert_eq!(announcements.len(), 1);
+        }
+    }
+}
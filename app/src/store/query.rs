@@ -0,0 +1,1093 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
+use sqlx::{QueryBuilder, Sqlite};
+
+use super::Store;
+use crate::announcement::{AnnouncedVia, ItemKind};
+use crate::debug_log::LogEventKind;
+use crate::event::{ConnectError, ConnectionId, ConnectionStats, Direction};
+use crate::handshake::{Handshake, HandshakeFailureReason, HandshakeOutcome};
+use crate::network_type::NetworkType;
+use crate::parse_health::ParseHealth;
+use crate::relay::{FEE_RATE_BUCKETS, TX_SIZE_BUCKETS};
+use crate::rpc::{CorePeer, NodeStatus};
+use crate::transport::{PacketStats, PeerTransportCount, Transport};
+
+/// A remote peer the node has connected to
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PeerRecord {
+    pub address: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_seen: DateTime<Utc>,
+    /// None for peers recorded before network types were tracked
+    pub network_type: Option<NetworkType>,
+    /// Node the record is limited to, None when it covers all nodes
+    pub node: Option<String>,
+}
+
+/// A proxied connection, with its final statistics once closed
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ConnectionRecord {
+    pub id: i64,
+    pub node: String,
+    pub client_addr: String,
+    pub target_addr: String,
+    /// `auto` until the first message revealed it, when the proxy auto-detects networks
+    pub network: String,
+    /// The peer connected to the node, rather than the node to the peer
+    pub inbound: bool,
+    /// Port an inbound peer connected from, its IP is the target address
+    pub peer_port: Option<u16>,
+    /// Address the proxy connected to the node from, for inbound connections
+    pub local_addr: Option<String>,
+    pub opened_at: DateTime<Utc>,
+    pub closed_at: Option<DateTime<Utc>>,
+    pub bytes_inbound: i64,
+    pub bytes_outbound: i64,
+    pub messages_inbound: i64,
+    pub messages_outbound: i64,
+    pub transport: Option<Transport>,
+}
+
+impl ConnectionRecord {
+    pub fn connection_id(&self) -> ConnectionId {
+        self.id as ConnectionId
+    }
+
+    pub fn stats(&self) -> ConnectionStats {
+        ConnectionStats {
+            bytes_inbound: self.bytes_inbound as u64,
+            bytes_outbound: self.bytes_outbound as u64,
+            messages_inbound: self.messages_inbound as u64,
+            messages_outbound: self.messages_outbound as u64,
+        }
+    }
+}
+
+/// A failed connect to the target a client requested
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct ConnectFailureRecord {
+    pub connection_id: i64,
+    pub node: String,
+    pub client_addr: String,
+    pub target_addr: String,
+    pub network: String,
+    pub error: ConnectError,
+    pub detail: String,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// A node that has been proxied, with the number of connections it made
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct NodeRecord {
+    pub node: String,
+    pub network: String,
+    pub connections: i64,
+    pub last_opened_at: DateTime<Utc>,
+}
+
+/// A single P2P message seen on a connection
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct MessageRecord {
+    pub id: i64,
+    pub connection_id: i64,
+    /// Label of the node of the connection
+    pub node: String,
+    pub direction: Direction,
+    pub command: String,
+    pub payload_len: i64,
+    pub description: String,
+    pub received_at: DateTime<Utc>,
+    pub forwarded_at: Option<DateTime<Utc>>,
+}
+
+impl MessageRecord {
+    /// Microseconds between receiving and forwarding the message
+    pub fn forward_latency_us(&self) -> Option<i64> {
+        self.forwarded_at
+            .and_then(|at| (at - self.received_at).num_microseconds())
+    }
+}
+
+/// A ping matched with its pong on a connection
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PingRttRecord {
+    pub id: i64,
+    pub connection_id: i64,
+    pub direction: Direction,
+    pub nonce: i64,
+    pub ping_at: DateTime<Utc>,
+    pub pong_at: DateTime<Utc>,
+    pub rtt_us: i64,
+}
+
+/// An event from a node's debug.log
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct LogEventRecord {
+    pub id: i64,
+    pub node: String,
+    pub connection_id: Option<i64>,
+    pub peer_id: Option<i64>,
+    pub address: Option<String>,
+    pub kind: LogEventKind,
+    pub height: Option<i64>,
+    pub message: String,
+    pub logged_at: DateTime<Utc>,
+}
+
+/// A block or transaction the node accepted
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AcceptanceRecord {
+    pub id: i64,
+    pub node: String,
+    pub kind: ItemKind,
+    pub hash: String,
+    pub wtxid: Option<String>,
+    pub accepted_at: DateTime<Utc>,
+}
+
+/// A peer's announcement of an item the node accepted
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct AnnouncementRecord {
+    pub acceptance_id: i64,
+    pub connection_id: i64,
+    pub via: AnnouncedVia,
+    pub announced_at: DateTime<Utc>,
+    /// Milliseconds from the node accepting the item to the peer announcing it, negative
+    /// when the peer announced it first
+    pub delay_ms: f64,
+}
+
+/// How early a connection's peer announced the items the node accepted, per kind of item
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PropagationStats {
+    pub connection_id: i64,
+    pub kind: ItemKind,
+    /// Items the peer announced that the node accepted
+    pub announced: i64,
+    /// Of those, the ones no other peer of the node announced earlier
+    pub first: i64,
+    pub avg_delay_ms: f64,
+}
+
+/// A peer announced a block or transaction, whether or not the node accepted it
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct InventoryAnnouncementRecord {
+    pub id: i64,
+    pub connection_id: i64,
+    pub node: String,
+    pub kind: ItemKind,
+    pub hash: String,
+    pub via: AnnouncedVia,
+    pub announced_at: DateTime<Utc>,
+}
+
+/// The node requested a block or transaction from a peer with getdata
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct InventoryRequestRecord {
+    pub id: i64,
+    pub connection_id: i64,
+    pub node: String,
+    pub kind: ItemKind,
+    pub hash: String,
+    /// None when the peer answered a request that wasn't seen with notfound
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When the peer answered with notfound
+    pub not_found_at: Option<DateTime<Utc>>,
+}
+
+/// What a connection's peer announced and was asked for
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct InventoryStats {
+    pub connection_id: i64,
+    pub announced: i64,
+    /// Announcements of items no other peer of the node announced earlier
+    pub first_announced: i64,
+    /// Items the node requested from the peer
+    pub requested: i64,
+    /// Items the peer answered with notfound, also ones whose request wasn't seen
+    pub not_found: i64,
+}
+
+/// Transaction relay of one peer, or summed over all peers of a node
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayStats {
+    /// Transactions the peer announced by inv
+    pub announced: u64,
+    /// Transactions the node requested with getdata
+    pub requested: u64,
+    /// Transactions the peer delivered with tx
+    pub delivered: u64,
+    /// Deliveries of transactions the node already got from this or another peer
+    pub duplicates: u64,
+    /// Deliveries whose fee is known
+    pub fee_known: u64,
+    /// Deliveries per bucket of [`TX_SIZE_BUCKETS`], plus the overflow bucket
+    pub size_buckets: [u64; TX_SIZE_BUCKETS.len() + 1],
+    /// Deliveries of known fee per bucket of [`FEE_RATE_BUCKETS`], plus the overflow bucket
+    pub fee_rate_buckets: [u64; FEE_RATE_BUCKETS.len() + 1],
+}
+
+impl RelayStats {
+    fn add(&mut self, other: &RelayStats) {
+        self.announced += other.announced;
+        self.requested += other.requested;
+        self.delivered += other.delivered;
+        self.duplicates += other.duplicates;
+        self.fee_known += other.fee_known;
+        for (sum, count) in self.size_buckets.iter_mut().zip(other.size_buckets) {
+            *sum += count;
+        }
+        for (sum, count) in self.fee_rate_buckets.iter_mut().zip(other.fee_rate_buckets) {
+            *sum += count;
+        }
+    }
+}
+
+/// Transaction relay of a connection's peer
+#[derive(Debug, Clone)]
+pub struct ConnectionRelayStats {
+    pub connection_id: ConnectionId,
+    /// Whether both sides sent wtxidrelay, so transactions are announced by wtxid
+    pub wtxid_relay: bool,
+    pub stats: RelayStats,
+}
+
+/// Transaction relay over all connections of a node, or of all nodes
+#[derive(Debug, Clone, Default)]
+pub struct RelaySummary {
+    pub connections: u64,
+    /// Connections relaying transactions by wtxid
+    pub wtxid_relay_connections: u64,
+    pub stats: RelayStats,
+}
+
+/// A fee filter a peer sent
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FeeFilterRecord {
+    pub connection_id: i64,
+    /// Fee rate in sat/kvB
+    pub fee_rate: i64,
+    pub received_at: DateTime<Utc>,
+}
+
+/// Filters for listing connections, all of which must match
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionFilter {
+    /// Only connections that are still open (or already closed)
+    pub active: Option<bool>,
+    pub node: Option<String>,
+    pub client_addr: Option<String>,
+    pub target_addr: Option<String>,
+    pub network: Option<String>,
+    pub inbound: Option<bool>,
+    pub transport: Option<Transport>,
+    pub opened_after: Option<DateTime<Utc>>,
+    pub opened_before: Option<DateTime<Utc>>,
+}
+
+/// Filters for listing handshakes, all of which must match
+#[derive(Debug, Clone, Default)]
+pub struct HandshakeFilter {
+    pub node: Option<String>,
+    pub outcome: Option<HandshakeOutcome>,
+    pub failure_reason: Option<HandshakeFailureReason>,
+    pub target_addr: Option<String>,
+}
+
+/// Filters for listing messages of a connection
+#[derive(Debug, Clone, Default)]
+pub struct MessageFilter {
+    pub direction: Option<Direction>,
+    pub command: Option<String>,
+}
+
+/// Filters for listing accepted items, all of which must match
+#[derive(Debug, Clone, Default)]
+pub struct AcceptanceFilter {
+    pub node: Option<String>,
+    pub kind: Option<ItemKind>,
+    /// Block hash, txid or wtxid
+    pub hash: Option<String>,
+}
+
+/// Filters for listing debug.log events, all of which must match
+#[derive(Debug, Clone, Default)]
+pub struct LogEventFilter {
+    pub node: Option<String>,
+    pub kind: Option<LogEventKind>,
+    pub peer_id: Option<i64>,
+}
+
+impl Store {
+    /// List connections, newest first, starting below the `before` id
+    pub async fn list_connections(
+        &self,
+        filter: &ConnectionFilter,
+        before: Option<ConnectionId>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ConnectionRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM connections WHERE 1 = 1");
+        if let Some(before) = before {
+            query.push(" AND id < ").push_bind(before as i64);
+        }
+        match filter.active {
+            Some(true) => {
+                query.push(" AND closed_at IS NULL");
+            }
+            Some(false) => {
+                query.push(" AND closed_at IS NOT NULL");
+            }
+            None => {}
+        }
+        if let Some(node) = &filter.node {
+            query.push(" AND node = ").push_bind(node);
+        }
+        if let Some(client_addr) = &filter.client_addr {
+            query.push(" AND client_addr = ").push_bind(client_addr);
+        }
+        if let Some(target_addr) = &filter.target_addr {
+            query.push(" AND target_addr = ").push_bind(target_addr);
+        }
+        if let Some(network) = &filter.network {
+            query.push(" AND network = ").push_bind(network);
+        }
+        if let Some(inbound) = filter.inbound {
+            query.push(" AND inbound = ").push_bind(inbound);
+        }
+        if let Some(transport) = filter.transport {
+            query.push(" AND transport = ").push_bind(transport);
+        }
+        if let Some(opened_after) = filter.opened_after {
+            query.push(" AND opened_at >= ").push_bind(opened_after);
+        }
+        if let Some(opened_before) = filter.opened_before {
+            query.push(" AND opened_at < ").push_bind(opened_before);
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(limit as i64);
+
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    pub async fn connection(&self, id: ConnectionId) -> anyhow::Result<Option<ConnectionRecord>> {
+        Ok(sqlx::query_as("SELECT * FROM connections WHERE id = ?")
+            .bind(id as i64)
+            .fetch_optional(&self.pool)
+            .await?)
+    }
+
+    /// List peers ordered by address, starting after the `after` address
+    ///
+    /// With a `node`, only the peers that node had connections with, as seen by that node.
+    pub async fn list_peers(
+        &self,
+        node: Option<&str>,
+        after: Option<&str>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<PeerRecord>> {
+        let mut query = peers_query(node);
+        if let Some(after) = after {
+            query.push(" AND peers.address > ").push_bind(after);
+        }
+        if node.is_some() {
+            query.push(" GROUP BY peers.address");
+        }
+        query
+            .push(" ORDER BY peers.address LIMIT ")
+            .push_bind(limit as i64);
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// All nodes that had connections proxied, ordered by label
+    pub async fn nodes(&self) -> anyhow::Result<Vec<NodeRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT node, network, COUNT(*) AS connections, MAX(opened_at) AS last_opened_at
+             FROM connections GROUP BY node, network ORDER BY node, network",
+        )
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// The given peers, as seen by `node` when given
+    pub async fn peers_by_address(
+        &self,
+        addresses: &[String],
+        node: Option<&str>,
+    ) -> anyhow::Result<Vec<PeerRecord>> {
+        let mut query = peers_query(node);
+        query.push(" AND peers.address IN ");
+        push_list(&mut query, addresses);
+        if node.is_some() {
+            query.push(" GROUP BY peers.address");
+        }
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// The newest `limit` connections of `node`, or of all nodes, to each of the
+    /// given peers below the `before` id
+    pub async fn connections_by_peer(
+        &self,
+        addresses: &[String],
+        node: Option<&str>,
+        before: Option<ConnectionId>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<ConnectionRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT * FROM (
+               SELECT *, ROW_NUMBER() OVER (PARTITION BY target_addr ORDER BY id DESC) AS n
+               FROM connections WHERE target_addr IN ",
+        );
+        push_list(&mut query, addresses);
+        if let Some(node) = node {
+            query.push(" AND node = ").push_bind(node.to_string());
+        }
+        if let Some(before) = before {
+            query.push(" AND id < ").push_bind(before as i64);
+        }
+        query
+            .push(") WHERE n <= ")
+            .push_bind(limit as i64)
+            .push(" ORDER BY id DESC");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Number of connections of `node`, or of all nodes, per transport to each of the given peers
+    pub async fn peer_transports(
+        &self,
+        addresses: &[String],
+        node: Option<&str>,
+    ) -> anyhow::Result<Vec<PeerTransportCount>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT target_addr AS address, transport, COUNT(*) AS connections
+             FROM connections WHERE target_addr IN ",
+        );
+        push_list(&mut query, addresses);
+        if let Some(node) = node {
+            query.push(" AND node = ").push_bind(node.to_string());
+        }
+        query.push(" GROUP BY target_addr, transport");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// List handshakes, newest first, starting below the `before` connection id
+    pub async fn list_handshakes(
+        &self,
+        filter: &HandshakeFilter,
+        before: Option<ConnectionId>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Handshake>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM handshakes WHERE 1 = 1");
+        if let Some(before) = before {
+            query.push(" AND connection_id < ").push_bind(before as i64);
+        }
+        if let Some(node) = &filter.node {
+            query.push(" AND node = ").push_bind(node);
+        }
+        if let Some(outcome) = filter.outcome {
+            query.push(" AND outcome = ").push_bind(outcome);
+        }
+        if let Some(failure_reason) = filter.failure_reason {
+            query
+                .push(" AND failure_reason = ")
+                .push_bind(failure_reason);
+        }
+        if let Some(target_addr) = &filter.target_addr {
+            query.push(" AND target_addr = ").push_bind(target_addr);
+        }
+        query
+            .push(" ORDER BY connection_id DESC LIMIT ")
+            .push_bind(limit as i64);
+
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    pub async fn handshakes(&self, ids: &[ConnectionId]) -> anyhow::Result<Vec<Handshake>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM handshakes WHERE connection_id IN ");
+        push_list(&mut query, &ids);
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// The last `limit` messages of each of the given connections, oldest first
+    pub async fn recent_messages(
+        &self,
+        ids: &[ConnectionId],
+        limit: usize,
+    ) -> anyhow::Result<Vec<MessageRecord>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT id, connection_id, node, direction, command, payload_len, description,
+               received_at, forwarded_at FROM (
+               SELECT messages.*, connections.node,
+                 ROW_NUMBER() OVER (PARTITION BY connection_id ORDER BY messages.id DESC) AS n
+               FROM messages JOIN connections ON connections.id = messages.connection_id
+               WHERE connection_id IN ",
+        );
+        push_list(&mut query, &ids);
+        query
+            .push(") WHERE n <= ")
+            .push_bind(limit as i64)
+            .push(" ORDER BY id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// The last `limit` ping round trips measured on each of the given connections, oldest first
+    pub async fn ping_rtts(
+        &self,
+        ids: &[ConnectionId],
+        limit: usize,
+    ) -> anyhow::Result<Vec<PingRttRecord>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT id, connection_id, direction, nonce, ping_at, pong_at, rtt_us FROM (
+               SELECT *, ROW_NUMBER() OVER (PARTITION BY connection_id ORDER BY id DESC) AS n
+               FROM ping_rtts WHERE connection_id IN ",
+        );
+        push_list(&mut query, &ids);
+        query
+            .push(") WHERE n <= ")
+            .push_bind(limit as i64)
+            .push(" ORDER BY id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Failed connects of the given connection ids
+    pub async fn connect_failures(
+        &self,
+        ids: &[ConnectionId],
+    ) -> anyhow::Result<Vec<ConnectFailureRecord>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM connect_failures WHERE connection_id IN ");
+        push_list(&mut query, &ids);
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Bitcoin Core's latest view of the given connections
+    pub async fn core_peers(&self, ids: &[ConnectionId]) -> anyhow::Result<Vec<CorePeer>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM core_peers WHERE connection_id IN ");
+        push_list(&mut query, &ids);
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Latest state of the given nodes, as far as it was collected
+    pub async fn node_status(&self, nodes: &[String]) -> anyhow::Result<Vec<NodeStatus>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM node_status WHERE node IN ");
+        push_list(&mut query, nodes);
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Connections of a node that closed at or after `since`, newest first
+    pub async fn connections_closed_since(
+        &self,
+        node: &str,
+        since: DateTime<Utc>,
+    ) -> anyhow::Result<Vec<ConnectionRecord>> {
+        Ok(sqlx::query_as(
+            "SELECT * FROM connections WHERE node = ? AND closed_at >= ? ORDER BY id DESC",
+        )
+        .bind(node)
+        .bind(since)
+        .fetch_all(&self.pool)
+        .await?)
+    }
+
+    /// The connection a node most likely had open to a peer at the given time
+    ///
+    /// Matches the latest connection opened up to `slack` after `at` that wasn't closed more
+    /// than `slack` before it, as log timestamps only have second precision. The node knows
+    /// inbound peers by the proxy's local address, outbound ones by their target address.
+    pub async fn connection_at(
+        &self,
+        node: &str,
+        peer_addr: &str,
+        at: DateTime<Utc>,
+        slack: chrono::Duration,
+    ) -> anyhow::Result<Option<ConnectionId>> {
+        let id: Option<i64> = sqlx::query_scalar(
+            "SELECT id FROM connections
+             WHERE node = ?1
+               AND ((inbound = 0 AND target_addr = ?2) OR (inbound = 1 AND local_addr = ?2))
+               AND opened_at <= ?3 AND (closed_at IS NULL OR closed_at >= ?4)
+             ORDER BY id DESC LIMIT 1",
+        )
+        .bind(node)
+        .bind(peer_addr)
+        .bind(at + slack)
+        .bind(at - slack)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(id.map(|id| id as ConnectionId))
+    }
+
+    /// The connection Bitcoin Core reported under a peer id that was open at the given time
+    ///
+    /// Peer ids start over when bitcoind restarts, so the connection's lifetime has to match too.
+    pub async fn connection_of_core_peer(
+        &self,
+        node: &str,
+        peer_id: i64,
+        at: DateTime<Utc>,
+        slack: chrono::Duration,
+    ) -> anyhow::Result<Option<ConnectionId>> {
+        let id: Option<i64> = sqlx::query_scalar(
+            "SELECT core_peers.connection_id FROM core_peers
+             JOIN connections ON connections.id = core_peers.connection_id
+             WHERE core_peers.node = ? AND core_peers.peer_id = ? AND connections.opened_at <= ?
+               AND (connections.closed_at IS NULL OR connections.closed_at >= ?)
+             ORDER BY core_peers.updated_at DESC LIMIT 1",
+        )
+        .bind(node)
+        .bind(peer_id)
+        .bind(at + slack)
+        .bind(at - slack)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(id.map(|id| id as ConnectionId))
+    }
+
+    /// List debug.log events, newest first, starting below the `before` id
+    pub async fn list_log_events(
+        &self,
+        filter: &LogEventFilter,
+        before: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<LogEventRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM log_events WHERE 1 = 1");
+        if let Some(before) = before {
+            query.push(" AND id < ").push_bind(before);
+        }
+        if let Some(node) = &filter.node {
+            query.push(" AND node = ").push_bind(node);
+        }
+        if let Some(kind) = filter.kind {
+            query.push(" AND kind = ").push_bind(kind);
+        }
+        if let Some(peer_id) = filter.peer_id {
+            query.push(" AND peer_id = ").push_bind(peer_id);
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(limit as i64);
+
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// All debug.log events matched to the given connections, oldest first
+    pub async fn log_events(&self, ids: &[ConnectionId]) -> anyhow::Result<Vec<LogEventRecord>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM log_events WHERE connection_id IN ");
+        push_list(&mut query, &ids);
+        query.push(" ORDER BY id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// List accepted blocks and transactions, newest first, starting below the `before` id
+    pub async fn list_acceptances(
+        &self,
+        filter: &AcceptanceFilter,
+        before: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<AcceptanceRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM acceptances WHERE 1 = 1");
+        if let Some(before) = before {
+            query.push(" AND id < ").push_bind(before);
+        }
+        if let Some(node) = &filter.node {
+            query.push(" AND node = ").push_bind(node);
+        }
+        if let Some(kind) = filter.kind {
+            query.push(" AND kind = ").push_bind(kind);
+        }
+        if let Some(hash) = &filter.hash {
+            query
+                .push(" AND (hash = ")
+                .push_bind(hash)
+                .push(" OR wtxid = ")
+                .push_bind(hash)
+                .push(")");
+        }
+        query
+            .push(" ORDER BY id DESC LIMIT ")
+            .push_bind(limit as i64);
+
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Announcements of the given accepted items by the node's peers, earliest first
+    pub async fn acceptance_announcements(
+        &self,
+        acceptance_ids: &[i64],
+    ) -> anyhow::Result<Vec<AnnouncementRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT acceptances.id AS acceptance_id, announcements.connection_id, announcements.via,
+               announcements.announced_at,
+               (julianday(announcements.announced_at) - julianday(acceptances.accepted_at))
+                 * 86400000.0 AS delay_ms
+             FROM acceptances JOIN announcements ON announcements.node = acceptances.node
+               AND announcements.kind = acceptances.kind
+               AND (announcements.hash = acceptances.hash OR announcements.hash = acceptances.wtxid)
+             WHERE acceptances.id IN ",
+        );
+        push_list(&mut query, acceptance_ids);
+        query.push(" ORDER BY announcements.announced_at, announcements.id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Propagation statistics of the given connections, over the items the node accepted
+    pub async fn propagation_stats(
+        &self,
+        ids: &[ConnectionId],
+    ) -> anyhow::Result<Vec<PropagationStats>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT announcements.connection_id, announcements.kind, COUNT(*) AS announced,
+               SUM(announcements.announced_at = (
+                 SELECT MIN(earliest.announced_at) FROM announcements AS earliest
+                 WHERE earliest.node = acceptances.node
+                   AND (earliest.hash = acceptances.hash OR earliest.hash = acceptances.wtxid)
+               )) AS first,
+               AVG(julianday(announcements.announced_at) - julianday(acceptances.accepted_at))
+                 * 86400000.0 AS avg_delay_ms
+             FROM announcements JOIN acceptances ON acceptances.node = announcements.node
+               AND acceptances.kind = announcements.kind
+               AND (acceptances.hash = announcements.hash OR acceptances.wtxid = announcements.hash)
+             WHERE announcements.connection_id IN ",
+        );
+        push_list(&mut query, &ids);
+        query.push(
+            " GROUP BY announcements.connection_id, announcements.kind ORDER BY announcements.kind",
+        );
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Announcements of an item by any peer, earliest first
+    ///
+    /// Transactions are matched by both their txid and wtxid once the node accepted them.
+    pub async fn item_announcements(
+        &self,
+        node: Option<&str>,
+        hash: &str,
+    ) -> anyhow::Result<Vec<InventoryAnnouncementRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT * FROM announcements WHERE hash IN ");
+        push_item_hashes(&mut query, hash);
+        if let Some(node) = node {
+            query.push(" AND node = ").push_bind(node.to_string());
+        }
+        query.push(" ORDER BY announced_at, id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Requests of an item from any peer, earliest first
+    pub async fn item_requests(
+        &self,
+        node: Option<&str>,
+        hash: &str,
+    ) -> anyhow::Result<Vec<InventoryRequestRecord>> {
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM inventory_requests WHERE hash IN ");
+        push_item_hashes(&mut query, hash);
+        if let Some(node) = node {
+            query.push(" AND node = ").push_bind(node.to_string());
+        }
+        query.push(" ORDER BY COALESCE(requested_at, not_found_at), id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Inventory statistics of the given connections
+    pub async fn inventory_stats(
+        &self,
+        ids: &[ConnectionId],
+    ) -> anyhow::Result<Vec<InventoryStats>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT connections.id AS connection_id,
+               (SELECT COUNT(*) FROM announcements
+                WHERE announcements.connection_id = connections.id) AS announced,
+               (SELECT COUNT(*) FROM announcements
+                WHERE announcements.connection_id = connections.id AND NOT EXISTS (
+                  SELECT 1 FROM announcements AS earlier
+                  WHERE earlier.node = announcements.node AND earlier.hash = announcements.hash
+                    AND earlier.announced_at < announcements.announced_at
+                )) AS first_announced,
+               (SELECT COUNT(requested_at) FROM inventory_requests
+                WHERE inventory_requests.connection_id = connections.id) AS requested,
+               (SELECT COUNT(not_found_at) FROM inventory_requests
+                WHERE inventory_requests.connection_id = connections.id) AS not_found
+             FROM connections WHERE connections.id IN ",
+        );
+        push_list(&mut query, &ids);
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Transaction relay statistics of the given connections
+    pub async fn relay_stats(
+        &self,
+        ids: &[ConnectionId],
+    ) -> anyhow::Result<Vec<ConnectionRelayStats>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        self.relay_stats_of(&ConnectionSet::Ids(&ids)).await
+    }
+
+    /// Transaction relay statistics summed over the connections of a node, or of all nodes
+    pub async fn relay_summary(&self, node: Option<&str>) -> anyhow::Result<RelaySummary> {
+        let mut summary = RelaySummary::default();
+        for connection in self.relay_stats_of(&ConnectionSet::Node(node)).await? {
+            summary.connections += 1;
+            summary.wtxid_relay_connections += connection.wtxid_relay as u64;
+            summary.stats.add(&connection.stats);
+        }
+        Ok(summary)
+    }
+
+    async fn relay_stats_of(
+        &self,
+        connections: &ConnectionSet<'_>,
+    ) -> anyhow::Result<Vec<ConnectionRelayStats>> {
+        let mut stats: HashMap<i64, RelayStats> = HashMap::new();
+
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT connection_id, COUNT(*) FROM announcements WHERE kind = 'tx' AND connection_id IN ",
+        );
+        connections.push(&mut query);
+        query.push(" GROUP BY connection_id");
+        for (connection_id, announced) in query
+            .build_query_as::<(i64, i64)>()
+            .fetch_all(&self.pool)
+            .await?
+        {
+            stats.entry(connection_id).or_default().announced = announced as u64;
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT connection_id, COUNT(requested_at) FROM inventory_requests
+             WHERE kind = 'tx' AND connection_id IN ",
+        );
+        connections.push(&mut query);
+        query.push(" GROUP BY connection_id");
+        for (connection_id, requested) in query
+            .build_query_as::<(i64, i64)>()
+            .fetch_all(&self.pool)
+            .await?
+        {
+            stats.entry(connection_id).or_default().requested = requested as u64;
+        }
+
+        // Every delivery of the transactions is numbered, so earlier ones on other
+        // connections count too
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT connection_id, COUNT(*), SUM(delivery > 1), COUNT(fee) FROM (
+               SELECT connection_id, fee,
+                 ROW_NUMBER() OVER (PARTITION BY node, txid ORDER BY id) AS delivery
+               FROM tx_deliveries
+               WHERE (node, txid) IN (SELECT node, txid FROM tx_deliveries WHERE connection_id IN ",
+        );
+        connections.push(&mut query);
+        query.push(")) WHERE connection_id IN ");
+        connections.push(&mut query);
+        query.push(" GROUP BY connection_id");
+        for (connection_id, delivered, duplicates, fee_known) in query
+            .build_query_as::<(i64, i64, i64, i64)>()
+            .fetch_all(&self.pool)
+            .await?
+        {
+            let stats = stats.entry(connection_id).or_default();
+            stats.delivered = delivered as u64;
+            stats.duplicates = duplicates as u64;
+            stats.fee_known = fee_known as u64;
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new("SELECT connection_id, ");
+        push_bucket(
+            &mut query,
+            "vsize",
+            &TX_SIZE_BUCKETS.map(|bound| bound as f64),
+        );
+        query.push(", ");
+        push_bucket(&mut query, "fee_rate", &FEE_RATE_BUCKETS);
+        query.push(", COUNT(*) FROM tx_deliveries WHERE connection_id IN ");
+        connections.push(&mut query);
+        query.push(" GROUP BY 1, 2, 3");
+        for (connection_id, size_bucket, fee_rate_bucket, count) in query
+            .build_query_as::<(i64, i64, Option<i64>, i64)>()
+            .fetch_all(&self.pool)
+            .await?
+        {
+            let stats = stats.entry(connection_id).or_default();
+            stats.size_buckets[size_bucket as usize] += count as u64;
+            if let Some(bucket) = fee_rate_bucket {
+                stats.fee_rate_buckets[bucket as usize] += count as u64;
+            }
+        }
+
+        // Connections without any relay still count, e.g. block-relay-only ones
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT connections.id,
+               COALESCE(handshakes.local_wtxidrelay AND handshakes.remote_wtxidrelay, 0)
+             FROM connections LEFT JOIN handshakes ON handshakes.connection_id = connections.id
+             WHERE connections.id IN ",
+        );
+        connections.push(&mut query);
+        query.push(" ORDER BY connections.id");
+        Ok(query
+            .build_query_as::<(i64, bool)>()
+            .fetch_all(&self.pool)
+            .await?
+            .into_iter()
+            .map(|(connection_id, wtxid_relay)| ConnectionRelayStats {
+                connection_id: connection_id as ConnectionId,
+                wtxid_relay,
+                stats: stats.remove(&connection_id).unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// Fee filters the peers of the given connections sent, oldest first
+    pub async fn fee_filters(&self, ids: &[ConnectionId]) -> anyhow::Result<Vec<FeeFilterRecord>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT connection_id, fee_rate, received_at FROM fee_filters WHERE connection_id IN ",
+        );
+        push_list(&mut query, &ids);
+        query.push(" ORDER BY connection_id, received_at, id");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Parse health of both directions of the given connections
+    pub async fn parse_health(&self, ids: &[ConnectionId]) -> anyhow::Result<Vec<ParseHealth>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM parse_health WHERE connection_id IN ");
+        push_list(&mut query, &ids);
+        query.push(" ORDER BY connection_id, direction");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// Final packet statistics of both directions of the given connections
+    pub async fn packet_stats(&self, ids: &[ConnectionId]) -> anyhow::Result<Vec<PacketStats>> {
+        let ids: Vec<i64> = ids.iter().map(|id| *id as i64).collect();
+        let mut query =
+            QueryBuilder::<Sqlite>::new("SELECT * FROM packet_stats WHERE connection_id IN ");
+        push_list(&mut query, &ids);
+        query.push(" ORDER BY connection_id, direction");
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+
+    /// List messages of a connection in order, starting after the `after` id
+    pub async fn list_messages(
+        &self,
+        connection_id: ConnectionId,
+        filter: &MessageFilter,
+        after: Option<i64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<MessageRecord>> {
+        let mut query = QueryBuilder::<Sqlite>::new(
+            "SELECT messages.*, connections.node FROM messages
+             JOIN connections ON connections.id = messages.connection_id
+             WHERE connection_id = ",
+        );
+        query.push_bind(connection_id as i64);
+        if let Some(after) = after {
+            query.push(" AND messages.id > ").push_bind(after);
+        }
+        if let Some(direction) = filter.direction {
+            query.push(" AND direction = ").push_bind(direction);
+        }
+        if let Some(command) = &filter.command {
+            query.push(" AND command = ").push_bind(command);
+        }
+        query
+            .push(" ORDER BY messages.id LIMIT ")
+            .push_bind(limit as i64);
+
+        Ok(query.build_query_as().fetch_all(&self.pool).await?)
+    }
+}
+
+/// Connections to gather statistics over
+enum ConnectionSet<'a> {
+    Ids(&'a [i64]),
+    /// All connections of a node, or of all nodes
+    Node(Option<&'a str>),
+}
+
+impl<'a> ConnectionSet<'a> {
+    /// Push the connection ids, to follow an IN
+    fn push(&self, query: &mut QueryBuilder<'a, Sqlite>) {
+        match self {
+            ConnectionSet::Ids(ids) => push_list(query, ids),
+            ConnectionSet::Node(Some(node)) => {
+                query
+                    .push("(SELECT id FROM connections WHERE node = ")
+                    .push_bind(node.to_string())
+                    .push(")");
+            }
+            ConnectionSet::Node(None) => {
+                query.push("(SELECT id FROM connections)");
+            }
+        }
+    }
+}
+
+/// Push the index of the bucket a column's value falls into, NULL for NULL values
+fn push_bucket(query: &mut QueryBuilder<'_, Sqlite>, column: &str, bounds: &[f64]) {
+    query.push(format!("CASE WHEN {} IS NULL THEN NULL", column));
+    for (index, bound) in bounds.iter().enumerate() {
+        query.push(format!(" WHEN {} <= {} THEN {}", column, bound, index));
+    }
+    query.push(format!(" ELSE {} END", bounds.len()));
+}
+
+/// Push a list of the hash and, for accepted transactions, its txid or wtxid counterpart
+fn push_item_hashes(query: &mut QueryBuilder<'_, Sqlite>, hash: &str) {
+    query
+        .push("(SELECT ")
+        .push_bind(hash.to_string())
+        .push(" UNION SELECT wtxid FROM acceptances WHERE wtxid IS NOT NULL AND hash = ")
+        .push_bind(hash.to_string())
+        .push(" UNION SELECT hash FROM acceptances WHERE wtxid = ")
+        .push_bind(hash.to_string())
+        .push(")");
+}
+
+/// Push a parenthesized list of bound values, e.g. for `IN` clauses
+fn push_list<'a, T>(query: &mut QueryBuilder<'a, Sqlite>, values: &'a [T])
+where
+    T: sqlx::Encode<'a, Sqlite> + sqlx::Type<Sqlite> + Send + Sync + Clone + 'a,
+{
+    query.push("(");
+    let mut separated = query.separated(", ");
+    for value in values {
+        separated.push_bind(value.clone());
+    }
+    query.push(")");
+}
+
+/// Select peers, to be followed by `AND` conditions
+///
+/// The peers table spans all nodes, so with a `node` the times a peer was seen
+/// come from that node's connections instead, and the query has to be grouped
+/// by address.
+fn peers_query<'a>(node: Option<&str>) -> QueryBuilder<'a, Sqlite> {
+    match node {
+        None => QueryBuilder::new("SELECT *, NULL AS node FROM peers WHERE 1 = 1"),
+        Some(node) => {
+            let mut query = QueryBuilder::new(
+                "SELECT peers.address, MIN(connections.opened_at) AS first_seen,
+                   MAX(COALESCE(connections.closed_at, connections.opened_at)) AS last_seen,
+                   peers.network_type, connections.node
+                 FROM peers JOIN connections ON connections.target_addr = peers.address
+                 WHERE connections.node = ",
+            );
+            query.push_bind(node.to_string());
+            query
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use chrono::{DateTime, Utc};
+use sqlx::sqlite::SqliteRow;
+use sqlx::{FromRow, Row};
+
+use crate::event::{ConnectionId, Direction};
+
+/// Upper bounds of the packet size buckets in bytes, larger packets fall into a final bucket
+pub const PACKET_SIZE_BUCKETS: [u64; 4] = [64, 256, 1024, 4096];
+
+/// P2P transport spoken on a connection, decided from the client's first bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, sqlx::Type)]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum Transport {
+    /// Plaintext messages starting with the network magic
+    V1,
+    /// BIP324 encrypted transport, messages can't be parsed
+    V2Encrypted,
+}
+
+impl Transport {
+    /// Snake case name, as stored and used in metric labels
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Transport::V1 => "v1",
+            Transport::V2Encrypted => "v2_encrypted",
+        }
+    }
+}
+
+/// Sizes and timing of the reads from one side of a connection
+///
+/// A read is what the proxy got from a single `read` call, which may span
+/// several TCP segments when data arrives faster than it is forwarded.
+#[derive(Debug, Clone)]
+pub struct PacketStats {
+    pub connection_id: ConnectionId,
+    pub direction: Direction,
+    pub packets: u64,
+    pub bytes: u64,
+    pub first_at: Option<DateTime<Utc>>,
+    pub last_at: Option<DateTime<Utc>>,
+    /// Packet counts per bucket of [`PACKET_SIZE_BUCKETS`], plus the overflow bucket
+    pub size_buckets: [u64; PACKET_SIZE_BUCKETS.len() + 1],
+}
+
+impl PacketStats {
+    pub fn new(connection_id: ConnectionId, direction: Direction) -> Self {
+        Self {
+            connection_id,
+            direction,
+            packets: 0,
+            bytes: 0,
+            first_at: None,
+            last_at: None,
+            size_buckets: Default::default(),
+        }
+    }
+
+    /// Index of the size bucket a packet of `bytes` falls into
+    pub fn bucket(bytes: u64) -> usize {
+        PACKET_SIZE_BUCKETS
+            .iter()
+            .position(|bound| bytes <= *bound)
+            .unwrap_or(PACKET_SIZE_BUCKETS.len())
+    }
+}
+
+impl FromRow<'_, SqliteRow> for PacketStats {
+    fn from_row(row: &SqliteRow) -> sqlx::Result<Self> {
+        let count = |name: &str| row.try_get::<i64, _>(name).map(|n| n as u64);
+        Ok(Self {
+            connection_id: row.try_get::<i64, _>("connection_id")? as ConnectionId,
+            direction: row.try_get("direction")?,
+            packets: count("packets")?,
+            bytes: count("bytes")?,
+            first_at: row.try_get("first_at")?,
+            last_at: row.try_get("last_at")?,
+            size_buckets: [
+                count("size_le_64")?,
+                count("size_le_256")?,
+                count("size_le_1024")?,
+                count("size_le_4096")?,
+                count("size_gt_4096")?,
+            ],
+        })
+    }
+}
+
+/// Number of connections to a peer per transport
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct PeerTransportCount {
+    pub address: String,
+    /// None for connections that closed before the client sent enough to tell
+    pub transport: Option<Transport>,
+    pub connections: i64,
+}
@@ -8,18 +8,133 @@ use serde::{Deserialize, Serialize};
 pub struct Config {
     #[serde(default)]
     pub server: server::ServerConfig,
+    /// One proxy per node, e.g. mainnet, signet and regtest nodes side by side
+    #[serde(default = "default_proxies")]
+    pub proxies: Vec<proxy::ProxyConfig>,
+    /// The single proxy of configs from before each node got its own, still
+    /// accepted in place of `proxies`
+    #[serde(default, skip_serializing)]
+    proxy: Option<proxy::ProxyConfig>,
+    /// Bitcoin Core RPC interfaces to poll, by the node label of their proxy
     #[serde(default)]
-    pub proxy: proxy::ProxyConfig,
+    pub rpc: Vec<rpc::RpcConfig>,
+    /// debug.log files to ingest, by the node label of their proxy
+    #[serde(default)]
+    pub logs: Vec<logs::LogConfig>,
+    /// ZMQ endpoints to listen to, by the node label of their proxy
+    #[serde(default)]
+    pub zmq: Vec<zmq::ZmqConfig>,
+    #[serde(default)]
+    pub storage: app::StorageConfig,
+    /// Seconds open connections get to close on shutdown before they are closed
+    #[serde(default = "default_shutdown_grace_secs")]
+    pub shutdown_grace_secs: u64,
 }
 
 impl Config {
     pub fn init(path: impl AsRef<Path>) -> anyhow::Result<Self> {
         let config_file = std::fs::read_to_string(&path)
             .context(format!("Couldn't read config file {:?}", path.as_ref()))?;
+        Self::parse(&config_file)
+    }
+
+    fn parse(config_file: &str) -> anyhow::Result<Self> {
+        let mut value: serde_yaml::Value =
+            serde_yaml::from_str(config_file).context("Couldn't parse config file")?;
+        let has_proxies = value.get("proxies").is_some();
+        if let Some(proxy) = value.get_mut("proxy") {
+            migrate_port(proxy)?;
+        }
+        if let Some(proxies) = value.get_mut("proxies").and_then(|p| p.as_sequence_mut()) {
+            for proxy in proxies {
+                migrate_port(proxy)?;
+            }
+        }
+        let mut config: Config =
+            serde_yaml::from_value(value).context("Couldn't parse config file")?;
 
-        let config: Config =
-            serde_yaml::from_str(&config_file).context("Couldn't parse config file")?;
+        if let Some(proxy) = config.proxy.take() {
+            anyhow::ensure!(
+                !has_proxies,
+                "Config file sets both proxy and proxies, move the proxy into proxies"
+            );
+            config.proxies = vec![proxy];
+        }
 
         Ok(config)
     }
 }
+
+/// Replace the `port` of a proxy from before it got `bind` with the address it
+/// listened on, all interfaces
+fn migrate_port(proxy: &mut serde_yaml::Value) -> anyhow::Result<()> {
+    let Some(proxy) = proxy.as_mapping_mut() else {
+        return Ok(());
+    };
+    let Some(port) = proxy.remove("port") else {
+        return Ok(());
+    };
+    anyhow::ensure!(
+        !proxy.contains_key("bind"),
+        "Config file sets both port and bind of a proxy, remove port"
+    );
+    let port: u16 = serde_yaml::from_value(port)
+        .context("Couldn't parse config file, the proxy port isn't a port number")?;
+    proxy.insert("bind".into(), format!("0.0.0.0:{}", port).into());
+    Ok(())
+}
+
+fn default_proxies() -> Vec<proxy::ProxyConfig> {
+    vec![proxy::ProxyConfig::default()]
+}
+
+fn default_shutdown_grace_secs() -> u64 {
+    10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_config_from_before_bind() {
+        // nodescope.yml as shipped before proxies were kept per node
+        let config = Config::parse(
+            "proxy:
+  port: 6788
+  network: mainnet
+
+server:
+  port: 6789
+",
+        )
+        .unwrap();
+        assert_eq!(config.proxies.len(), 1);
+        assert_eq!(config.proxies[0].bind, "0.0.0.0:6788".parse().unwrap());
+        assert_eq!(config.proxies[0].network.name(), "mainnet");
+        assert_eq!(config.server.port, 6789);
+
+        let config = Config::parse(
+            "proxies:
+  - node: signet
+    port: 38788
+",
+        )
+        .unwrap();
+        assert_eq!(config.proxies[0].bind, "0.0.0.0:38788".parse().unwrap());
+
+        let both = Config::parse(
+            "proxies:
+  - port: 6788
+    bind: 127.0.0.1:6788
+",
+        );
+        assert!(both.unwrap_err().to_string().contains("both port and bind"));
+    }
+
+    #[test]
+    fn test_parses_example_config() {
+        let config = Config::parse(include_str!("../../nodescope.yml")).unwrap();
+        assert_eq!(config.proxies[0].node, "mainnet");
+    }
+}
@@ -1,9 +1,11 @@
 mod config;
 
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Context;
 use clap::{Parser, Subcommand};
+use tracing::{info, warn};
 
 use crate::config::Config;
 
@@ -25,6 +27,12 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Run,
+    /// Ingest the configured debug.log files from their start, then exit
+    Backfill {
+        /// Only the log of this node
+        #[clap(long)]
+        node: Option<String>,
+    },
 }
 
 pub async fn run() -> anyhow::Result<()> {
@@ -41,26 +49,115 @@ pub async fn run() -> anyhow::Result<()> {
             let config = Config::init(cli.config)?;
             run_app(config).await?;
         }
+        Commands::Backfill { node } => {
+            let config = Config::init(cli.config)?;
+            backfill(config, node).await?;
+        }
     }
 
     Ok(())
 }
 
 async fn run_app(config: Config) -> anyhow::Result<()> {
-    let app = app::NodeScopeApp::new();
+    let app = app::NodeScopeApp::init(config.storage.clone())
+        .await
+        .context("storage error")?;
+    tokio::spawn(shutdown_on_signal(app.shutdown().clone()));
 
-    tokio::try_join!(
+    // A subsystem that fails makes the others shut down too
+    let (proxy, rpc, logs, zmq, server) = tokio::join!(
+        async {
+            let result = proxy::run(config.proxies.clone(), app.clone())
+                .await
+                .context("proxy server error");
+            app.shutdown().drain();
+            result
+        },
+        async {
+            let result = rpc::run(config.rpc.clone(), app.clone())
+                .await
+                .context("RPC collector error");
+            app.shutdown().drain();
+            result
+        },
+        async {
+            let result = logs::run(config.logs.clone(), app.clone())
+                .await
+                .context("log ingestion error");
+            app.shutdown().drain();
+            result
+        },
         async {
-            proxy::run(config.proxy.clone(), app.clone())
+            let result = zmq::run(config.zmq.clone(), app.clone())
                 .await
-                .context("proxy server error")
+                .context("ZMQ listener error");
+            app.shutdown().drain();
+            result
         },
         async {
-            server::run(config.server.clone(), app.clone())
+            let result = server::run(config.server.clone(), app.clone())
                 .await
-                .context("server error")
+                .context("server error");
+            app.shutdown().drain();
+            result
         }
-    )?;
+    );
 
-    Ok(())
+    app.drain_connections(Duration::from_secs(config.shutdown_grace_secs))
+        .await;
+    app.close().await;
+    info!("Shutdown complete");
+
+    proxy.and(rpc).and(logs).and(zmq).and(server)
+}
+
+/// Ingest debug.log files once, matching them to the connections stored so far
+async fn backfill(config: Config, node: Option<String>) -> anyhow::Result<()> {
+    let configs: Vec<_> = config
+        .logs
+        .into_iter()
+        .filter(|log| node.as_ref().is_none_or(|node| log.node == *node))
+        .collect();
+    if configs.is_empty() {
+        anyhow::bail!("No debug.log configured to backfill");
+    }
+
+    let app = app::NodeScopeApp::init(config.storage)
+        .await
+        .context("storage error")?;
+    let result = logs::backfill(configs, app.clone()).await;
+    app.close().await;
+    result
+}
+
+/// Drain on the first SIGINT or SIGTERM, close open connections right away on the second
+async fn shutdown_on_signal(shutdown: app::Shutdown) {
+    if let Err(e) = signal().await {
+        warn!("Couldn't listen for shutdown signals: {}", e);
+        return;
+    }
+    info!("Shutting down, signal again to close open connections right away");
+    shutdown.drain();
+
+    if signal().await.is_ok() {
+        shutdown.close();
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM
+async fn signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await
+    }
 }
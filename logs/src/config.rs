@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Ingestion of a node's debug.log
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// Label of the node, the same as for its proxy
+    pub node: String,
+
+    /// Path to the node's debug.log, followed across rotation
+    pub path: PathBuf,
+
+    /// Milliseconds between checks for new lines
+    #[serde(default = "default_poll_interval_ms")]
+    pub poll_interval_ms: u64,
+}
+
+fn default_poll_interval_ms() -> u64 {
+    1000
+}
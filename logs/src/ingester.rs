@@ -0,0 +1,228 @@
+use std::collections::HashMap;
+
+use app::{ConnectionId, LogEvent, LogEventKind, NodeScopeApp};
+use chrono::{DateTime, Utc};
+
+use crate::parser::{LogLine, ParsedEvent, parse_line};
+
+/// How far log timestamps, which only have second precision, may be off from the proxy's
+const TIMESTAMP_SLACK: chrono::Duration = chrono::Duration::seconds(2);
+
+/// Parses lines of a node's debug.log, matches them to proxied connections and records them
+pub struct Ingester {
+    node: String,
+    app: NodeScopeApp,
+    /// Connections of the peer ids seen so far, until the peer disconnects
+    peers: HashMap<i64, ConnectionId>,
+}
+
+impl Ingester {
+    pub fn new(node: String, app: NodeScopeApp) -> Self {
+        Self {
+            node,
+            app,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Record the events among the lines, returning how many there were
+    pub async fn ingest(&mut self, lines: &[String]) -> anyhow::Result<usize> {
+        let mut events = Vec::new();
+        for line in lines {
+            match parse_line(line) {
+                Some(LogLine::Event(event)) => events.push(self.correlate(event).await?),
+                Some(LogLine::Started) => self.peers.clear(),
+                None => {}
+            }
+        }
+        self.app.store().insert_log_events(&events).await?;
+        Ok(events.len())
+    }
+
+    /// Match an event to a connection by the peer's address, or else by its peer id
+    ///
+    /// Addresses are only logged with `-logips`, peer ids are learnt from them or from
+    /// Bitcoin Core's RPC when it is polled.
+    async fn correlate(&mut self, event: ParsedEvent) -> anyhow::Result<LogEvent> {
+        let logged_at = event.logged_at.unwrap_or_else(Utc::now);
+
+        let mut connection_id = None;
+        if let Some(address) = &event.address {
+            connection_id = self.connection_to(address, logged_at).await?;
+        }
+        if let Some(peer_id) = event.peer_id {
+            match connection_id {
+                Some(id) => {
+                    self.peers.insert(peer_id, id);
+                }
+                None => {
+                    connection_id = match self.peers.get(&peer_id) {
+                        Some(id) => Some(*id),
+                        None => {
+                            self.app
+                                .store()
+                                .connection_of_core_peer(
+                                    &self.node,
+                                    peer_id,
+                                    logged_at,
+                                    TIMESTAMP_SLACK,
+                                )
+                                .await?
+                        }
+                    };
+                }
+            }
+            if event.kind == LogEventKind::PeerDisconnected {
+                self.peers.remove(&peer_id);
+            }
+        }
+
+        Ok(LogEvent {
+            node: self.node.clone(),
+            connection_id,
+            peer_id: event.peer_id,
+            address: event.address,
+            kind: event.kind,
+            height: event.height,
+            message: event.message,
+            logged_at,
+        })
+    }
+
+    /// The connection of a peer address that was open at the given time
+    ///
+    /// Bitcoin Core logs inbound peers forwarded by the transparent proxy with the
+    /// address the proxy connected from. Open connections may not be stored yet, so
+    /// they are looked up first.
+    async fn connection_to(
+        &self,
+        address: &str,
+        at: DateTime<Utc>,
+    ) -> anyhow::Result<Option<ConnectionId>> {
+        let live = self
+            .app
+            .connections()
+            .list()
+            .into_iter()
+            .filter(|connection| {
+                let peer_addr = if connection.inbound {
+                    connection.local_addr.as_deref()
+                } else {
+                    Some(connection.target_addr.as_str())
+                };
+                connection.node == self.node
+                    && peer_addr == Some(address)
+                    && connection.opened_at <= at + TIMESTAMP_SLACK
+            })
+            .max_by_key(|connection| connection.opened_at);
+        if let Some(connection) = live {
+            return Ok(Some(connection.connection_id));
+        }
+        self.app
+            .store()
+            .connection_at(&self.node, address, at, TIMESTAMP_SLACK)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use app::{
+        ConnectionClosed, ConnectionOpened, ConnectionStats, LiveConnection, LiveStats,
+        LogEventFilter, NetworkType, P2pEvent, StorageConfig,
+    };
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_correlates_by_address_and_peer_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+        let _registration = app.connections().register(LiveConnection {
+            connection_id: 9,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:50001".to_string(),
+            target_addr: "203.0.113.5:8333".to_string(),
+            local_addr: None,
+            network: None,
+            inbound: false,
+            opened_at: "2024-05-01T12:00:00Z".parse().unwrap(),
+            stats: Arc::new(LiveStats::default()),
+        });
+
+        // Inbound peers are logged with the address the proxy connected to the node from
+        let _inbound = app.connections().register(LiveConnection {
+            connection_id: 10,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:8334".to_string(),
+            target_addr: "198.51.100.7".to_string(),
+            local_addr: Some("127.0.0.1:41000".to_string()),
+            network: None,
+            inbound: true,
+            opened_at: "2024-05-01T11:59:30Z".parse().unwrap(),
+            stats: Arc::new(LiveStats::default()),
+        });
+        app.publish(P2pEvent::ConnectionOpened(ConnectionOpened {
+            connection_id: 11,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:8334".to_string(),
+            target_addr: "198.51.100.8".to_string(),
+            peer_port: Some(52000),
+            local_addr: Some("127.0.0.1:41001".to_string()),
+            network: None,
+            network_type: NetworkType::Ipv4,
+            inbound: true,
+            timestamp: "2024-05-01T11:59:00Z".parse().unwrap(),
+        }));
+        app.publish(P2pEvent::ConnectionClosed(ConnectionClosed {
+            connection_id: 11,
+            node: "mainnet".to_string(),
+            stats: ConnectionStats::default(),
+            timestamp: "2024-05-01T12:00:10Z".parse().unwrap(),
+        }));
+        while app
+            .store()
+            .connection(11)
+            .await
+            .unwrap()
+            .is_none_or(|connection| connection.closed_at.is_none())
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let lines = [
+            "2024-05-01T12:00:00Z [net] receive version message: /Satoshi:27.0.0/: version 70016, \
+             blocks=850000, us=[::]:0, txrelay=1, peer=4, peeraddr=127.0.0.1:41000",
+            "2024-05-01T12:00:00Z [net] receive version message: /Satoshi:27.0.0/: version 70016, \
+             blocks=850000, us=[::]:0, txrelay=1, peer=5, peeraddr=127.0.0.1:41001",
+            "2024-05-01T12:00:00Z [net] Added connection to 203.0.113.5:8333 peer=3",
+            "2024-05-01T12:00:01Z [net] Misbehaving: peer=3: invalid header received",
+            "2024-05-01T12:00:02Z [net] disconnecting peer=3",
+            "2024-05-01T12:00:03Z UpdateTip: new best=0000 height=850001",
+            // bitcoind restarted, peer id 3 is someone else now
+            "2024-05-01T12:05:00Z Bitcoin Core version v27.0.0 (release build)",
+            "2024-05-01T12:05:01Z [net] Misbehaving: peer=3: invalid header received",
+        ]
+        .map(str::to_string);
+        let mut ingester = Ingester::new("mainnet".to_string(), app.clone());
+        assert_eq!(ingester.ingest(&lines).await.unwrap(), 7);
+
+        let mut events = app
+            .store()
+            .list_log_events(&LogEventFilter::default(), None, 10)
+            .await
+            .unwrap();
+        events.reverse();
+        let connections: Vec<_> = events.iter().map(|event| event.connection_id).collect();
+        assert_eq!(
+            connections,
+            [Some(10), Some(11), Some(9), Some(9), Some(9), None, None]
+        );
+        assert_eq!(events[5].height, Some(850_001));
+        assert_eq!(app.store().log_events(&[9]).await.unwrap().len(), 3);
+    }
+}
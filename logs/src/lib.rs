@@ -0,0 +1,100 @@
+mod config;
+mod ingester;
+mod parser;
+mod tailer;
+
+pub use config::LogConfig;
+pub use ingester::Ingester;
+pub use parser::{LogLine, ParsedEvent, parse_line};
+pub use tailer::LogTailer;
+
+use std::io;
+use std::time::Duration;
+
+use anyhow::Context;
+use app::{NodeScopeApp, ShutdownPhase};
+use tokio::task::JoinSet;
+use tokio::time::MissedTickBehavior;
+use tracing::{info, warn};
+
+/// Follow the debug.log of every configured node until the app starts shutting down
+///
+/// Only lines logged from now on are ingested, older ones can be added with [`backfill`].
+pub async fn run(configs: Vec<LogConfig>, app: NodeScopeApp) -> anyhow::Result<()> {
+    let mut followers = JoinSet::new();
+    for config in configs {
+        let (tailer, result) =
+            blocking(LogTailer::new(config.path.clone()), LogTailer::seek_to_end).await;
+        result.context(format!("Couldn't open {:?}", config.path))?;
+        info!("[node:{}] Following {:?}", config.node, config.path);
+        followers.spawn(follow(config, tailer, app.clone()));
+    }
+
+    // Without any log to follow this still waits, returning would shut the app down
+    app.shutdown().reached(ShutdownPhase::Draining).await;
+    while let Some(result) = followers.join_next().await {
+        result?;
+    }
+    Ok(())
+}
+
+/// Ingest the debug.log of every configured node once, from its start
+pub async fn backfill(configs: Vec<LogConfig>, app: NodeScopeApp) -> anyhow::Result<()> {
+    for config in configs {
+        let mut tailer = LogTailer::new(config.path.clone());
+        let mut ingester = Ingester::new(config.node.clone(), app.clone());
+        let mut events = 0;
+        loop {
+            let (returned, lines) = blocking(tailer, LogTailer::read_lines).await;
+            tailer = returned;
+            let lines = lines.context(format!("Couldn't read {:?}", config.path))?;
+            if lines.is_empty() {
+                break;
+            }
+            events += ingester.ingest(&lines).await?;
+        }
+        info!(
+            "[node:{}] Backfilled {} events from {:?}",
+            config.node, events, config.path
+        );
+    }
+    Ok(())
+}
+
+async fn follow(config: LogConfig, mut tailer: LogTailer, app: NodeScopeApp) {
+    let mut ingester = Ingester::new(config.node.clone(), app.clone());
+    let mut interval = tokio::time::interval(Duration::from_millis(config.poll_interval_ms.max(1)));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = app.shutdown().reached(ShutdownPhase::Draining) => break,
+        }
+        let (returned, lines) = blocking(tailer, LogTailer::read_lines).await;
+        tailer = returned;
+        let result = match lines {
+            Ok(lines) if lines.is_empty() => continue,
+            Ok(lines) => ingester.ingest(&lines).await,
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = result {
+            warn!(
+                "[node:{}] Failed to ingest {:?}: {:#}",
+                config.node, config.path, e
+            );
+        }
+    }
+}
+
+/// Run file IO of the tailer on the blocking pool, off the async workers
+async fn blocking<T: Send + 'static>(
+    mut tailer: LogTailer,
+    io: impl FnOnce(&mut LogTailer) -> io::Result<T> + Send + 'static,
+) -> (LogTailer, io::Result<T>) {
+    tokio::task::spawn_blocking(move || {
+        let result = io(&mut tailer);
+        (tailer, result)
+    })
+    .await
+    .expect("log file IO panicked")
+}
@@ -0,0 +1,244 @@
+use app::LogEventKind;
+use chrono::{DateTime, Utc};
+
+/// Categories bitcoind logs under besides `net`, `validation`, `mempool`, `mempoolrej` and
+/// `addrman`, lines tagged with these are skipped
+const OTHER_CATEGORIES: [&str; 25] = [
+    "tor",
+    "http",
+    "bench",
+    "zmq",
+    "walletdb",
+    "rpc",
+    "estimatefee",
+    "selectcoins",
+    "reindex",
+    "cmpctblock",
+    "rand",
+    "prune",
+    "proxy",
+    "libevent",
+    "coindb",
+    "qt",
+    "leveldb",
+    "i2p",
+    "ipc",
+    "lock",
+    "blockstorage",
+    "txreconciliation",
+    "scan",
+    "txpackages",
+    "util",
+];
+
+/// A line of debug.log worth recording
+#[derive(Debug, Clone, PartialEq)]
+pub enum LogLine {
+    Event(ParsedEvent),
+    /// bitcoind started, so peer ids start over
+    Started,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParsedEvent {
+    /// None when bitcoind runs with `-logtimestamps=0`
+    pub logged_at: Option<DateTime<Utc>>,
+    pub kind: LogEventKind,
+    pub peer_id: Option<i64>,
+    pub address: Option<String>,
+    pub height: Option<i64>,
+    pub message: String,
+}
+
+/// Parse a line of debug.log, None for lines that aren't recorded
+pub fn parse_line(line: &str) -> Option<LogLine> {
+    let mut rest = line.trim_end();
+
+    let mut logged_at = None;
+    if let Some((first, after)) = rest.split_once(' ')
+        && let Ok(timestamp) = DateTime::parse_from_rfc3339(first)
+    {
+        logged_at = Some(timestamp.with_timezone(&Utc));
+        rest = after;
+    }
+
+    // Thread names, categories and levels, e.g. `[msghand] [net:debug]`
+    while let Some(tagged) = rest.strip_prefix('[') {
+        let (tag, after) = tagged.split_once(']')?;
+        let name = tag.split(':').next().unwrap_or(tag);
+        if OTHER_CATEGORIES.contains(&name) {
+            return None;
+        }
+        // Parsed categories are implied by the event kind, thread names and levels don't matter
+        rest = after.trim_start();
+    }
+    let message = rest;
+
+    if message.starts_with("Bitcoin Core version ") {
+        return Some(LogLine::Started);
+    }
+
+    let kind = if message.starts_with("Added connection") {
+        LogEventKind::PeerConnected
+    } else if message.starts_with("receive version message:") {
+        LogEventKind::PeerVersion
+    } else if message.contains("for eviction peer=") || message.starts_with("disconnecting extra ")
+    {
+        LogEventKind::Evicted
+    } else if message.starts_with("disconnecting peer=") {
+        LogEventKind::PeerDisconnected
+    } else if message.starts_with("Misbehaving:") {
+        LogEventKind::Misbehaving
+    } else if message.starts_with("UpdateTip:") {
+        LogEventKind::UpdateTip
+    } else if message.starts_with("Leaving InitialBlockDownload") {
+        LogEventKind::LeftInitialBlockDownload
+    } else if message.starts_with("AcceptToMemoryPool: peer=") && message.contains(": accepted ") {
+        LogEventKind::MempoolAccepted
+    } else if message.contains(" was not accepted: ") {
+        LogEventKind::MempoolRejected
+    } else if message.starts_with("Added ") && message.contains(" addresses") {
+        LogEventKind::AddrmanAdded
+    } else {
+        return None;
+    };
+
+    let address = match kind {
+        // With -logips, e.g. `Added connection to 203.0.113.5:8333 peer=3`
+        LogEventKind::PeerConnected => message
+            .strip_prefix("Added connection to ")
+            .and_then(|rest| rest.split_whitespace().next())
+            .map(str::to_string),
+        // The source of addresses is logged without a port, so it can't identify a peer
+        LogEventKind::AddrmanAdded => None,
+        _ => field(message, "peeraddr=").map(str::to_string),
+    };
+    let height = match kind {
+        LogEventKind::UpdateTip => field(message, "height=").and_then(|height| height.parse().ok()),
+        _ => None,
+    };
+
+    Some(LogLine::Event(ParsedEvent {
+        logged_at,
+        kind,
+        peer_id: field(message, "peer=").and_then(|id| id.parse().ok()),
+        address,
+        height,
+        message: message.to_string(),
+    }))
+}
+
+/// Value of a `key=value` pair in a message, up to the next space, comma or parenthesis
+fn field<'a>(message: &'a str, key: &str) -> Option<&'a str> {
+    let start = message
+        .match_indices(key)
+        .map(|(index, _)| index)
+        // `peer=` must not match the end of another key
+        .find(|index| {
+            message[..*index]
+                .chars()
+                .next_back()
+                .is_none_or(|c| !c.is_alphanumeric() && c != '_')
+        })?
+        + key.len();
+    let value = &message[start..];
+    let end = value
+        .find(|c: char| c.is_whitespace() || matches!(c, ',' | ';' | '(' | ')'))
+        .unwrap_or(value.len());
+    // Trailing colons, e.g. `peer=3: accepted`
+    Some(value[..end].trim_end_matches(':')).filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(line: &str) -> ParsedEvent {
+        match parse_line(line) {
+            Some(LogLine::Event(event)) => event,
+            other => panic!("{:?} parsed as {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn test_parse_lines() {
+        let connected =
+            event("2024-05-01T12:00:00Z [net] Added connection to 203.0.113.5:8333 peer=3\n");
+        assert_eq!(connected.kind, LogEventKind::PeerConnected);
+        assert_eq!(connected.peer_id, Some(3));
+        assert_eq!(connected.address.as_deref(), Some("203.0.113.5:8333"));
+        assert_eq!(
+            connected.logged_at,
+            Some("2024-05-01T12:00:00Z".parse().unwrap())
+        );
+        assert_eq!(
+            connected.message,
+            "Added connection to 203.0.113.5:8333 peer=3"
+        );
+
+        let version = event(
+            "2024-05-01T12:00:00.123456Z [msghand] [net:debug] receive version message: \
+             /Satoshi:27.0.0/: version 70016, blocks=850000, us=[::]:0, txrelay=1, peer=3, \
+             peeraddr=[2001:db8::1]:8333",
+        );
+        assert_eq!(version.kind, LogEventKind::PeerVersion);
+        assert_eq!(version.peer_id, Some(3));
+        assert_eq!(version.address.as_deref(), Some("[2001:db8::1]:8333"));
+
+        let tip = event(
+            "2024-05-01T12:00:01Z UpdateTip: new best=00000000000000000002a7c4 height=850001 \
+             version=0x20000000 log2_work=94.9 tx=1000 date='2024-05-01T11:59:00Z' progress=1.000000",
+        );
+        assert_eq!(tip.kind, LogEventKind::UpdateTip);
+        assert_eq!(tip.height, Some(850_001));
+        assert_eq!(tip.peer_id, None);
+
+        assert_eq!(
+            event("2024-05-01T12:00:02Z [net] disconnecting peer=3").kind,
+            LogEventKind::PeerDisconnected
+        );
+        assert_eq!(
+            event("2024-05-01T12:00:02Z [net] disconnecting extra outbound peer=4 (last block announcement received at 1714564800)")
+                .peer_id,
+            Some(4)
+        );
+        let misbehaving = event(
+            "2024-05-01T12:00:03Z [net] Misbehaving: peer=5 (0 -> 100): invalid header received",
+        );
+        assert_eq!(misbehaving.kind, LogEventKind::Misbehaving);
+        assert_eq!(misbehaving.peer_id, Some(5));
+        let accepted = event(
+            "2024-05-01T12:00:04Z [mempool] AcceptToMemoryPool: peer=6: accepted 1a2b3c (wtxid=4d5e6f) (poolsz 3000 txn, 4000 kB)",
+        );
+        assert_eq!(accepted.kind, LogEventKind::MempoolAccepted);
+        assert_eq!(accepted.peer_id, Some(6));
+        assert_eq!(
+            event("2024-05-01T12:00:05Z [mempoolrej] 1a2b3c (wtxid=4d5e6f) from peer=7 was not accepted: min relay fee not met")
+                .peer_id,
+            Some(7)
+        );
+        assert_eq!(
+            event("2024-05-01T12:00:06Z Leaving InitialBlockDownload (latching to false)").kind,
+            LogEventKind::LeftInitialBlockDownload
+        );
+        assert_eq!(
+            event("2024-05-01T12:00:07Z [addrman] Added 5 addresses (of 10) from 203.0.113.0: 0 tried, 5 new").kind,
+            LogEventKind::AddrmanAdded
+        );
+
+        // Without timestamps, and lines of other categories or without events
+        assert_eq!(event("[net] disconnecting peer=3").logged_at, None);
+        assert_eq!(
+            parse_line("2024-05-01T12:00:00Z [rpc] Added connection peer=3"),
+            None
+        );
+        assert_eq!(
+            parse_line("2024-05-01T12:00:00Z [net] sending ping (8 bytes) peer=3"),
+            None
+        );
+        assert_eq!(
+            parse_line("2024-05-01T12:00:00Z Bitcoin Core version v27.0.0 (release build)"),
+            Some(LogLine::Started)
+        );
+    }
+}
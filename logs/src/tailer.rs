@@ -0,0 +1,179 @@
+use std::fs::{File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// Bytes read from the log at once, so backfilling a large log doesn't load it whole
+const MAX_READ: u64 = 1024 * 1024;
+
+/// Follows a log file by path, across rotation and truncation
+pub struct LogTailer {
+    path: PathBuf,
+    file: Option<File>,
+    /// Identity of the open file, to notice when the path points to a new one
+    file_id: Option<u64>,
+    offset: u64,
+    /// Start of a line that wasn't completely written yet
+    partial: Vec<u8>,
+}
+
+impl LogTailer {
+    /// Tail from the start of the log
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            file_id: None,
+            offset: 0,
+            partial: Vec::new(),
+        }
+    }
+
+    /// Skip what is already logged, the log may not exist yet
+    pub fn seek_to_end(&mut self) -> io::Result<()> {
+        match File::open(&self.path) {
+            Ok(file) => {
+                let metadata = file.metadata()?;
+                self.file_id = file_id(&metadata);
+                self.offset = metadata.len();
+                self.file = Some(file);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Complete lines written since the last call
+    pub fn read_lines(&mut self) -> io::Result<Vec<String>> {
+        let mut lines = Vec::new();
+        if self.file.is_none() && !self.open()? {
+            return Ok(lines);
+        }
+        if self.read_chunk(&mut lines)? {
+            return Ok(lines);
+        }
+
+        // At the end of the open file, so check whether the log moved on without it
+        let metadata = match std::fs::metadata(&self.path) {
+            Ok(metadata) => metadata,
+            // Rotated away, and the new log isn't created yet
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(lines),
+            Err(e) => return Err(e),
+        };
+        if file_id(&metadata) != self.file_id {
+            // The last line of the old log is complete, even without a newline
+            if !self.partial.is_empty() {
+                lines.push(decode(&std::mem::take(&mut self.partial)));
+            }
+            if self.open()? {
+                self.read_chunk(&mut lines)?;
+            }
+        } else if metadata.len() < self.offset {
+            // Truncated in place, e.g. by logrotate's copytruncate
+            self.offset = 0;
+            self.partial.clear();
+            self.read_chunk(&mut lines)?;
+        }
+        Ok(lines)
+    }
+
+    /// Open the file at the path from its start, false if there is none
+    fn open(&mut self) -> io::Result<bool> {
+        match File::open(&self.path) {
+            Ok(file) => {
+                self.file_id = file_id(&file.metadata()?);
+                self.file = Some(file);
+                self.offset = 0;
+                self.partial.clear();
+                Ok(true)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read up to MAX_READ bytes from the open file, true if that read anything
+    fn read_chunk(&mut self, lines: &mut Vec<String>) -> io::Result<bool> {
+        let Some(file) = &mut self.file else {
+            return Ok(false);
+        };
+        file.seek(SeekFrom::Start(self.offset))?;
+        let read = file.take(MAX_READ).read_to_end(&mut self.partial)?;
+        self.offset += read as u64;
+
+        if let Some(end) = self.partial.iter().rposition(|byte| *byte == b'\n') {
+            let rest = self.partial.split_off(end + 1);
+            let complete = std::mem::replace(&mut self.partial, rest);
+            lines.extend(
+                complete
+                    .split(|byte| *byte == b'\n')
+                    .filter(|line| !line.is_empty())
+                    .map(decode),
+            );
+        }
+        Ok(read > 0)
+    }
+}
+
+fn decode(line: &[u8]) -> String {
+    String::from_utf8_lossy(line)
+        .trim_end_matches('\r')
+        .to_string()
+}
+
+#[cfg(unix)]
+fn file_id(metadata: &Metadata) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    Some(metadata.ino())
+}
+
+/// Without inodes only truncation is noticed, not files replaced by rotation
+#[cfg(not(unix))]
+fn file_id(_metadata: &Metadata) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn append(path: &std::path::Path, text: &str) {
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_follows_rotation() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("debug.log");
+        append(&path, "old line\n");
+
+        let mut tailer = LogTailer::new(path.clone());
+        tailer.seek_to_end().unwrap();
+        assert!(tailer.read_lines().unwrap().is_empty());
+
+        append(&path, "first\nsecond");
+        assert_eq!(tailer.read_lines().unwrap(), ["first"]);
+        append(&path, " half\n");
+        assert_eq!(tailer.read_lines().unwrap(), ["second half"]);
+
+        // Moved away with a line still being written, then a new log is started
+        append(&path, "last of old");
+        std::fs::rename(&path, dir.path().join("debug.log.1")).unwrap();
+        assert_eq!(tailer.read_lines().unwrap(), Vec::<String>::new());
+        append(&path, "new\n");
+        assert_eq!(tailer.read_lines().unwrap(), ["last of old", "new"]);
+
+        // Truncated in place
+        std::fs::write(&path, "").unwrap();
+        assert!(tailer.read_lines().unwrap().is_empty());
+        append(&path, "after\n");
+        assert_eq!(tailer.read_lines().unwrap(), ["after"]);
+    }
+}
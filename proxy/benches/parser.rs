@@ -0,0 +1,138 @@
+//! Parser throughput over a recorded IBD stream and over garbage
+//!
+//! The stream is what a peer sent a syncing node, recorded to `benches/data/ibd_stream.bin`
+//! with `just record-bench-stream`. Set `NODESCOPE_BENCH_STREAM` to benchmark another
+//! recording. Without any, a synthetic stream of similar messages is used.
+
+use std::hint::black_box;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::block::{Header, Version};
+use bitcoin::consensus::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::p2p::Magic;
+use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+use bitcoin::transaction::{self, OutPoint, Sequence, TxIn, TxOut};
+use bitcoin::{
+    Amount, Block, BlockHash, CompactTarget, ScriptBuf, Transaction, TxMerkleNode, Witness,
+};
+use chrono::Utc;
+use criterion::{Criterion, Throughput, criterion_group, criterion_main};
+use proxy::bitcoin_protocol::{MessageParser, Network};
+
+/// Bytes handed to the parser per call, like a single read from the socket
+const CHUNK_SIZE: usize = 8192;
+
+/// Recording used unless `NODESCOPE_BENCH_STREAM` names another
+const RECORDED_STREAM: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/benches/data/ibd_stream.bin");
+
+fn message(payload: NetworkMessage) -> Vec<u8> {
+    serialize(&RawNetworkMessage::new(Magic::BITCOIN, payload))
+}
+
+fn header(n: u32) -> Header {
+    Header {
+        version: Version::TWO,
+        prev_blockhash: BlockHash::all_zeros(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: 1_600_000_000 + n,
+        bits: CompactTarget::from_consensus(0x1d00ffff),
+        nonce: n,
+    }
+}
+
+/// A two-in two-out segwit transaction, typical of IBD blocks
+fn transaction(n: u32) -> Transaction {
+    let input = |vout| TxIn {
+        previous_output: OutPoint {
+            txid: bitcoin::Txid::all_zeros(),
+            vout,
+        },
+        script_sig: ScriptBuf::new(),
+        sequence: Sequence::MAX,
+        witness: Witness::from_slice(&[vec![0x30; 72], vec![0x02; 33]]),
+    };
+    let output = TxOut {
+        value: Amount::from_sat(50_000 + n as u64),
+        script_pubkey: ScriptBuf::from_bytes(vec![0x00; 22]),
+    };
+
+    Transaction {
+        version: transaction::Version::TWO,
+        lock_time: LockTime::ZERO,
+        input: vec![input(n), input(n + 1)],
+        output: vec![output.clone(), output],
+    }
+}
+
+/// The recorded stream, or a synthetic one when nothing was recorded
+fn ibd_stream() -> Vec<u8> {
+    let path = std::env::var_os("NODESCOPE_BENCH_STREAM").unwrap_or_else(|| RECORDED_STREAM.into());
+    match std::fs::read(&path) {
+        Ok(stream) => stream,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            eprintln!(
+                "No recorded stream at {:?}, using a synthetic one (see `just record-bench-stream`)",
+                path
+            );
+            synthetic_ibd_stream()
+        }
+        Err(e) => panic!("Couldn't read recorded stream {:?}: {}", path, e),
+    }
+}
+
+/// Headers followed by full blocks of about 1 MB each, sprinkled with pings
+fn synthetic_ibd_stream() -> Vec<u8> {
+    let mut stream = message(NetworkMessage::Headers((0..2000).map(header).collect()));
+
+    for n in 0..16 {
+        let block = Block {
+            header: header(n),
+            txdata: (0..2500).map(transaction).collect(),
+        };
+        stream.extend(message(NetworkMessage::Block(block)));
+        stream.extend(message(NetworkMessage::Ping(n as u64)));
+    }
+    stream
+}
+
+/// Bytes that never contain the network magic
+fn garbage(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state as u8) & 0x7f
+        })
+        .collect()
+}
+
+fn parse_all(stream: &[u8]) -> usize {
+    let mut parser = MessageParser::new(Network::Bitcoin);
+    let received_at = Utc::now();
+    stream
+        .chunks(CHUNK_SIZE)
+        .map(|chunk| parser.push_data(chunk, received_at).len())
+        .sum()
+}
+
+fn bench_parser(c: &mut Criterion) {
+    let stream = ibd_stream();
+    let garbage = garbage(4 * 1024 * 1024);
+
+    let mut group = c.benchmark_group("parser");
+    group.sample_size(20);
+
+    group.throughput(Throughput::Bytes(stream.len() as u64));
+    group.bench_function("ibd_stream", |b| b.iter(|| parse_all(black_box(&stream))));
+
+    group.throughput(Throughput::Bytes(garbage.len() as u64));
+    group.bench_function("garbage", |b| b.iter(|| parse_all(black_box(&garbage))));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_parser);
+criterion_main!(benches);
@@ -0,0 +1,57 @@
+//! Every decoder must either reject its input or ask for more bytes, and never
+//! claim a frame longer than what it was given: a frame decodes from the
+//! shortest prefix holding it, asking for more never reads past that prefix, and
+//! the bytes after it change nothing
+
+#![no_main]
+
+use std::fmt::Debug;
+
+use libfuzzer_sys::fuzz_target;
+use proxy::socks5::{DecodeError, Decoded, decode_credentials, decode_greeting, decode_request};
+
+/// Decode every prefix of `data`, returning the frame with the bytes it used
+fn check<T: Debug + PartialEq>(
+    data: &[u8],
+    decode: fn(&[u8]) -> Result<Decoded<T>, DecodeError>,
+) -> Option<(T, usize)> {
+    // Lengths read so far, each with how many bytes it asked for after them
+    let mut incomplete = Vec::new();
+    let mut frame: Option<(T, usize)> = None;
+
+    for len in 0..=data.len() {
+        match decode(&data[..len]) {
+            Ok(Decoded::Frame(decoded)) => match &frame {
+                Some((first, _)) => assert_eq!(&decoded, first, "bytes past the frame changed it"),
+                None => {
+                    for (read, needed) in &incomplete {
+                        assert!(read + needed <= len, "asked for bytes past the frame");
+                    }
+                    frame = Some((decoded, len));
+                }
+            },
+            Ok(Decoded::Incomplete(needed)) => {
+                assert!(needed > 0, "decoder asked for no bytes");
+                assert!(frame.is_none(), "a complete frame became incomplete");
+                incomplete.push((len, needed));
+            }
+            Err(e) => {
+                assert!(frame.is_none(), "bytes past the frame rejected it: {}", e);
+                return None;
+            }
+        }
+    }
+
+    let (_, consumed) = frame.as_ref()?;
+    assert!(*consumed <= data.len());
+    frame
+}
+
+fuzz_target!(|data: &[u8]| {
+    check(data, decode_greeting);
+    check(data, decode_credentials);
+    if let Some((request, _)) = check(data, decode_request) {
+        // The target is handed to connect, so it has to format back
+        let _ = request.to_string();
+    }
+});
@@ -0,0 +1,40 @@
+//! Drives the decoders the way the proxy reads a connection: greeting,
+//! credentials, then request, each read exactly as requested by the decoder
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use proxy::socks5::{DecodeError, Decoded, decode_credentials, decode_greeting, decode_request};
+
+/// Decode one frame from the front of `data`, returning it with the bytes it used
+fn read_frame<T>(
+    data: &[u8],
+    decode: fn(&[u8]) -> Result<Decoded<T>, DecodeError>,
+) -> Option<(T, usize)> {
+    let mut len = 0;
+    loop {
+        match decode(&data[..len]).ok()? {
+            Decoded::Frame(frame) => return Some((frame, len)),
+            Decoded::Incomplete(needed) => {
+                assert!(needed > 0, "decoder asked for no bytes");
+                len += needed;
+                if len > data.len() {
+                    return None;
+                }
+            }
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((methods, mut offset)) = read_frame(data, decode_greeting) else {
+        return;
+    };
+    if methods.contains(&0x02) {
+        let Some((_, used)) = read_frame(&data[offset..], decode_credentials) else {
+            return;
+        };
+        offset += used;
+    }
+    let _ = read_frame(&data[offset..], decode_request);
+});
@@ -0,0 +1,104 @@
+use app::{AnnouncedVia, Item, ItemKind};
+use bitcoin::p2p::message::NetworkMessage;
+use bitcoin::p2p::message_blockdata::Inventory;
+
+/// Most headers Bitcoin Core announces new blocks with, larger batches answer getheaders
+const MAX_HEADERS_ANNOUNCEMENT: usize = 8;
+
+/// The blocks and transactions a message announces, if it is an announcement
+pub fn announced_items(message: &NetworkMessage) -> Option<(AnnouncedVia, Vec<Item>)> {
+    let (via, items) = match message {
+        NetworkMessage::Inv(inventory) => (AnnouncedVia::Inv, inventory_items(inventory)),
+        NetworkMessage::Headers(headers) if headers.len() <= MAX_HEADERS_ANNOUNCEMENT => (
+            AnnouncedVia::Headers,
+            headers
+                .iter()
+                .map(|header| Item {
+                    kind: ItemKind::Block,
+                    hash: header.block_hash().to_string(),
+                })
+                .collect(),
+        ),
+        NetworkMessage::CmpctBlock(block) => (
+            AnnouncedVia::Cmpctblock,
+            vec![Item {
+                kind: ItemKind::Block,
+                hash: block.compact_block.header.block_hash().to_string(),
+            }],
+        ),
+        _ => return None,
+    };
+    Some((via, items)).filter(|(_, items)| !items.is_empty())
+}
+
+/// The blocks and transactions of a getdata or notfound message
+pub fn inventory_items(inventory: &[Inventory]) -> Vec<Item> {
+    inventory.iter().filter_map(inventory_item).collect()
+}
+
+fn inventory_item(inventory: &Inventory) -> Option<Item> {
+    let (kind, hash) = match inventory {
+        Inventory::Transaction(txid) | Inventory::WitnessTransaction(txid) => {
+            (ItemKind::Tx, txid.to_string())
+        }
+        Inventory::WTx(wtxid) => (ItemKind::Tx, wtxid.to_string()),
+        Inventory::Block(hash) | Inventory::CompactBlock(hash) | Inventory::WitnessBlock(hash) => {
+            (ItemKind::Block, hash.to_string())
+        }
+        Inventory::Error | Inventory::Unknown { .. } => return None,
+    };
+    Some(Item { kind, hash })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hashes::Hash;
+    use bitcoin::{BlockHash, Txid, Wtxid};
+
+    #[test]
+    fn test_announced_items() {
+        let txid = Txid::from_byte_array([1; 32]);
+        let wtxid = Wtxid::from_byte_array([2; 32]);
+        let block = BlockHash::from_byte_array([3; 32]);
+        let (via, items) = announced_items(&NetworkMessage::Inv(vec![
+            Inventory::Transaction(txid),
+            Inventory::WTx(wtxid),
+            Inventory::Block(block),
+            Inventory::Error,
+        ]))
+        .unwrap();
+        assert_eq!(via, AnnouncedVia::Inv);
+        assert_eq!(
+            items,
+            [
+                Item {
+                    kind: ItemKind::Tx,
+                    hash: txid.to_string()
+                },
+                Item {
+                    kind: ItemKind::Tx,
+                    hash: wtxid.to_string()
+                },
+                Item {
+                    kind: ItemKind::Block,
+                    hash: block.to_string()
+                },
+            ]
+        );
+
+        let genesis =
+            bitcoin::blockdata::constants::genesis_block(bitcoin::Network::Bitcoin).header;
+        let (via, items) = announced_items(&NetworkMessage::Headers(vec![genesis])).unwrap();
+        assert_eq!(via, AnnouncedVia::Headers);
+        assert_eq!(
+            items[0].hash,
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+
+        // Headers answering getheaders aren't announcements
+        assert!(announced_items(&NetworkMessage::Headers(vec![genesis; 9])).is_none());
+        assert!(announced_items(&NetworkMessage::Inv(vec![])).is_none());
+        assert!(announced_items(&NetworkMessage::Verack).is_none());
+    }
+}
@@ -1,8 +1,16 @@
-use bitcoin::consensus::encode::{self, Decodable};
-use bitcoin::p2p::message::{CommandString, RawNetworkMessage};
+use app::ParserState;
+use bitcoin::consensus::encode;
 use bitcoin::p2p::Magic;
+use bitcoin::p2p::message::{CommandString, NetworkMessage, RawNetworkMessage};
+use bytes::{Buf, BytesMut};
+use chrono::{DateTime, Utc};
 use std::fmt;
-use std::io::Cursor;
+
+/// Size of a message header: magic, command, payload length and checksum
+pub const HEADER_SIZE: usize = 24;
+
+/// Largest payload accepted, matching Bitcoin Core's `MAX_PROTOCOL_MESSAGE_LENGTH`
+pub const MAX_PAYLOAD_SIZE: usize = 4_000_000;
 
 /// Re-export bitcoin types for convenience
 pub use bitcoin::Network;
@@ -14,6 +22,10 @@ pub struct BitcoinMessage {
     pub command: CommandString,
     pub payload_len: usize,
     pub raw_message: RawNetworkMessage,
+    /// When the bytes completing this message were read from the source
+    pub received_at: DateTime<Utc>,
+    /// When the bytes completing this message were written to the destination
+    pub forwarded_at: Option<DateTime<Utc>>,
 }
 
 impl fmt::Display for BitcoinMessage {
@@ -21,9 +33,7 @@ impl fmt::Display for BitcoinMessage {
         write!(
             f,
             "BitcoinMessage {{ network: {:?}, command: {}, payload_size: {} }}",
-            self.network,
-            self.command,
-            self.payload_len
+            self.network, self.command, self.payload_len
         )
     }
 }
@@ -36,8 +46,6 @@ impl BitcoinMessage {
 
     /// Get a detailed description of the message for logging
     pub fn description(&self) -> String {
-        use bitcoin::p2p::message::NetworkMessage;
-
         match self.raw_message.payload() {
             NetworkMessage::Version(v) => {
                 format!(
@@ -92,74 +100,279 @@ impl BitcoinMessage {
     }
 }
 
+/// Something a parser found in its stream
+#[derive(Debug)]
+pub enum ParserEvent {
+    Message(BitcoinMessage),
+    /// The magic of the first message told which network the stream belongs to
+    ///
+    /// Only reported when auto-detecting, or when it isn't the parser's network.
+    /// Custom signets have a magic of their own and are reported as signet.
+    NetworkDetected {
+        network: Network,
+        magic: Magic,
+    },
+    /// The parser lost or regained track of message boundaries
+    StateChanged(ParserState),
+    /// Bytes that couldn't be attributed to any message
+    BytesSkipped(usize),
+    /// The payload didn't match the checksum in its header, so it was skipped
+    ChecksumMismatch {
+        command: String,
+        payload_len: usize,
+    },
+    /// A header announced a payload above `MAX_PAYLOAD_SIZE`
+    OversizePayload {
+        command: String,
+        payload_len: usize,
+    },
+    /// A message with a command rust-bitcoin doesn't know, still emitted as a message
+    UnknownCommand {
+        command: String,
+        payload_len: usize,
+    },
+    /// The checksum matched but the payload couldn't be decoded
+    InvalidPayload {
+        command: String,
+        payload_len: usize,
+    },
+}
+
 /// Parser that maintains state for streaming Bitcoin message parsing
+///
+/// Headers are decoded first, so a message is only decoded once all of its
+/// payload has arrived. When the stream doesn't line up with message
+/// boundaries, the parser scans for the network magic to resynchronize.
+///
+/// The magic of the first header is always checked against the known networks,
+/// so a parser created with [`MessageParser::auto`] can adopt it.
 pub struct MessageParser {
-    buffer: Vec<u8>,
-    network: Network,
+    buffer: BytesMut,
+    /// Not known before the first message when auto-detecting
+    network: Option<Network>,
+    magic: Option<[u8; 4]>,
+    /// Whether the first header has been checked for its network
+    identified: bool,
+    state: ParserState,
+    events: Vec<ParserEvent>,
+    /// Bytes skipped but not reported yet, so adjacent skips become one event
+    skipped: usize,
+    /// Resuming after a gap in the inspected stream, so what precedes the next
+    /// message isn't a parse problem
+    resuming: bool,
 }
 
 impl MessageParser {
     /// Create a new parser for the given network
     pub fn new(network: Network) -> Self {
+        Self::with_network(Some(network))
+    }
+
+    /// Create a parser that takes the network from the magic of the first message
+    pub fn auto() -> Self {
+        Self::with_network(None)
+    }
+
+    fn with_network(network: Option<Network>) -> Self {
         Self {
-            buffer: Vec::new(),
+            buffer: BytesMut::new(),
             network,
+            magic: network.map(|network| Magic::from(network).to_bytes()),
+            identified: false,
+            state: ParserState::Synced,
+            events: Vec::new(),
+            skipped: 0,
+            resuming: false,
         }
     }
 
-    /// Add data to the parser and extract any complete messages
-    pub fn push_data(&mut self, data: &[u8]) -> Vec<BitcoinMessage> {
+    /// Add data read at `received_at` to the parser and extract any complete messages
+    pub fn push_data(&mut self, data: &[u8], received_at: DateTime<Utc>) -> Vec<ParserEvent> {
         self.buffer.extend_from_slice(data);
 
-        let mut messages = Vec::new();
+        while self.buffer.len() >= 4 {
+            if !self.identified {
+                if self.buffer.len() < HEADER_SIZE {
+                    break;
+                }
+                self.identify();
+            }
+            let Some(magic) = self.magic else {
+                // Still looking for a first header to take the network from
+                self.set_state(ParserState::Desynced);
+                self.skip(1);
+                continue;
+            };
 
-        loop {
-            if self.buffer.is_empty() {
+            if self.buffer[..4] != magic {
+                // Noise, encrypted data or another network
+                self.set_state(ParserState::Desynced);
+                self.skip_to_magic(magic);
+                continue;
+            }
+            if self.buffer.len() < HEADER_SIZE {
                 break;
             }
 
-            // Try to decode a message
-            let mut cursor = Cursor::new(&self.buffer);
-            match RawNetworkMessage::consensus_decode(&mut cursor) {
+            let command = command_name(&self.buffer[4..16]);
+            let payload_len =
+                u32::from_le_bytes(self.buffer[16..20].try_into().expect("4 bytes")) as usize;
+            if payload_len > MAX_PAYLOAD_SIZE {
+                // Not a header we can trust, look for the next magic
+                self.report(ParserEvent::OversizePayload {
+                    command,
+                    payload_len,
+                });
+                self.set_state(ParserState::Desynced);
+                self.skip(1);
+                continue;
+            }
+
+            let message_len = HEADER_SIZE + payload_len;
+            if self.buffer.len() < message_len {
+                // Wait for the rest of the payload
+                self.buffer.reserve(message_len - self.buffer.len());
+                break;
+            }
+
+            // The header was sane, so the stream stays aligned even if the payload isn't
+            let frame = self.buffer.split_to(message_len);
+            match bitcoin::consensus::deserialize::<RawNetworkMessage>(&frame) {
                 Ok(raw_message) => {
-                    let bytes_read = cursor.position() as usize;
-                    
-                    // Verify the network magic matches
-                    if *raw_message.magic() != Magic::from(self.network) {
-                        // Skip one byte and try again (could be noise or wrong network)
-                        self.buffer.drain(..1);
-                        continue;
+                    self.resuming = false;
+                    if self.state == ParserState::Desynced {
+                        self.set_state(ParserState::Resynced);
                     }
-
-                    let message = BitcoinMessage {
-                        network: self.network,
+                    if let NetworkMessage::Unknown { .. } = raw_message.payload() {
+                        self.emit(ParserEvent::UnknownCommand {
+                            command,
+                            payload_len,
+                        });
+                    }
+                    self.emit(ParserEvent::Message(BitcoinMessage {
+                        network: self.network.expect("network known once a magic is"),
                         command: raw_message.command(),
-                        payload_len: bytes_read - 24, // Subtract header size
+                        payload_len,
                         raw_message,
-                    };
-
-                    messages.push(message);
-                    self.buffer.drain(..bytes_read);
+                        received_at,
+                        forwarded_at: None,
+                    }));
                 }
-                Err(encode::Error::Io(ref e)) if e.kind() == bitcoin::io::ErrorKind::UnexpectedEof => {
-                    // Not enough data yet, wait for more
-                    break;
+                Err(encode::Error::InvalidChecksum { .. }) => {
+                    self.report(ParserEvent::ChecksumMismatch {
+                        command,
+                        payload_len,
+                    });
+                    self.count_skipped(frame.len());
                 }
                 Err(_) => {
-                    // Parse error - could be noise or encrypted data
-                    // Skip one byte and try again
-                    self.buffer.drain(..1);
-                    
-                    // If buffer gets too large without a valid message, clear it
-                    if self.buffer.len() > 10_000_000 {
-                        self.buffer.clear();
-                        break;
-                    }
+                    self.report(ParserEvent::InvalidPayload {
+                        command,
+                        payload_len,
+                    });
+                    self.count_skipped(frame.len());
                 }
             }
         }
 
-        messages
+        self.flush_skipped();
+        std::mem::take(&mut self.events)
+    }
+
+    /// Check the magic of the first header against the known networks
+    ///
+    /// A parser without a network adopts the one it finds. Otherwise only the
+    /// first header is checked, so a wrong network can be reported.
+    fn identify(&mut self) {
+        let magic = Magic::from_bytes(self.buffer[..4].try_into().expect("4 bytes"));
+        let network = match Network::try_from(magic) {
+            Ok(network) => network,
+            // Custom signets have their own magic, but still open with a version
+            Err(_) if command_name(&self.buffer[4..16]) == "version" => Network::Signet,
+            Err(_) => {
+                self.identified = self.network.is_some();
+                return;
+            }
+        };
+
+        self.identified = true;
+        match self.magic {
+            Some(expected) if expected == magic.to_bytes() => return,
+            Some(_) => {}
+            None => {
+                self.network = Some(network);
+                self.magic = Some(magic.to_bytes());
+            }
+        }
+        self.emit(ParserEvent::NetworkDetected { network, magic });
+    }
+
+    /// Whether the parser is aligned with message boundaries
+    pub fn state(&self) -> ParserState {
+        self.state
+    }
+
+    /// Start over after part of the stream was not inspected
+    ///
+    /// Buffered bytes are discarded and the parser picks up at the next message
+    /// it finds. Neither counts as a parse problem, as the bytes were never
+    /// given to the parser.
+    pub fn resume_after_gap(&mut self) {
+        self.buffer.clear();
+        self.resuming = true;
+    }
+
+    fn set_state(&mut self, state: ParserState) {
+        if self.resuming {
+            return;
+        }
+        if self.state != state {
+            self.state = state;
+            self.emit(ParserEvent::StateChanged(state));
+        }
+    }
+
+    /// Queue a parse problem, unless it is caused by resuming after a gap
+    fn report(&mut self, event: ParserEvent) {
+        if !self.resuming {
+            self.emit(event);
+        }
+    }
+
+    /// Queue an event, after reporting bytes skipped before it
+    fn emit(&mut self, event: ParserEvent) {
+        self.flush_skipped();
+        self.events.push(event);
+    }
+
+    fn flush_skipped(&mut self) {
+        if self.skipped > 0 {
+            self.events
+                .push(ParserEvent::BytesSkipped(std::mem::take(&mut self.skipped)));
+        }
+    }
+
+    /// Skip ahead to the next occurrence of the network magic
+    ///
+    /// Up to 3 trailing bytes are kept, as they may be the start of a magic.
+    fn skip_to_magic(&mut self, magic: [u8; 4]) {
+        let skip = self.buffer[1..]
+            .windows(4)
+            .position(|window| window == magic)
+            .map_or(self.buffer.len().saturating_sub(3), |i| i + 1);
+        self.skip(skip.max(1));
+    }
+
+    /// Drop bytes from the front of the buffer
+    fn skip(&mut self, n: usize) {
+        self.buffer.advance(n);
+        self.count_skipped(n);
+    }
+
+    fn count_skipped(&mut self, n: usize) {
+        if !self.resuming {
+            self.skipped += n;
+        }
     }
 
     /// Get the current buffer size (for debugging)
@@ -169,18 +382,214 @@ impl MessageParser {
     }
 }
 
+/// Command of a header, without the NUL padding
+fn command_name(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn serialize(payload: NetworkMessage) -> Vec<u8> {
+        bitcoin::consensus::serialize(&RawNetworkMessage::new(Magic::BITCOIN, payload))
+    }
+
+    fn messages(events: &[ParserEvent]) -> Vec<&BitcoinMessage> {
+        events
+            .iter()
+            .filter_map(|event| match event {
+                ParserEvent::Message(msg) => Some(msg),
+                _ => None,
+            })
+            .collect()
+    }
+
     #[test]
     fn test_message_parser_incomplete() {
         let mut parser = MessageParser::new(Network::Bitcoin);
-        
+
         // Incomplete data should not produce any messages
-        let messages = parser.push_data(&[0xf9, 0xbe, 0xb4, 0xd9]);
-        assert_eq!(messages.len(), 0);
+        let events = parser.push_data(&[0xf9, 0xbe, 0xb4, 0xd9], Utc::now());
+        assert!(events.is_empty());
         assert!(parser.buffer_len() > 0);
     }
-}
 
+    #[test]
+    fn test_message_parser_resyncs_after_garbage() {
+        let mut parser = MessageParser::new(Network::Bitcoin);
+
+        let mut data = vec![0x00, 0x01, 0x02];
+        data.extend_from_slice(&serialize(NetworkMessage::Verack));
+        let events = parser.push_data(&data, Utc::now());
+
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ParserEvent::StateChanged(ParserState::Desynced),
+                ParserEvent::BytesSkipped(3),
+                ParserEvent::StateChanged(ParserState::Resynced),
+                ParserEvent::Message(_),
+            ]
+        ));
+        assert_eq!(parser.state(), ParserState::Resynced);
+    }
+
+    #[test]
+    fn test_message_parser_resumes_after_gap_quietly() {
+        let mut parser = MessageParser::new(Network::Bitcoin);
+        let ping = serialize(NetworkMessage::Ping(42));
+
+        // The rest of the ping was never queued for inspection
+        assert!(parser.push_data(&ping[..10], Utc::now()).is_empty());
+        parser.resume_after_gap();
+
+        let mut data = ping[HEADER_SIZE..].to_vec();
+        data.extend_from_slice(&serialize(NetworkMessage::Verack));
+        let events = parser.push_data(&data, Utc::now());
+        assert!(matches!(events.as_slice(), [ParserEvent::Message(_)]));
+        assert_eq!(parser.state(), ParserState::Synced);
+
+        // Real problems are reported again afterwards
+        let events = parser.push_data(&[0x00], Utc::now());
+        assert!(events.is_empty());
+        let events = parser.push_data(&serialize(NetworkMessage::Verack), Utc::now());
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ParserEvent::StateChanged(ParserState::Desynced),
+                ParserEvent::BytesSkipped(1),
+                ParserEvent::StateChanged(ParserState::Resynced),
+                ParserEvent::Message(_),
+            ]
+        ));
+    }
+
+    #[test]
+    fn test_message_parser_waits_for_payload() {
+        let mut parser = MessageParser::new(Network::Bitcoin);
+        let ping = serialize(NetworkMessage::Ping(42));
+
+        // A complete header isn't enough without the payload
+        assert!(
+            parser
+                .push_data(&ping[..HEADER_SIZE + 3], Utc::now())
+                .is_empty()
+        );
+        let events = parser.push_data(&ping[HEADER_SIZE + 3..], Utc::now());
+        assert_eq!(messages(&events)[0].payload_len, 8);
+        assert_eq!(parser.buffer_len(), 0);
+        assert_eq!(parser.state(), ParserState::Synced);
+    }
+
+    #[test]
+    fn test_message_parser_skips_checksum_mismatch() {
+        let mut parser = MessageParser::new(Network::Bitcoin);
+        let mut data = serialize(NetworkMessage::Ping(42));
+        data[HEADER_SIZE] ^= 0xff;
+        data.extend_from_slice(&serialize(NetworkMessage::Pong(42)));
+
+        let events = parser.push_data(&data, Utc::now());
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ParserEvent::ChecksumMismatch { payload_len: 8, .. },
+                ParserEvent::BytesSkipped(32),
+                ParserEvent::Message(_),
+            ]
+        ));
+        // The header was intact, so the parser never lost track of boundaries
+        assert_eq!(parser.state(), ParserState::Synced);
+    }
+
+    #[test]
+    fn test_message_parser_rejects_oversized_payload() {
+        let mut parser = MessageParser::new(Network::Bitcoin);
+        let mut header = Magic::BITCOIN.to_bytes().to_vec();
+        header.extend_from_slice(b"block\0\0\0\0\0\0\0");
+        header.extend_from_slice(&(MAX_PAYLOAD_SIZE as u32 + 1).to_le_bytes());
+        header.extend_from_slice(&[0; 4]);
+
+        let events = parser.push_data(&header, Utc::now());
+        assert!(matches!(
+            &events[0],
+            ParserEvent::OversizePayload { command, .. } if command == "block"
+        ));
+        assert_eq!(parser.state(), ParserState::Desynced);
+        // Only the last 3 bytes are kept, in case they start the next magic
+        assert!(parser.buffer_len() <= 3);
+    }
+
+    #[test]
+    fn test_message_parser_reports_unknown_command() {
+        let mut parser = MessageParser::new(Network::Bitcoin);
+        let data = serialize(NetworkMessage::Unknown {
+            command: CommandString::try_from_static("futurecmd").unwrap(),
+            payload: vec![1, 2, 3],
+        });
+
+        let events = parser.push_data(&data, Utc::now());
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ParserEvent::UnknownCommand { command, .. },
+                ParserEvent::Message(_),
+            ] if command == "futurecmd"
+        ));
+    }
+
+    #[test]
+    fn test_message_parser_detects_network() {
+        let signet = bitcoin::consensus::serialize(&RawNetworkMessage::new(
+            Magic::SIGNET,
+            NetworkMessage::Verack,
+        ));
+        let mut parser = MessageParser::auto();
+        let events = parser.push_data(&signet, Utc::now());
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ParserEvent::NetworkDetected { network: Network::Signet, .. },
+                ParserEvent::Message(msg),
+            ] if msg.network == Network::Signet
+        ));
+
+        // A custom signet is recognized by its version message
+        let custom = Magic::from_bytes([0x54, 0xd2, 0x6f, 0xbd]);
+        let mut version = bitcoin::consensus::serialize(&RawNetworkMessage::new(
+            custom,
+            NetworkMessage::Unknown {
+                command: CommandString::try_from_static("version").unwrap(),
+                payload: vec![0; 4],
+            },
+        ));
+        let mut parser = MessageParser::auto();
+        let events = parser.push_data(&version[..HEADER_SIZE], Utc::now());
+        assert!(matches!(
+            events.as_slice(),
+            [ParserEvent::NetworkDetected { network: Network::Signet, magic }] if *magic == custom
+        ));
+        // Later messages are framed with the custom magic too
+        version.drain(..HEADER_SIZE);
+        version.extend_from_slice(&bitcoin::consensus::serialize(&RawNetworkMessage::new(
+            custom,
+            NetworkMessage::Verack,
+        )));
+        let events = parser.push_data(&version, Utc::now());
+        assert_eq!(messages(&events)[0].command_name(), "verack");
+
+        // A configured network only reports a first message of another network
+        let mut parser = MessageParser::new(Network::Bitcoin);
+        let events = parser.push_data(&signet, Utc::now());
+        assert!(matches!(
+            events.first(),
+            Some(ParserEvent::NetworkDetected {
+                network: Network::Signet,
+                ..
+            })
+        ));
+        assert!(messages(&events).is_empty());
+    }
+}
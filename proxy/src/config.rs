@@ -1,15 +1,110 @@
+use std::net::SocketAddr;
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(deny_unknown_fields)]
 pub struct ProxyConfig {
-    /// Port to listen on for SOCKS5 proxy
-    #[serde(default = "default_port")]
-    pub port: u16,
-    
-    /// Bitcoin network (mainnet, testnet, signet, regtest)
+    /// Label of the node using this proxy, its data is kept apart from other nodes
+    #[serde(default = "default_node")]
+    pub node: String,
+
+    /// Address to listen on for SOCKS5, e.g. 127.0.0.1:6788 or [::]:6788
+    ///
+    /// Configs from before set a `port` instead, which listens on all interfaces.
+    #[serde(default = "default_bind")]
+    pub bind: SocketAddr,
+
+    /// Bitcoin network (mainnet, testnet, testnet4, signet, regtest), or auto to
+    /// detect it from the first message of each connection
     #[serde(default = "default_network")]
     pub network: NetworkConfig,
+
+    /// Seconds a connection may take to complete the SOCKS5 and version handshakes
+    #[serde(default = "default_handshake_timeout_secs")]
+    pub handshake_timeout_secs: u64,
+
+    /// Seconds to wait for the connect to a requested target
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+
+    /// Queueing of forwarded traffic for parsing
+    #[serde(default)]
+    pub inspection: InspectionConfig,
+
+    /// Credentials clients must authenticate with, any are accepted when unset
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+
+    /// SOCKS5 proxies for peers that can't be connected to directly
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+
+    /// Listener for inbound peers, forwarded to the node without SOCKS5
+    #[serde(default)]
+    pub transparent: Option<TransparentConfig>,
+}
+
+/// Transparent proxying of peers that connect to the node
+///
+/// The node has to listen on `node_addr` instead of the public P2P port, which
+/// the proxy takes over. It then sees every inbound peer coming from the proxy.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransparentConfig {
+    /// Address peers connect to, usually port 8333 on mainnet
+    pub bind: SocketAddr,
+    /// P2P address of the node, e.g. 127.0.0.1:8334
+    pub node_addr: String,
+}
+
+/// Upstream SOCKS5 proxies by network, other peers are connected to directly
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamConfig {
+    /// Proxy for .onion peers, usually Tor at 127.0.0.1:9050
+    pub onion: Option<String>,
+    /// Proxy for .b32.i2p peers, usually the SOCKS proxy of i2pd at 127.0.0.1:4447
+    pub i2p: Option<String>,
+}
+
+/// Fixed SOCKS5 username and password (RFC 1929)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InspectionConfig {
+    /// Chunks of forwarded data (up to 8 KiB each) queued per direction, in a queue both share
+    #[serde(default = "default_queue_chunks")]
+    pub queue_chunks: usize,
+
+    /// What to do when the parser falls behind the forwarded traffic
+    #[serde(default)]
+    pub overflow: OverflowPolicy,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverflowPolicy {
+    /// Keep forwarding at full speed and skip inspecting what doesn't fit
+    #[default]
+    Drop,
+    /// Slow down forwarding until the parser catches up
+    Backpressure,
+}
+
+impl Default for InspectionConfig {
+    fn default() -> Self {
+        Self {
+            queue_chunks: default_queue_chunks(),
+            overflow: OverflowPolicy::default(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize)]
@@ -17,17 +112,55 @@ pub struct ProxyConfig {
 pub enum NetworkConfig {
     Mainnet,
     Testnet,
+    Testnet4,
     Signet,
     Regtest,
+    /// Take the network from the magic of each connection's first message,
+    /// which also recognizes custom signets
+    Auto,
 }
 
-impl From<NetworkConfig> for bitcoin::Network {
-    fn from(config: NetworkConfig) -> Self {
-        match config {
-            NetworkConfig::Mainnet => bitcoin::Network::Bitcoin,
-            NetworkConfig::Testnet => bitcoin::Network::Testnet,
-            NetworkConfig::Signet => bitcoin::Network::Signet,
-            NetworkConfig::Regtest => bitcoin::Network::Regtest,
+impl NetworkConfig {
+    /// Name as written in the config, used to label metrics
+    pub fn name(self) -> &'static str {
+        match self {
+            NetworkConfig::Mainnet => "mainnet",
+            NetworkConfig::Testnet => "testnet",
+            NetworkConfig::Testnet4 => "testnet4",
+            NetworkConfig::Signet => "signet",
+            NetworkConfig::Regtest => "regtest",
+            NetworkConfig::Auto => "auto",
+        }
+    }
+
+    /// Name to label metrics with, None when each connection is labeled with its detected network
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            NetworkConfig::Auto => None,
+            network => Some(network.name()),
+        }
+    }
+
+    /// Name of a detected network as it would be configured
+    pub fn name_of(network: bitcoin::Network) -> &'static str {
+        match network {
+            bitcoin::Network::Bitcoin => "mainnet",
+            bitcoin::Network::Testnet => "testnet",
+            bitcoin::Network::Testnet4 => "testnet4",
+            bitcoin::Network::Signet => "signet",
+            bitcoin::Network::Regtest => "regtest",
+        }
+    }
+
+    /// The configured network, None when it is auto-detected
+    pub fn network(self) -> Option<bitcoin::Network> {
+        match self {
+            NetworkConfig::Mainnet => Some(bitcoin::Network::Bitcoin),
+            NetworkConfig::Testnet => Some(bitcoin::Network::Testnet),
+            NetworkConfig::Testnet4 => Some(bitcoin::Network::Testnet4),
+            NetworkConfig::Signet => Some(bitcoin::Network::Signet),
+            NetworkConfig::Regtest => Some(bitcoin::Network::Regtest),
+            NetworkConfig::Auto => None,
         }
     }
 }
@@ -35,16 +168,39 @@ impl From<NetworkConfig> for bitcoin::Network {
 impl Default for ProxyConfig {
     fn default() -> Self {
         Self {
-            port: default_port(),
+            node: default_node(),
+            bind: default_bind(),
             network: default_network(),
+            handshake_timeout_secs: default_handshake_timeout_secs(),
+            connect_timeout_secs: default_connect_timeout_secs(),
+            inspection: InspectionConfig::default(),
+            auth: None,
+            upstream: UpstreamConfig::default(),
+            transparent: None,
         }
     }
 }
 
-fn default_port() -> u16 {
-    6788
+fn default_node() -> String {
+    "default".to_string()
+}
+
+fn default_bind() -> SocketAddr {
+    SocketAddr::from(([0, 0, 0, 0], 6788))
 }
 
 fn default_network() -> NetworkConfig {
     NetworkConfig::Mainnet
 }
+
+fn default_handshake_timeout_secs() -> u64 {
+    60
+}
+
+fn default_connect_timeout_secs() -> u64 {
+    10
+}
+
+fn default_queue_chunks() -> usize {
+    1024
+}
@@ -1,82 +1,191 @@
-use crate::bitcoin_protocol::{BitcoinMessage, MessageParser, Network};
+use crate::announcement::{announced_items, inventory_items};
+use crate::bitcoin_protocol::{BitcoinMessage, MessageParser, Network, ParserEvent};
+use crate::config::{AuthConfig, InspectionConfig, NetworkConfig, UpstreamConfig};
+use crate::handshake::HandshakeTracker;
+use crate::inspection::{Chunk, Inspection, InspectionQueue};
+use crate::latency::PingTracker;
+use crate::relay::TxOutputCache;
+use crate::transport::TransportDetector;
 use anyhow::Context;
-use std::sync::Arc;
+use app::{
+    ConnectionClosed, ConnectionOpened, Direction, FeeFilterReceived, HandshakeFailureReason,
+    ItemsAnnounced, ItemsNotFound, ItemsRequested, LiveConnection, LiveStats, MessageSeen,
+    NetworkDetected, NetworkType, NodeMetrics, NodeScopeApp, P2pEvent, ParseHealth, ParserState,
+    ShutdownPhase, Transport, TransportDetected,
+};
+use bitcoin::p2p::Magic;
+use bitcoin::p2p::message::NetworkMessage;
+use chrono::{DateTime, Utc};
+use std::collections::VecDeque;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
+use tokio::sync::mpsc;
 use tracing::{debug, info, warn};
 
-/// Represents a direction of traffic flow
-#[derive(Debug, Clone, Copy)]
-pub enum Direction {
-    Inbound,  // Client -> Bitcoin Core
-    Outbound, // Bitcoin Core -> Client
+/// Size of the buffer used to read from either side
+const READ_BUFFER_SIZE: usize = 8192;
+
+/// Settings shared by every connection accepted on a listener
+#[derive(Clone)]
+pub struct ConnectionSettings {
+    /// Label of the node behind the listener
+    pub node: String,
+    /// None when detected from the first message of each connection
+    pub network: Option<Network>,
+    pub handshake_timeout: Duration,
+    pub connect_timeout: Duration,
+    pub metrics: NodeMetrics,
+    pub inspection: InspectionConfig,
+    pub auth: Option<AuthConfig>,
+    pub upstream: UpstreamConfig,
+    /// Peers connect to the listener, instead of the node connecting through it
+    pub inbound: bool,
 }
 
-impl std::fmt::Display for Direction {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Direction::Inbound => write!(f, "→"),
-            Direction::Outbound => write!(f, "←"),
+impl ConnectionSettings {
+    /// Settings of a single connection, whose metrics get the network detected for it
+    pub fn for_connection(&self) -> Self {
+        Self {
+            metrics: self.metrics.for_connection(),
+            ..self.clone()
         }
     }
-}
 
-/// Statistics for a connection
-#[derive(Debug, Clone, Default)]
-pub struct ConnectionStats {
-    pub bytes_inbound: u64,
-    pub bytes_outbound: u64,
-    pub messages_inbound: u64,
-    pub messages_outbound: u64,
+    /// Direction of the traffic sent by the side that opened the connection
+    fn initiator_direction(&self) -> Direction {
+        if self.inbound {
+            Direction::Outbound
+        } else {
+            Direction::Inbound
+        }
+    }
 }
 
-/// Handles a single proxy connection between a client and Bitcoin Core
+/// Handles a single proxy connection between Bitcoin Core and a peer
+///
+/// The client is always the node and the target its peer, whichever opened the
+/// connection, so directions mean the same for inbound connections.
 pub struct ConnectionHandler {
     connection_id: u64,
     client_addr: String,
     target_addr: String,
-    network: Network,
-    stats: Arc<tokio::sync::Mutex<ConnectionStats>>,
+    network_type: NetworkType,
+    settings: ConnectionSettings,
+    stats: Arc<LiveStats>,
+    inspector: Arc<Inspector>,
+    app: NodeScopeApp,
 }
 
 impl ConnectionHandler {
-    pub fn new(connection_id: u64, client_addr: String, target_addr: String, network: Network) -> Self {
+    pub fn new(
+        connection_id: u64,
+        client_addr: String,
+        target_addr: String,
+        network_type: NetworkType,
+        settings: ConnectionSettings,
+        handshake: HandshakeTracker,
+        app: NodeScopeApp,
+    ) -> Self {
+        let stats = Arc::new(LiveStats::default());
+        let inspector = Arc::new(Inspector {
+            connection_id,
+            settings: settings.clone(),
+            stats: stats.clone(),
+            handshake,
+            pings: PingTracker::new(connection_id),
+            transport: OnceLock::new(),
+            network: OnceLock::new(),
+            app: app.clone(),
+        });
+
         Self {
             connection_id,
             client_addr,
             target_addr,
-            network,
-            stats: Arc::new(tokio::sync::Mutex::new(ConnectionStats::default())),
+            network_type,
+            settings,
+            stats,
+            inspector,
+            app,
         }
     }
 
     /// Handle the proxied connection
-    pub async fn handle(
-        self,
-        mut client: TcpStream,
-        mut target: TcpStream,
-    ) -> anyhow::Result<()> {
+    pub async fn handle(self, mut client: TcpStream, mut target: TcpStream) -> anyhow::Result<()> {
         info!(
             "[conn:{}] Established: {} <-> {}",
             self.connection_id, self.client_addr, self.target_addr
         );
 
+        // The node sees an inbound peer coming from the address the proxy connected from
+        let (peer_port, local_addr) = if self.settings.inbound {
+            (
+                target.peer_addr().ok().map(|addr| addr.port()),
+                client.local_addr().ok().map(|addr| addr.to_string()),
+            )
+        } else {
+            (None, None)
+        };
+
+        let opened_at = chrono::Utc::now();
+        let registration = self.app.connections().register(LiveConnection {
+            connection_id: self.connection_id,
+            node: self.settings.node.clone(),
+            client_addr: self.client_addr.clone(),
+            target_addr: self.target_addr.clone(),
+            local_addr: local_addr.clone(),
+            network: self.settings.network,
+            inbound: self.settings.inbound,
+            opened_at,
+            stats: self.stats.clone(),
+        });
+
+        self.app
+            .publish(P2pEvent::ConnectionOpened(ConnectionOpened {
+                connection_id: self.connection_id,
+                node: self.settings.node.clone(),
+                client_addr: self.client_addr.clone(),
+                target_addr: self.target_addr.clone(),
+                peer_port,
+                local_addr,
+                network: self.settings.network,
+                network_type: self.network_type,
+                inbound: self.settings.inbound,
+                timestamp: opened_at,
+            }));
+        self.settings.metrics.connection_opened();
+
+        // Parsing runs in its own task so it never delays forwarding. Both directions share
+        // one queue, so messages are inspected in the order they were read.
+        let (queue, inspections) = InspectionQueue::new(
+            2 * self.settings.inspection.queue_chunks,
+            self.settings.inspection.overflow,
+        );
+        let inspection = tokio::spawn(self.inspector.clone().run(inspections));
+
         // Split both streams into read/write halves
         let (client_read, client_write) = client.split();
         let (target_read, target_write) = target.split();
 
         // Create bidirectional forwarding tasks
-        let inbound = self.forward_traffic(
-            client_read,
-            target_write,
-            Direction::Inbound,
-        );
+        let inbound = self.forward_traffic(client_read, target_write, Direction::Inbound, &queue);
 
-        let outbound = self.forward_traffic(
-            target_read,
-            client_write,
-            Direction::Outbound,
-        );
+        let outbound = self.forward_traffic(target_read, client_write, Direction::Outbound, &queue);
+
+        // Flag handshakes that don't complete in time, without closing the connection
+        let timeout = async {
+            tokio::time::sleep_until(self.inspector.handshake.deadline()).await;
+            self.inspector.handshake.fail(
+                HandshakeFailureReason::Timeout,
+                format!(
+                    "no verack within {}s of accepting",
+                    self.settings.handshake_timeout.as_secs()
+                ),
+            );
+            std::future::pending::<()>().await
+        };
 
         // Wait for either direction to close or error
         tokio::select! {
@@ -90,10 +199,28 @@ impl ConnectionHandler {
                     warn!("[conn:{}] Outbound error: {}", self.connection_id, e);
                 }
             }
+            _ = timeout => {}
+            _ = self.app.shutdown().reached(ShutdownPhase::Closing) => {
+                info!("[conn:{}] Closing for shutdown", self.connection_id);
+            }
+        }
+
+        // Let the inspector finish what was queued
+        drop(queue);
+        let _ = inspection.await;
+
+        self.inspector.handshake.fail(
+            HandshakeFailureReason::DisconnectedBeforeVerack,
+            "connection closed before the handshake completed",
+        );
+
+        for direction in [Direction::Inbound, Direction::Outbound] {
+            let packets = self.stats.packet_stats(self.connection_id, direction);
+            self.app.publish(P2pEvent::PacketStatsRecorded(packets));
         }
 
         // Log final statistics
-        let stats = self.stats.lock().await;
+        let stats = self.stats.snapshot();
         info!(
             "[conn:{}] Closed: {} bytes in ({} msgs), {} bytes out ({} msgs)",
             self.connection_id,
@@ -103,22 +230,36 @@ impl ConnectionHandler {
             stats.messages_outbound
         );
 
+        self.app
+            .publish(P2pEvent::ConnectionClosed(ConnectionClosed {
+                connection_id: self.connection_id,
+                node: self.settings.node.clone(),
+                stats,
+                timestamp: chrono::Utc::now(),
+            }));
+        self.settings.metrics.connection_closed();
+        drop(registration);
+
         Ok(())
     }
 
-    /// Forward traffic in one direction while parsing Bitcoin messages
+    /// Forward traffic in one direction, queueing a copy for inspection
     async fn forward_traffic<R, W>(
         &self,
         mut reader: R,
         mut writer: W,
         direction: Direction,
+        inspection: &InspectionQueue,
     ) -> anyhow::Result<()>
     where
         R: AsyncReadExt + Unpin,
         W: AsyncWriteExt + Unpin,
     {
-        let mut parser = MessageParser::new(self.network);
-        let mut buffer = vec![0u8; 8192];
+        let mut buffer = vec![0u8; READ_BUFFER_SIZE];
+        let mut after_gap = false;
+        // The side that connected speaks first, so its bytes tell which transport is used
+        let mut detector =
+            (direction == self.settings.initiator_direction()).then(TransportDetector::new);
 
         loop {
             // Read data from source
@@ -132,46 +273,600 @@ impl ConnectionHandler {
                 break;
             }
 
+            let received_at = chrono::Utc::now();
             let data = &buffer[..n];
 
-            // Update statistics
+            self.stats.record_packet(direction, n as u64, received_at);
+            if let Some(transport) = detector.as_mut().and_then(|d| d.push(data)) {
+                detector = None;
+                self.inspector.transport_detected(transport);
+            }
+            let encrypted = self.inspector.is_encrypted();
+
+            // Encrypted traffic can't be parsed, only its sizes and timing are kept
+            let queued = if encrypted {
+                self.settings.metrics.encrypted_packet(direction, n);
+                false
+            } else if inspection
+                .push(direction, data, received_at, after_gap)
+                .await
             {
-                let mut stats = self.stats.lock().await;
-                match direction {
-                    Direction::Inbound => stats.bytes_inbound += n as u64,
-                    Direction::Outbound => stats.bytes_outbound += n as u64,
+                after_gap = false;
+                true
+            } else {
+                if !after_gap {
+                    debug!(
+                        "[conn:{}] {} Parser fell behind, skipping inspection",
+                        self.connection_id, direction
+                    );
                 }
+                self.settings.metrics.inspection_dropped(direction, n);
+                after_gap = true;
+                false
+            };
+
+            // Forward the data unchanged
+            let forwarded = async {
+                writer.write_all(data).await?;
+                writer.flush().await
             }
+            .await;
 
-            // Try to parse Bitcoin messages
-            let messages = parser.push_data(data);
-            for msg in messages {
-                self.log_message(&msg, direction).await;
+            let forwarded_at = forwarded.is_ok().then(chrono::Utc::now);
+            if forwarded.is_ok() {
+                self.settings.metrics.bytes_forwarded(direction, n);
+            }
+            if queued {
+                inspection.forwarded(direction, forwarded_at);
             }
 
-            // Forward the data unchanged
-            writer
-                .write_all(data)
-                .await
-                .context("Failed to write to stream")?;
-            writer.flush().await.context("Failed to flush stream")?;
+            forwarded.context("Failed to forward data")?;
         }
 
         Ok(())
     }
+}
+
+/// Messages parsed from a chunk, waiting for the chunk to be forwarded
+struct Parsed {
+    direction: Direction,
+    messages: Vec<BitcoinMessage>,
+    forwarded: bool,
+    forwarded_at: Option<DateTime<Utc>>,
+    /// Keeps the chunk's room in the queue until its messages are published
+    _room: tokio::sync::OwnedSemaphorePermit,
+}
+
+/// Parser and parse health of one direction
+struct DirectionParser {
+    parser: MessageParser,
+    health: ParseHealth,
+}
+
+/// Parses the traffic of a connection and publishes what it finds
+struct Inspector {
+    connection_id: u64,
+    settings: ConnectionSettings,
+    stats: Arc<LiveStats>,
+    handshake: HandshakeTracker,
+    pings: PingTracker,
+    /// Set once the client's first bytes revealed the transport
+    transport: OnceLock<Transport>,
+    /// Set once the first message revealed the network, when auto-detecting
+    network: OnceLock<Network>,
+    app: NodeScopeApp,
+}
+
+impl Inspector {
+    /// Record the transport of the connection, stopping inspection if it is encrypted
+    fn transport_detected(&self, transport: Transport) {
+        if self.transport.set(transport).is_err() {
+            return;
+        }
+
+        match transport {
+            Transport::V1 => debug!("[conn:{}] Transport: v1", self.connection_id),
+            Transport::V2Encrypted => {
+                info!(
+                    "[conn:{}] BIP324 v2 transport detected, messages won't be parsed",
+                    self.connection_id
+                );
+                self.handshake.encrypted();
+            }
+        }
+        self.settings.metrics.transport_detected(transport);
+        self.app
+            .publish(P2pEvent::TransportDetected(TransportDetected {
+                connection_id: self.connection_id,
+                transport,
+                timestamp: chrono::Utc::now(),
+            }));
+    }
+
+    /// Record the network a direction's first message belongs to
+    ///
+    /// Auto-detecting connections adopt it, others warn that nothing of the
+    /// direction can be parsed, as the magic isn't the configured one.
+    fn network_detected(&self, direction: Direction, network: Network, magic: Magic) {
+        let Some(configured) = self.settings.network else {
+            if self.network.set(network).is_ok() {
+                self.settings
+                    .metrics
+                    .network_detected(NetworkConfig::name_of(network));
+                info!(
+                    "[conn:{}] {} Detected network {} (magic {})",
+                    self.connection_id, direction, network, magic
+                );
+                self.app.publish(P2pEvent::NetworkDetected(NetworkDetected {
+                    connection_id: self.connection_id,
+                    network,
+                    timestamp: chrono::Utc::now(),
+                }));
+            } else if self.network.get() != Some(&network) {
+                warn!(
+                    "[conn:{}] {} Magic {} is for {}, but the other direction is on {:?}",
+                    self.connection_id,
+                    direction,
+                    magic,
+                    network,
+                    self.network.get()
+                );
+            }
+            return;
+        };
+
+        warn!(
+            "[conn:{}] {} Magic {} is for {}, not the configured {}, so messages can't be parsed",
+            self.connection_id, direction, magic, network, configured
+        );
+        self.settings
+            .metrics
+            .parse_error(direction, "network_mismatch");
+    }
 
-    /// Log a parsed Bitcoin message
-    async fn log_message(&self, msg: &BitcoinMessage, direction: Direction) {
-        let mut stats = self.stats.lock().await;
-        match direction {
-            Direction::Inbound => stats.messages_inbound += 1,
-            Direction::Outbound => stats.messages_outbound += 1,
+    fn is_encrypted(&self) -> bool {
+        self.transport.get() == Some(&Transport::V2Encrypted)
+    }
+
+    /// Parse queued chunks of both directions until the forwarders are done
+    ///
+    /// Messages are published in the order their data was read, each once its
+    /// chunk was forwarded, so answers never come before what they answer.
+    async fn run(self: Arc<Self>, mut inspections: mpsc::UnboundedReceiver<Inspection>) {
+        let parser = || {
+            self.settings
+                .network
+                .map_or_else(MessageParser::auto, MessageParser::new)
+        };
+        let mut inbound = DirectionParser {
+            parser: parser(),
+            health: ParseHealth::new(self.connection_id, Direction::Inbound),
+        };
+        let mut outbound = DirectionParser {
+            parser: parser(),
+            health: ParseHealth::new(self.connection_id, Direction::Outbound),
+        };
+        let mut parsed = VecDeque::new();
+        let mut outputs = TxOutputCache::default();
+
+        while let Some(inspection) = inspections.recv().await {
+            match inspection {
+                Inspection::Chunk(chunk) => {
+                    let side = match chunk.direction {
+                        Direction::Inbound => &mut inbound,
+                        Direction::Outbound => &mut outbound,
+                    };
+                    parsed.push_back(self.parse(chunk, side));
+                }
+                Inspection::Forwarded { direction, at } => {
+                    // Chunks of a direction are forwarded one after another
+                    if let Some(chunk) = parsed.iter_mut().find(|chunk: &&mut Parsed| {
+                        chunk.direction == direction && !chunk.forwarded
+                    }) {
+                        chunk.forwarded = true;
+                        chunk.forwarded_at = at;
+                    }
+                }
+            }
+
+            while parsed.front().is_some_and(|chunk| chunk.forwarded) {
+                let chunk = parsed.pop_front().expect("front exists");
+                self.publish_messages(chunk, &mut outputs);
+            }
+        }
+
+        // Chunks whose forwarding was cut short when the connection ended
+        for chunk in parsed {
+            self.publish_messages(chunk, &mut outputs);
+        }
+    }
+
+    /// Parse a chunk, recording problems right away and keeping its messages
+    fn parse(&self, chunk: Chunk, side: &mut DirectionParser) -> Parsed {
+        let direction = chunk.direction;
+        if chunk.after_gap {
+            side.parser.resume_after_gap();
         }
 
+        let mut messages = Vec::new();
+        let mut changed = false;
+        for event in side.parser.push_data(&chunk.data, chunk.received_at) {
+            match event {
+                ParserEvent::Message(msg) => messages.push(msg),
+                ParserEvent::NetworkDetected { network, magic } => {
+                    self.network_detected(direction, network, magic);
+                }
+                event => {
+                    self.record_parser_event(direction, &event, &mut side.health);
+                    changed = true;
+                }
+            }
+        }
+
+        if changed {
+            side.health.updated_at = chrono::Utc::now();
+            self.app
+                .publish(P2pEvent::ParseHealthUpdated(side.health.clone()));
+        }
+
+        Parsed {
+            direction,
+            messages,
+            forwarded: false,
+            forwarded_at: None,
+            _room: chunk.room,
+        }
+    }
+
+    fn publish_messages(&self, chunk: Parsed, outputs: &mut TxOutputCache) {
+        for mut msg in chunk.messages {
+            msg.forwarded_at = chunk.forwarded_at;
+            self.log_message(msg, chunk.direction, outputs);
+        }
+    }
+
+    /// Apply a parser problem or state change to the parse health of a direction
+    fn record_parser_event(
+        &self,
+        direction: Direction,
+        event: &ParserEvent,
+        health: &mut ParseHealth,
+    ) {
+        let metrics = &self.settings.metrics;
+        match event {
+            ParserEvent::Message(_) | ParserEvent::NetworkDetected { .. } => {}
+            ParserEvent::StateChanged(state) => {
+                health.state = *state;
+                match state {
+                    ParserState::Desynced => {
+                        health.desyncs += 1;
+                        metrics.parse_error(direction, "desync");
+                        warn!(
+                            "[conn:{}] {} Parser lost sync, scanning for magic",
+                            self.connection_id, direction
+                        );
+                    }
+                    ParserState::Resynced => {
+                        info!(
+                            "[conn:{}] {} Parser resynchronized",
+                            self.connection_id, direction
+                        );
+                    }
+                    ParserState::Synced => {}
+                }
+            }
+            ParserEvent::BytesSkipped(n) => {
+                health.bytes_skipped += *n as u64;
+                metrics.bytes_skipped(direction, *n);
+            }
+            ParserEvent::ChecksumMismatch {
+                command,
+                payload_len,
+            } => {
+                health.checksum_mismatches += 1;
+                metrics.parse_error(direction, "checksum_mismatch");
+                warn!(
+                    "[conn:{}] {} Checksum mismatch in {} ({} bytes)",
+                    self.connection_id, direction, command, payload_len
+                );
+            }
+            ParserEvent::OversizePayload {
+                command,
+                payload_len,
+            } => {
+                health.oversize_payloads += 1;
+                metrics.parse_error(direction, "oversize_payload");
+                warn!(
+                    "[conn:{}] {} Oversize payload announced for {} ({} bytes)",
+                    self.connection_id, direction, command, payload_len
+                );
+            }
+            ParserEvent::UnknownCommand { command, .. } => {
+                // Newer than the parser, but the stream is fine
+                health.unknown_commands += 1;
+                metrics.unknown_command(direction);
+                debug!(
+                    "[conn:{}] {} Unknown command {}",
+                    self.connection_id, direction, command
+                );
+            }
+            ParserEvent::InvalidPayload {
+                command,
+                payload_len,
+            } => {
+                health.invalid_payloads += 1;
+                metrics.parse_error(direction, "invalid_payload");
+                warn!(
+                    "[conn:{}] {} Invalid {} payload ({} bytes)",
+                    self.connection_id, direction, command, payload_len
+                );
+            }
+        }
+    }
+
+    /// Log a parsed Bitcoin message and publish it to the app
+    fn log_message(&self, msg: BitcoinMessage, direction: Direction, outputs: &mut TxOutputCache) {
+        self.stats.record_message(direction);
+
+        self.settings
+            .metrics
+            .message(direction, msg.raw_message.payload(), msg.payload_len + 24);
+        self.handshake.observe(direction, msg.raw_message.payload());
+        if let Some(ping) = self.pings.observe(direction, &msg) {
+            debug!(
+                "[conn:{}] Ping {} answered in {}µs",
+                self.connection_id,
+                ping.nonce,
+                ping.rtt().num_microseconds().unwrap_or_default()
+            );
+            self.settings
+                .metrics
+                .ping_rtt(ping.direction, ping.rtt().to_std().unwrap_or_default());
+            self.app.publish(P2pEvent::PingMeasured(ping));
+        }
+
+        // Only what the peer announces to the node tells how fast blocks and transactions reach it
+        if direction == Direction::Outbound
+            && let Some((via, items)) = announced_items(msg.raw_message.payload())
+        {
+            self.app.publish(P2pEvent::ItemsAnnounced(ItemsAnnounced {
+                connection_id: self.connection_id,
+                node: self.settings.node.clone(),
+                via,
+                items,
+                timestamp: msg.received_at,
+            }));
+        }
+        // What the node asks the peer for, and what the peer doesn't have after all
+        if direction == Direction::Inbound
+            && let NetworkMessage::GetData(inventory) = msg.raw_message.payload()
+            && let items = inventory_items(inventory)
+            && !items.is_empty()
+        {
+            self.app.publish(P2pEvent::ItemsRequested(ItemsRequested {
+                connection_id: self.connection_id,
+                node: self.settings.node.clone(),
+                items,
+                timestamp: msg.received_at,
+            }));
+        }
+        if direction == Direction::Outbound
+            && let NetworkMessage::NotFound(inventory) = msg.raw_message.payload()
+            && let items = inventory_items(inventory)
+            && !items.is_empty()
+        {
+            self.app.publish(P2pEvent::ItemsNotFound(ItemsNotFound {
+                connection_id: self.connection_id,
+                node: self.settings.node.clone(),
+                items,
+                timestamp: msg.received_at,
+            }));
+        }
+
+        // Transactions and fee filters from the peer, for transaction relay statistics
+        if direction == Direction::Outbound {
+            match msg.raw_message.payload() {
+                NetworkMessage::Tx(tx) => {
+                    let received = outputs.received(
+                        self.connection_id,
+                        &self.settings.node,
+                        tx,
+                        msg.received_at,
+                    );
+                    self.app.publish(P2pEvent::TxReceived(received));
+                }
+                NetworkMessage::FeeFilter(fee_rate) => {
+                    self.app
+                        .publish(P2pEvent::FeeFilterReceived(FeeFilterReceived {
+                            connection_id: self.connection_id,
+                            node: self.settings.node.clone(),
+                            fee_rate: *fee_rate,
+                            timestamp: msg.received_at,
+                        }));
+                }
+                _ => {}
+            }
+        }
+
+        let description = msg.description();
         info!(
             "[conn:{}] {} {}",
-            self.connection_id, direction, msg.description()
+            self.connection_id, direction, description
         );
+
+        self.app.publish(P2pEvent::MessageSeen(MessageSeen {
+            connection_id: self.connection_id,
+            node: self.settings.node.clone(),
+            direction,
+            command: msg.command_name().to_string(),
+            payload_len: msg.payload_len,
+            description,
+            message: Arc::new(msg.raw_message.into_payload()),
+            received_at: msg.received_at,
+            forwarded_at: msg.forwarded_at,
+        }));
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use app::StorageConfig;
+    use bitcoin::p2p::message::RawNetworkMessage;
+
+    use super::*;
+    use crate::config::OverflowPolicy;
+
+    async fn inspector(dir: &tempfile::TempDir) -> Arc<Inspector> {
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+        let metrics = app.metrics().for_node("mainnet", Some("bitcoin"));
+        let settings = ConnectionSettings {
+            node: "mainnet".to_string(),
+            network: Some(Network::Bitcoin),
+            handshake_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(10),
+            metrics: metrics.clone(),
+            inspection: InspectionConfig::default(),
+            auth: None,
+            upstream: UpstreamConfig::default(),
+            inbound: false,
+        };
+        Arc::new(Inspector {
+            connection_id: 1,
+            handshake: HandshakeTracker::new(
+                1,
+                "mainnet".to_string(),
+                "127.0.0.1:50000".to_string(),
+                settings.handshake_timeout,
+                metrics,
+                app.clone(),
+            ),
+            settings,
+            stats: Arc::new(LiveStats::default()),
+            pings: PingTracker::new(1),
+            transport: OnceLock::new(),
+            network: OnceLock::new(),
+            app,
+        })
+    }
+
+    fn serialize(payload: NetworkMessage) -> Vec<u8> {
+        bitcoin::consensus::serialize(&RawNetworkMessage::new(Magic::BITCOIN, payload))
+    }
+
+    #[tokio::test]
+    async fn test_publishes_messages_in_the_order_they_were_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let inspector = inspector(&dir).await;
+        let mut events = inspector.app.subscribe();
+        let (queue, inspections) = InspectionQueue::new(8, OverflowPolicy::Drop);
+        let inspection = tokio::spawn(inspector.clone().run(inspections));
+
+        // The pong is read while the ping is still being forwarded, and forwarded first
+        let ping_at = Utc::now();
+        let ping = serialize(NetworkMessage::Ping(7));
+        let pong = serialize(NetworkMessage::Pong(7));
+        assert!(queue.push(Direction::Inbound, &ping, ping_at, false).await);
+        assert!(
+            queue
+                .push(Direction::Outbound, &pong, Utc::now(), false)
+                .await
+        );
+        queue.forwarded(Direction::Outbound, Some(Utc::now()));
+        queue.forwarded(Direction::Inbound, Some(ping_at));
+        drop(queue);
+        inspection.await.unwrap();
+
+        let mut seen = Vec::new();
+        let mut pings = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                P2pEvent::MessageSeen(msg) => seen.push((msg.direction, msg.command)),
+                P2pEvent::PingMeasured(ping) => pings.push(ping),
+                _ => {}
+            }
+        }
+        assert_eq!(
+            seen,
+            vec![
+                (Direction::Inbound, "ping".to_string()),
+                (Direction::Outbound, "pong".to_string()),
+            ]
+        );
+        assert_eq!(pings.len(), 1);
+        assert_eq!(pings[0].ping_at, ping_at);
+    }
+
+    #[tokio::test]
+    async fn test_gap_is_not_a_parse_problem() {
+        let dir = tempfile::tempdir().unwrap();
+        let inspector = inspector(&dir).await;
+        let mut events = inspector.app.subscribe();
+        let (queue, inspections) = InspectionQueue::new(8, OverflowPolicy::Drop);
+        let inspection = tokio::spawn(inspector.clone().run(inspections));
+
+        // The middle of the ping was dropped from a full queue
+        let ping = serialize(NetworkMessage::Ping(7));
+        let mut rest = ping[28..].to_vec();
+        rest.extend_from_slice(&serialize(NetworkMessage::Verack));
+        for (data, after_gap) in [(&ping[..10], false), (&rest[..], true)] {
+            assert!(
+                queue
+                    .push(Direction::Inbound, data, Utc::now(), after_gap)
+                    .await
+            );
+            queue.forwarded(Direction::Inbound, Some(Utc::now()));
+        }
+        drop(queue);
+        inspection.await.unwrap();
+
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                P2pEvent::MessageSeen(msg) => seen.push(msg.command),
+                P2pEvent::ParseHealthUpdated(health) => panic!("unexpected {:?}", health),
+                _ => {}
+            }
+        }
+        assert_eq!(seen, vec!["verack".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_command_is_not_a_parse_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let inspector = inspector(&dir).await;
+        let mut events = inspector.app.subscribe();
+        let (queue, inspections) = InspectionQueue::new(8, OverflowPolicy::Drop);
+        let inspection = tokio::spawn(inspector.clone().run(inspections));
+
+        let sendtxrcncl = serialize(NetworkMessage::Unknown {
+            command: "sendtxrcncl".try_into().unwrap(),
+            payload: vec![0; 12],
+        });
+        assert!(
+            queue
+                .push(Direction::Outbound, &sendtxrcncl, Utc::now(), false)
+                .await
+        );
+        queue.forwarded(Direction::Outbound, Some(Utc::now()));
+        drop(queue);
+        inspection.await.unwrap();
+
+        let mut health = None;
+        while let Ok(event) = events.try_recv() {
+            if let P2pEvent::ParseHealthUpdated(update) = event {
+                health = Some(update);
+            }
+        }
+        let health = health.unwrap();
+        assert_eq!(health.unknown_commands, 1);
+        assert_eq!(health.state, ParserState::Synced);
+
+        let metrics = inspector.app.metrics().render().unwrap();
+        assert!(metrics.contains("nodescope_unknown_commands_total"));
+        assert!(!metrics.contains("nodescope_parse_errors_total"));
+    }
+}
@@ -0,0 +1,305 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use app::{
+    Direction, Handshake, HandshakeFailureReason, HandshakeOutcome, HandshakeSide, NodeMetrics,
+    NodeScopeApp, P2pEvent, SendCmpctInfo, VersionInfo,
+};
+use bitcoin::p2p::message::NetworkMessage;
+use chrono::Utc;
+use tokio::time::Instant;
+use tracing::{info, warn};
+
+/// Tracks the version handshake of a single connection
+///
+/// Every state change is published to the app as a full [`Handshake`] snapshot.
+pub struct HandshakeTracker {
+    handshake: Mutex<Handshake>,
+    /// When the SOCKS5 and version handshakes together must have completed
+    deadline: Instant,
+    metrics: NodeMetrics,
+    app: NodeScopeApp,
+}
+
+impl HandshakeTracker {
+    /// Start tracking a freshly accepted connection, which has `timeout` from now to complete
+    pub fn new(
+        connection_id: u64,
+        node: String,
+        client_addr: String,
+        timeout: Duration,
+        metrics: NodeMetrics,
+        app: NodeScopeApp,
+    ) -> Self {
+        let tracker = Self {
+            handshake: Mutex::new(Handshake::new(connection_id, node, client_addr, Utc::now())),
+            deadline: Instant::now() + timeout,
+            metrics,
+            app,
+        };
+        tracker.update(|_| true);
+        tracker
+    }
+
+    /// When the connection must have completed its handshakes, counted from the accept
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Record the target requested by the client and its stream isolation credentials
+    pub fn set_target(&self, target_addr: String, stream_isolation: Option<String>) {
+        self.update(|handshake| {
+            handshake.target_addr = Some(target_addr);
+            handshake.stream_isolation = stream_isolation;
+            true
+        });
+    }
+
+    /// Inspect a message for handshake and feature negotiation fields
+    pub fn observe(&self, direction: Direction, message: &NetworkMessage) {
+        if !is_handshake_message(message) {
+            return;
+        }
+
+        self.update(|handshake| {
+            let now = Utc::now();
+            // Inbound traffic is sent by the client, outbound by the target
+            let side = match direction {
+                Direction::Inbound => &mut handshake.local,
+                Direction::Outbound => &mut handshake.remote,
+            };
+
+            match message {
+                NetworkMessage::Version(version) => {
+                    side.version = Some(VersionInfo {
+                        protocol_version: version.version,
+                        services: version.services.to_u64(),
+                        timestamp: version.timestamp,
+                        user_agent: version.user_agent.clone(),
+                        start_height: version.start_height,
+                        relay: version.relay,
+                        nonce: version.nonce,
+                        addr_from: format_address(&version.sender),
+                        addr_recv: format_address(&version.receiver),
+                        seen_at: Some(now),
+                    });
+                }
+                NetworkMessage::Verack if side.verack_at.is_none() => side.verack_at = Some(now),
+                NetworkMessage::WtxidRelay => side.wtxid_relay = true,
+                NetworkMessage::SendAddrV2 => side.send_addr_v2 = true,
+                NetworkMessage::SendCmpct(cmpct) => {
+                    side.send_cmpct = Some(SendCmpctInfo {
+                        announce: cmpct.send_compact,
+                        version: cmpct.version,
+                    });
+                }
+                _ => return false,
+            }
+
+            if handshake.outcome == HandshakeOutcome::Pending
+                && is_complete(&handshake.local)
+                && is_complete(&handshake.remote)
+            {
+                handshake.outcome = HandshakeOutcome::Completed;
+                handshake.completed_at = Some(now);
+                let duration = handshake.duration().unwrap_or_default();
+                info!(
+                    "[conn:{}] Handshake completed in {}ms",
+                    handshake.connection_id,
+                    duration.num_milliseconds()
+                );
+                self.metrics
+                    .handshake_completed(duration.to_std().unwrap_or_default());
+            }
+            true
+        });
+    }
+
+    /// Mark a still pending handshake as hidden by BIP324 encryption
+    pub fn encrypted(&self) {
+        self.update(|handshake| {
+            if handshake.outcome != HandshakeOutcome::Pending {
+                return false;
+            }
+
+            handshake.outcome = HandshakeOutcome::Encrypted;
+            true
+        });
+    }
+
+    /// Mark a still pending handshake as failed
+    pub fn fail(&self, reason: HandshakeFailureReason, detail: impl Into<String>) {
+        self.update(|handshake| {
+            if handshake.outcome != HandshakeOutcome::Pending {
+                return false;
+            }
+
+            let detail = detail.into();
+            warn!(
+                "[conn:{}] Handshake failed ({:?}): {}",
+                handshake.connection_id, reason, detail
+            );
+            handshake.outcome = HandshakeOutcome::Failed;
+            handshake.failure_reason = Some(reason);
+            handshake.failure_detail = Some(detail);
+            handshake.failed_at = Some(Utc::now());
+            self.metrics.connection_failed(reason);
+            true
+        });
+    }
+
+    /// Apply a change and publish the new state if anything changed
+    fn update(&self, f: impl FnOnce(&mut Handshake) -> bool) {
+        let mut handshake = self.handshake.lock().expect("handshake lock poisoned");
+        if f(&mut handshake) {
+            // Publish while holding the lock so snapshots can't be reordered
+            let snapshot = Box::new(handshake.clone());
+            self.app.publish(P2pEvent::HandshakeUpdated(snapshot));
+        }
+    }
+}
+
+fn is_handshake_message(message: &NetworkMessage) -> bool {
+    matches!(
+        message,
+        NetworkMessage::Version(_)
+            | NetworkMessage::Verack
+            | NetworkMessage::WtxidRelay
+            | NetworkMessage::SendAddrV2
+            | NetworkMessage::SendCmpct(_)
+    )
+}
+
+fn is_complete(side: &HandshakeSide) -> bool {
+    side.version.is_some() && side.verack_at.is_some()
+}
+
+fn format_address(address: &bitcoin::p2p::Address) -> String {
+    match address.socket_addr() {
+        Ok(addr) => addr.to_string(),
+        Err(_) => format!("{:?}", address.address),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use app::{StorageConfig, VersionInfo};
+    use bitcoin::p2p::message_network::VersionMessage;
+    use bitcoin::p2p::{Address, ServiceFlags};
+
+    use super::*;
+
+    async fn tracker(
+        dir: &tempfile::TempDir,
+        timeout: Duration,
+    ) -> (HandshakeTracker, NodeScopeApp) {
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+        let tracker = HandshakeTracker::new(
+            1,
+            "mainnet".to_string(),
+            "127.0.0.1:50000".to_string(),
+            timeout,
+            app.metrics().for_node("mainnet", Some("bitcoin")),
+            app.clone(),
+        );
+        (tracker, app)
+    }
+
+    fn version(user_agent: &str) -> NetworkMessage {
+        let addr: SocketAddr = "1.2.3.4:8333".parse().unwrap();
+        let address = Address::new(&addr, ServiceFlags::NETWORK);
+        NetworkMessage::Version(VersionMessage::new(
+            ServiceFlags::NETWORK,
+            0,
+            address.clone(),
+            address,
+            7,
+            user_agent.to_string(),
+            850_000,
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_completes_once_both_sides_sent_verack() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tracker, app) = tracker(&dir, Duration::from_secs(10)).await;
+        let mut events = app.subscribe();
+
+        tracker.set_target("1.2.3.4:8333".to_string(), Some("0".to_string()));
+        tracker.observe(Direction::Inbound, &version("/Satoshi:27.0.0/"));
+        tracker.observe(Direction::Outbound, &version("/Satoshi:26.0.0/"));
+        tracker.observe(Direction::Outbound, &NetworkMessage::WtxidRelay);
+        // Not part of the handshake, so nothing is published
+        tracker.observe(Direction::Outbound, &NetworkMessage::Ping(1));
+        tracker.observe(Direction::Outbound, &NetworkMessage::Verack);
+        tracker.observe(Direction::Inbound, &NetworkMessage::Verack);
+        // Too late to fail a completed handshake
+        tracker.fail(HandshakeFailureReason::Timeout, "no verack");
+
+        let mut snapshots = Vec::new();
+        while let Ok(P2pEvent::HandshakeUpdated(handshake)) = events.try_recv() {
+            snapshots.push(handshake);
+        }
+        assert_eq!(snapshots.len(), 6);
+
+        let handshake = snapshots.pop().unwrap();
+        assert_eq!(handshake.outcome, HandshakeOutcome::Completed);
+        assert_eq!(handshake.target_addr.as_deref(), Some("1.2.3.4:8333"));
+        assert_eq!(handshake.stream_isolation.as_deref(), Some("0"));
+        assert!(handshake.completed_at.is_some());
+        assert!(handshake.remote.wtxid_relay);
+        assert!(!handshake.local.wtxid_relay);
+        let VersionInfo { user_agent, .. } = handshake.local.version.unwrap();
+        assert_eq!(user_agent, "/Satoshi:27.0.0/");
+        assert_eq!(
+            handshake.remote.version.unwrap().user_agent,
+            "/Satoshi:26.0.0/"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fails_pending_handshake_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tracker, app) = tracker(&dir, Duration::from_secs(10)).await;
+        let mut events = app.subscribe();
+
+        tracker.observe(Direction::Inbound, &version("/Satoshi:27.0.0/"));
+        tracker.fail(HandshakeFailureReason::Timeout, "no verack");
+        tracker.fail(
+            HandshakeFailureReason::DisconnectedBeforeVerack,
+            "connection closed",
+        );
+
+        let mut last = None;
+        let mut published = 0;
+        while let Ok(P2pEvent::HandshakeUpdated(handshake)) = events.try_recv() {
+            published += 1;
+            last = Some(handshake);
+        }
+        assert_eq!(published, 2);
+        let handshake = last.unwrap();
+        assert_eq!(handshake.outcome, HandshakeOutcome::Failed);
+        assert_eq!(
+            handshake.failure_reason,
+            Some(HandshakeFailureReason::Timeout)
+        );
+        assert_eq!(handshake.failure_detail.as_deref(), Some("no verack"));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_counts_from_accept() {
+        let dir = tempfile::tempdir().unwrap();
+        let (tracker, _app) = tracker(&dir, Duration::from_millis(300)).await;
+
+        // Time spent before the version handshake, e.g. on SOCKS5, is taken from the timeout
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let remaining = tracker.deadline() - Instant::now();
+        assert!(remaining <= Duration::from_millis(200));
+    }
+}
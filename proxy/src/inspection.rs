@@ -0,0 +1,154 @@
+use std::sync::Arc;
+
+use app::Direction;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, mpsc};
+
+use crate::config::OverflowPolicy;
+
+/// Forwarded data of both directions of a connection, queued for inspection
+///
+/// Data is queued in the order it was read, before it is forwarded, so a
+/// message is always queued before anything answering it. Only chunks take room
+/// in the queue, the times they were forwarded are always queued after them.
+#[derive(Clone)]
+pub struct InspectionQueue {
+    sender: mpsc::UnboundedSender<Inspection>,
+    room: Arc<Semaphore>,
+    overflow: OverflowPolicy,
+}
+
+/// An item of the inspection queue
+pub enum Inspection {
+    Chunk(Chunk),
+    /// The last chunk queued for the direction was forwarded, at the given time if it succeeded
+    Forwarded {
+        direction: Direction,
+        at: Option<DateTime<Utc>>,
+    },
+}
+
+/// A copy of data read from one side, queued for inspection
+pub struct Chunk {
+    pub direction: Direction,
+    pub data: Bytes,
+    pub received_at: DateTime<Utc>,
+    /// Data of the direction before this chunk wasn't inspected, so the parser has to start over
+    pub after_gap: bool,
+    /// Room the chunk takes in the queue, until what was parsed from it is published
+    pub room: OwnedSemaphorePermit,
+}
+
+impl InspectionQueue {
+    /// Create a queue with room for `chunks` chunks
+    pub fn new(
+        chunks: usize,
+        overflow: OverflowPolicy,
+    ) -> (Self, mpsc::UnboundedReceiver<Inspection>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let queue = Self {
+            sender,
+            room: Arc::new(Semaphore::new(chunks.max(1))),
+            overflow,
+        };
+        (queue, receiver)
+    }
+
+    /// Queue data read from one side, before forwarding it
+    ///
+    /// Returns false when the data wasn't queued, as the queue is full and the
+    /// overflow policy is to drop, or the inspector is gone.
+    pub async fn push(
+        &self,
+        direction: Direction,
+        data: &[u8],
+        received_at: DateTime<Utc>,
+        after_gap: bool,
+    ) -> bool {
+        let room = match self.overflow {
+            OverflowPolicy::Backpressure => self.room.clone().acquire_owned().await.ok(),
+            OverflowPolicy::Drop => self.room.clone().try_acquire_owned().ok(),
+        };
+        let Some(room) = room else {
+            return false;
+        };
+
+        self.sender
+            .send(Inspection::Chunk(Chunk {
+                direction,
+                data: Bytes::copy_from_slice(data),
+                received_at,
+                after_gap,
+                room,
+            }))
+            .is_ok()
+    }
+
+    /// Report that the last chunk queued for a direction was forwarded
+    pub fn forwarded(&self, direction: Direction, at: Option<DateTime<Utc>>) {
+        // Only fails once the inspector is gone, which ends the connection anyway
+        let _ = self.sender.send(Inspection::Forwarded { direction, at });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn chunk(inspection: Option<Inspection>) -> Chunk {
+        match inspection {
+            Some(Inspection::Chunk(chunk)) => chunk,
+            _ => panic!("expected a chunk"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drops_chunks_that_dont_fit() {
+        let (queue, mut inspections) = InspectionQueue::new(1, OverflowPolicy::Drop);
+        let now = Utc::now();
+
+        assert!(queue.push(Direction::Inbound, b"ping", now, false).await);
+        // Forwarding times don't take room
+        queue.forwarded(Direction::Inbound, Some(now));
+        assert!(!queue.push(Direction::Outbound, b"pong", now, false).await);
+
+        // Room is given back once the chunk is done with
+        let first = chunk(inspections.recv().await);
+        assert_eq!(&first.data[..], b"ping");
+        assert!(matches!(
+            inspections.recv().await,
+            Some(Inspection::Forwarded {
+                direction: Direction::Inbound,
+                at: Some(_)
+            })
+        ));
+        drop(first);
+        assert!(queue.push(Direction::Outbound, b"pong", now, true).await);
+        assert!(chunk(inspections.recv().await).after_gap);
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_room_with_backpressure() {
+        let (queue, mut inspections) = InspectionQueue::new(1, OverflowPolicy::Backpressure);
+        let now = Utc::now();
+
+        assert!(queue.push(Direction::Inbound, b"ping", now, false).await);
+        let waiting = queue.push(Direction::Outbound, b"pong", now, false);
+        tokio::pin!(waiting);
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), &mut waiting)
+                .await
+                .is_err()
+        );
+
+        drop(chunk(inspections.recv().await));
+        assert!(waiting.await);
+        assert_eq!(
+            chunk(inspections.recv().await).direction,
+            Direction::Outbound
+        );
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use app::{Direction, PingMeasured};
+use bitcoin::p2p::message::NetworkMessage;
+use chrono::{DateTime, Utc};
+
+use crate::bitcoin_protocol::BitcoinMessage;
+
+/// Unanswered pings kept per connection before the oldest are forgotten
+const MAX_PENDING_PINGS: usize = 32;
+
+/// Matches pings with pongs travelling the other way to measure round trips
+pub struct PingTracker {
+    connection_id: u64,
+    pending: Mutex<HashMap<(Direction, u64), DateTime<Utc>>>,
+}
+
+impl PingTracker {
+    pub fn new(connection_id: u64) -> Self {
+        Self {
+            connection_id,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Inspect a forwarded message, returning a measurement when it answers a ping
+    ///
+    /// Round trips start when the ping was forwarded and end when the pong was
+    /// received, so they exclude the time spent inside the proxy.
+    pub fn observe(&self, direction: Direction, msg: &BitcoinMessage) -> Option<PingMeasured> {
+        let mut pending = self.pending.lock().expect("ping tracker lock poisoned");
+        match msg.raw_message.payload() {
+            NetworkMessage::Ping(nonce) => {
+                if pending.len() >= MAX_PENDING_PINGS
+                    && let Some(oldest) = pending
+                        .iter()
+                        .min_by_key(|(_, at)| **at)
+                        .map(|(key, _)| *key)
+                {
+                    pending.remove(&oldest);
+                }
+                let ping_at = msg.forwarded_at.unwrap_or(msg.received_at);
+                pending.insert((direction, *nonce), ping_at);
+                None
+            }
+            NetworkMessage::Pong(nonce) => {
+                let ping_direction = match direction {
+                    Direction::Inbound => Direction::Outbound,
+                    Direction::Outbound => Direction::Inbound,
+                };
+                let ping_at = pending.remove(&(ping_direction, *nonce))?;
+                Some(PingMeasured {
+                    connection_id: self.connection_id,
+                    direction: ping_direction,
+                    nonce: *nonce,
+                    ping_at,
+                    pong_at: msg.received_at,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitcoin_protocol::Network;
+    use bitcoin::p2p::Magic;
+    use bitcoin::p2p::message::RawNetworkMessage;
+
+    fn message(payload: NetworkMessage, received_at: DateTime<Utc>) -> BitcoinMessage {
+        BitcoinMessage {
+            network: Network::Bitcoin,
+            command: payload.command(),
+            payload_len: 8,
+            raw_message: RawNetworkMessage::new(Magic::BITCOIN, payload),
+            received_at,
+            forwarded_at: Some(received_at),
+        }
+    }
+
+    #[test]
+    fn test_matches_pong_from_the_other_side() {
+        let tracker = PingTracker::new(1);
+        let start = Utc::now();
+        let later = start + chrono::Duration::milliseconds(40);
+
+        assert!(
+            tracker
+                .observe(Direction::Inbound, &message(NetworkMessage::Ping(7), start))
+                .is_none()
+        );
+        // A pong in the same direction doesn't answer the ping
+        assert!(
+            tracker
+                .observe(Direction::Inbound, &message(NetworkMessage::Pong(7), later))
+                .is_none()
+        );
+
+        let measured = tracker
+            .observe(
+                Direction::Outbound,
+                &message(NetworkMessage::Pong(7), later),
+            )
+            .unwrap();
+        assert_eq!(measured.direction, Direction::Inbound);
+        assert_eq!(measured.rtt().num_milliseconds(), 40);
+
+        // Each ping is only answered once
+        assert!(
+            tracker
+                .observe(
+                    Direction::Outbound,
+                    &message(NetworkMessage::Pong(7), later)
+                )
+                .is_none()
+        );
+    }
+}
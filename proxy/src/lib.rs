@@ -1,59 +1,129 @@
-mod bitcoin_protocol;
+mod announcement;
+pub mod bitcoin_protocol;
 mod config;
 mod connection;
-mod socks5;
+mod handshake;
+mod inspection;
+mod latency;
+mod relay;
+pub mod socks5;
+mod transport;
+mod upstream;
 
-pub use config::ProxyConfig;
+pub use config::{AuthConfig, ProxyConfig};
 
-use app::NodeScopeApp;
-use connection::ConnectionHandler;
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::sync::Arc;
+use app::{
+    ConnectError, ConnectionFailed, HandshakeFailureReason, NetworkType, NodeScopeApp, P2pEvent,
+    ShutdownPhase,
+};
+use connection::{ConnectionHandler, ConnectionSettings};
+use handshake::HandshakeTracker;
+use socks5::Reply;
+use std::collections::HashSet;
+use std::io;
+use std::time::Duration;
 use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinSet;
 use tracing::{error, info, warn};
 
 /// Bitcoin P2P Proxy Server
 pub struct ProxyServer {
     config: ProxyConfig,
-    _app: NodeScopeApp,
-    connection_counter: Arc<AtomicU64>,
+    app: NodeScopeApp,
 }
 
 impl ProxyServer {
     pub fn new(config: ProxyConfig, app: NodeScopeApp) -> Self {
-        Self {
-            config,
-            _app: app,
-            connection_counter: Arc::new(AtomicU64::new(0)),
-        }
+        Self { config, app }
     }
 
-    /// Start the proxy server
+    /// Start the proxy server, which runs until the app starts shutting down
     pub async fn start(&self) -> anyhow::Result<()> {
-        let bind_addr = format!("0.0.0.0:{}", self.config.port);
-        let listener = TcpListener::bind(&bind_addr).await?;
+        let listener = TcpListener::bind(self.config.bind).await?;
 
         info!(
-            "Bitcoin SOCKS5 Proxy listening on {} (network: {:?})",
-            bind_addr, self.config.network
+            "[node:{}] Bitcoin SOCKS5 Proxy listening on {} (network: {:?})",
+            self.config.node, self.config.bind, self.config.network
         );
 
-        let network: bitcoin_protocol::Network = self.config.network.into();
+        let settings = ConnectionSettings {
+            node: self.config.node.clone(),
+            network: self.config.network.network(),
+            handshake_timeout: Duration::from_secs(self.config.handshake_timeout_secs),
+            connect_timeout: Duration::from_secs(self.config.connect_timeout_secs),
+            metrics: self
+                .app
+                .metrics()
+                .for_node(&self.config.node, self.config.network.label()),
+            inspection: self.config.inspection.clone(),
+            auth: self.config.auth.clone(),
+            upstream: self.config.upstream.clone(),
+            inbound: false,
+        };
+
+        let Some(transparent) = &self.config.transparent else {
+            self.accept_socks5(listener, settings).await;
+            return Ok(());
+        };
+
+        let inbound_listener = TcpListener::bind(transparent.bind).await?;
+        info!(
+            "[node:{}] Transparent proxy for inbound peers listening on {}, forwarding to {}",
+            self.config.node, transparent.bind, transparent.node_addr
+        );
 
+        let inbound_settings = ConnectionSettings {
+            inbound: true,
+            ..settings.clone()
+        };
+        tokio::join!(
+            self.accept_socks5(listener, settings),
+            self.accept_inbound(inbound_listener, inbound_settings, &transparent.node_addr),
+        );
+        Ok(())
+    }
+
+    /// Accept connections from the node, which name their target over SOCKS5
+    async fn accept_socks5(&self, listener: TcpListener, settings: ConnectionSettings) {
+        let draining = self.app.shutdown().reached(ShutdownPhase::Draining);
+        tokio::pin!(draining);
         loop {
-            match listener.accept().await {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut draining => {
+                    info!("[node:{}] Stopped accepting connections", settings.node);
+                    break;
+                }
+            };
+            match accepted {
                 Ok((client_stream, client_addr)) => {
-                    let connection_id = self.connection_counter.fetch_add(1, Ordering::SeqCst);
+                    let connection_id = self.app.next_connection_id();
 
                     info!(
                         "[conn:{}] New connection from {}",
                         connection_id, client_addr
                     );
 
+                    let settings = settings.for_connection();
                     // Spawn a task to handle this connection
+                    let handshake = HandshakeTracker::new(
+                        connection_id,
+                        settings.node.clone(),
+                        client_addr.to_string(),
+                        settings.handshake_timeout,
+                        settings.metrics.clone(),
+                        self.app.clone(),
+                    );
+                    let app = self.app.clone();
                     tokio::spawn(async move {
-                        if let Err(e) =
-                            handle_connection(connection_id, client_stream, network).await
+                        if let Err(e) = handle_connection(
+                            connection_id,
+                            client_stream,
+                            settings,
+                            handshake,
+                            app,
+                        )
+                        .await
                         {
                             error!("[conn:{}] Connection error: {}", connection_id, e);
                         }
@@ -65,39 +135,371 @@ impl ProxyServer {
             }
         }
     }
+
+    /// Accept connections from peers, which are all forwarded to the node
+    async fn accept_inbound(
+        &self,
+        listener: TcpListener,
+        settings: ConnectionSettings,
+        node_addr: &str,
+    ) {
+        let draining = self.app.shutdown().reached(ShutdownPhase::Draining);
+        tokio::pin!(draining);
+        loop {
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = &mut draining => {
+                    info!("[node:{}] Stopped accepting inbound peers", settings.node);
+                    break;
+                }
+            };
+            match accepted {
+                Ok((peer_stream, peer_addr)) => {
+                    let connection_id = self.app.next_connection_id();
+
+                    info!(
+                        "[conn:{}] New inbound connection from {}",
+                        connection_id, peer_addr
+                    );
+
+                    let settings = settings.for_connection();
+                    // The node is the client of inbound connections too
+                    let handshake = HandshakeTracker::new(
+                        connection_id,
+                        settings.node.clone(),
+                        node_addr.to_string(),
+                        settings.handshake_timeout,
+                        settings.metrics.clone(),
+                        self.app.clone(),
+                    );
+                    handshake.set_target(peer_addr.ip().to_string(), None);
+                    let node_addr = node_addr.to_string();
+                    let app = self.app.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_inbound_connection(
+                            connection_id,
+                            peer_stream,
+                            node_addr,
+                            settings,
+                            handshake,
+                            app,
+                        )
+                        .await
+                        {
+                            error!("[conn:{}] Connection error: {}", connection_id, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!("Failed to accept inbound connection: {}", e);
+                }
+            }
+        }
+    }
 }
 
 /// Handle a single SOCKS5 proxied connection
 async fn handle_connection(
     connection_id: u64,
     mut client_stream: TcpStream,
-    network: bitcoin_protocol::Network,
+    settings: ConnectionSettings,
+    handshake: HandshakeTracker,
+    app: NodeScopeApp,
 ) -> anyhow::Result<()> {
     let client_addr = client_stream.peer_addr()?.to_string();
 
-    // Handle SOCKS5 handshake
-    let socks5_req = socks5::handle_socks5_handshake(&mut client_stream, connection_id).await?;
+    // Handle SOCKS5 handshake, which counts toward the handshake timeout
+    let socks5_req = match tokio::time::timeout_at(
+        handshake.deadline(),
+        socks5::handle_socks5_handshake(&mut client_stream, connection_id, settings.auth.as_ref()),
+    )
+    .await
+    {
+        Ok(Ok(req)) => req,
+        Ok(Err(e)) => {
+            handshake.fail(HandshakeFailureReason::Socks5Error, format!("{:#}", e));
+            return Err(e);
+        }
+        Err(_) => {
+            handshake.fail(
+                HandshakeFailureReason::Timeout,
+                "SOCKS5 negotiation timed out",
+            );
+            anyhow::bail!("SOCKS5 negotiation timed out");
+        }
+    };
     let target = socks5_req.to_string();
+    handshake.set_target(target.clone(), socks5_req.stream_isolation.clone());
 
-    // Connect to the requested target
-    let target_stream = match TcpStream::connect(&target).await {
-        Ok(stream) => stream,
+    // Connect to the requested target before telling the client how it went
+    let connect = tokio::time::timeout(
+        settings.connect_timeout,
+        upstream::connect(&settings.upstream, &socks5_req),
+    )
+    .await
+    .unwrap_or_else(|_| {
+        Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!(
+                "no connection within {}s",
+                settings.connect_timeout.as_secs()
+            ),
+        ))
+    });
+    let (target_stream, network_type) = match connect {
+        Ok(connected) => connected,
         Err(e) => {
+            let error = ConnectError::from(e.kind());
             warn!(
-                "[conn:{}] Failed to connect to target {}: {}",
-                connection_id, target, e
+                "[conn:{}] Failed to connect to target {} ({}): {}",
+                connection_id,
+                target,
+                error.as_str(),
+                e
             );
+            let _ = socks5::send_reply(&mut client_stream, error.into(), None).await;
+            handshake.fail(HandshakeFailureReason::ConnectFailed, e.to_string());
+            settings.metrics.connect_failed(error);
+            app.publish(P2pEvent::ConnectionFailed(ConnectionFailed {
+                connection_id,
+                node: settings.node.clone(),
+                client_addr,
+                target_addr: target,
+                network: settings.network,
+                error,
+                detail: e.to_string(),
+                timestamp: chrono::Utc::now(),
+            }));
             return Err(e.into());
         }
     };
 
+    // Only now the client learns that its request succeeded
+    let bound_addr = target_stream.local_addr().ok();
+    if let Err(e) = socks5::send_reply(&mut client_stream, Reply::Succeeded, bound_addr).await {
+        handshake.fail(HandshakeFailureReason::Socks5Error, format!("{:#}", e));
+        return Err(e);
+    }
+
     // Create and run the connection handler
-    let handler = ConnectionHandler::new(connection_id, client_addr, target, network);
+    let handler = ConnectionHandler::new(
+        connection_id,
+        client_addr,
+        target,
+        network_type,
+        settings,
+        handshake,
+        app,
+    );
     handler.handle(client_stream, target_stream).await
 }
 
-/// Run the proxy server (public API)
-pub async fn run(config: ProxyConfig, app: NodeScopeApp) -> anyhow::Result<()> {
-    let server = ProxyServer::new(config, app);
-    server.start().await
+/// Handle a peer connecting to the transparent listener, forwarding it to the node
+async fn handle_inbound_connection(
+    connection_id: u64,
+    peer_stream: TcpStream,
+    node_addr: String,
+    settings: ConnectionSettings,
+    handshake: HandshakeTracker,
+    app: NodeScopeApp,
+) -> anyhow::Result<()> {
+    let peer_addr = peer_stream.peer_addr()?;
+
+    let connect = tokio::time::timeout(settings.connect_timeout, TcpStream::connect(&node_addr))
+        .await
+        .unwrap_or_else(|_| {
+            Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!(
+                    "no connection within {}s",
+                    settings.connect_timeout.as_secs()
+                ),
+            ))
+        });
+    let node_stream = match connect {
+        Ok(stream) => stream,
+        Err(e) => {
+            let error = ConnectError::from(e.kind());
+            warn!(
+                "[conn:{}] Failed to connect to node {} ({}): {}",
+                connection_id,
+                node_addr,
+                error.as_str(),
+                e
+            );
+            handshake.fail(HandshakeFailureReason::ConnectFailed, e.to_string());
+            settings.metrics.connect_failed(error);
+            app.publish(P2pEvent::ConnectionFailed(ConnectionFailed {
+                connection_id,
+                node: settings.node.clone(),
+                client_addr: node_addr,
+                target_addr: peer_addr.ip().to_string(),
+                network: settings.network,
+                error,
+                detail: e.to_string(),
+                timestamp: chrono::Utc::now(),
+            }));
+            return Err(e.into());
+        }
+    };
+
+    // Peers are known by their IP, the port they connected from changes every time
+    let handler = ConnectionHandler::new(
+        connection_id,
+        node_addr,
+        peer_addr.ip().to_string(),
+        NetworkType::of_ip(peer_addr.ip()),
+        settings,
+        handshake,
+        app,
+    );
+    handler.handle(node_stream, peer_stream).await
+}
+
+/// Run a proxy server for each configured node (public API)
+///
+/// Returns once all servers stopped accepting connections. Connections that are
+/// still open keep running until [`NodeScopeApp::drain_connections`] closes them.
+pub async fn run(configs: Vec<ProxyConfig>, app: NodeScopeApp) -> anyhow::Result<()> {
+    let mut nodes = HashSet::new();
+    for config in &configs {
+        if !nodes.insert(config.node.as_str()) {
+            anyhow::bail!("node {:?} is configured more than once", config.node);
+        }
+    }
+
+    let mut servers = JoinSet::new();
+    for config in configs {
+        let server = ProxyServer::new(config, app.clone());
+        servers.spawn(async move { server.start().await });
+    }
+
+    let mut result = Ok(());
+    while let Some(joined) = servers.join_next().await {
+        if let Err(e) = joined
+            .map_err(anyhow::Error::from)
+            .and_then(|started| started)
+        {
+            // Stop the other servers too, keeping the first error
+            app.shutdown().drain();
+            if result.is_ok() {
+                result = Err(e);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use app::{Direction, StorageConfig};
+    use bitcoin::p2p::message::{NetworkMessage, RawNetworkMessage};
+    use bitcoin::p2p::message_network::VersionMessage;
+    use bitcoin::p2p::{Address, Magic, ServiceFlags};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+    use crate::bitcoin_protocol::Network;
+    use crate::config::{InspectionConfig, UpstreamConfig};
+
+    fn serialize(payload: NetworkMessage) -> Vec<u8> {
+        bitcoin::consensus::serialize(&RawNetworkMessage::new(Magic::BITCOIN, payload))
+    }
+
+    #[tokio::test]
+    async fn test_forwards_inbound_peers_to_the_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+        let mut events = app.subscribe();
+        let metrics = app.metrics().for_node("mainnet", Some("bitcoin"));
+        let settings = ConnectionSettings {
+            node: "mainnet".to_string(),
+            network: Some(Network::Bitcoin),
+            handshake_timeout: Duration::from_secs(10),
+            connect_timeout: Duration::from_secs(10),
+            metrics: metrics.clone(),
+            inspection: InspectionConfig::default(),
+            auth: None,
+            upstream: UpstreamConfig::default(),
+            inbound: true,
+        };
+
+        let node = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let node_addr = node.local_addr().unwrap().to_string();
+        let transparent = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut peer = TcpStream::connect(transparent.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (peer_stream, _) = transparent.accept().await.unwrap();
+        let handshake = HandshakeTracker::new(
+            1,
+            "mainnet".to_string(),
+            node_addr.clone(),
+            settings.handshake_timeout,
+            metrics,
+            app.clone(),
+        );
+        let handler = tokio::spawn(handle_inbound_connection(
+            1,
+            peer_stream,
+            node_addr.clone(),
+            settings,
+            handshake,
+            app.clone(),
+        ));
+        let (mut node_side, proxy_addr) = node.accept().await.unwrap();
+
+        // The peer opened the connection, so it sends its version first
+        let address = Address::new(&peer.local_addr().unwrap(), ServiceFlags::NETWORK);
+        let version = serialize(NetworkMessage::Version(VersionMessage::new(
+            ServiceFlags::NETWORK,
+            0,
+            address.clone(),
+            address,
+            7,
+            "/Satoshi:27.0.0/".to_string(),
+            850_000,
+        )));
+        let verack = serialize(NetworkMessage::Verack);
+        peer.write_all(&version).await.unwrap();
+        let mut received = vec![0; version.len()];
+        node_side.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, version);
+        node_side.write_all(&verack).await.unwrap();
+        let mut received = vec![0; verack.len()];
+        peer.read_exact(&mut received).await.unwrap();
+        assert_eq!(received, verack);
+        drop(peer);
+        drop(node_side);
+        handler.await.unwrap().unwrap();
+
+        let mut opened = None;
+        let mut seen = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            match event {
+                P2pEvent::ConnectionOpened(event) => opened = Some(event),
+                P2pEvent::MessageSeen(msg) => seen.push((msg.direction, msg.command)),
+                _ => {}
+            }
+        }
+        let opened = opened.unwrap();
+        assert!(opened.inbound);
+        assert_eq!(opened.client_addr, node_addr);
+        assert_eq!(opened.target_addr, "127.0.0.1");
+        assert!(opened.peer_port.is_some());
+        assert_eq!(opened.local_addr, Some(proxy_addr.to_string()));
+
+        // The node is still the client, so what the peer sends is outbound
+        assert_eq!(
+            seen,
+            vec![
+                (Direction::Outbound, "version".to_string()),
+                (Direction::Inbound, "verack".to_string()),
+            ]
+        );
+    }
 }
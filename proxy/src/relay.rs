@@ -0,0 +1,173 @@
+use std::collections::{HashMap, VecDeque};
+
+use app::TxReceived;
+use bitcoin::{Transaction, Txid};
+use chrono::{DateTime, Utc};
+
+/// Transactions of a connection whose output values are remembered to compute
+/// the fees of their children
+const MAX_CACHED_TRANSACTIONS: usize = 5_000;
+
+/// Output values of the transactions a peer relayed on one connection
+///
+/// Fees are only known for transactions spending outputs of transactions the
+/// same peer relayed earlier, the values of confirmed outputs aren't. Outputs
+/// aren't shared with other connections: the node may reject what a peer sent,
+/// so another peer's fees mustn't rely on it.
+#[derive(Default)]
+pub struct TxOutputCache {
+    values: HashMap<Txid, Vec<u64>>,
+    /// Oldest first, to evict when full
+    order: VecDeque<Txid>,
+}
+
+impl TxOutputCache {
+    /// Describe a transaction the peer delivered and remember its outputs
+    pub fn received(
+        &mut self,
+        connection_id: u64,
+        node: &str,
+        tx: &Transaction,
+        timestamp: DateTime<Utc>,
+    ) -> TxReceived {
+        let txid = tx.compute_txid();
+
+        // Values beyond the money supply don't sum up, leaving the fee unknown
+        let inputs = tx.input.iter().try_fold(0u64, |sum, input| {
+            let value = self
+                .values
+                .get(&input.previous_output.txid)?
+                .get(input.previous_output.vout as usize)?;
+            sum.checked_add(*value)
+        });
+        let outputs = tx
+            .output
+            .iter()
+            .try_fold(0u64, |sum, output| sum.checked_add(output.value.to_sat()));
+        // Coinbase transactions aren't relayed, and have no inputs to be cached
+        let fee = inputs
+            .filter(|_| !tx.input.is_empty())
+            .zip(outputs)
+            .and_then(|(inputs, outputs)| inputs.checked_sub(outputs));
+
+        if !self.values.contains_key(&txid) {
+            if self.order.len() >= MAX_CACHED_TRANSACTIONS
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.values.remove(&oldest);
+            }
+            let values = tx
+                .output
+                .iter()
+                .map(|output| output.value.to_sat())
+                .collect();
+            self.values.insert(txid, values);
+            self.order.push_back(txid);
+        }
+
+        TxReceived {
+            connection_id,
+            node: node.to_string(),
+            txid: txid.to_string(),
+            wtxid: tx.compute_wtxid().to_string(),
+            vsize: tx.vsize() as u64,
+            fee,
+            timestamp,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxIn, TxOut, Witness};
+
+    fn spending(previous_outputs: &[OutPoint], values: &[u64]) -> Transaction {
+        Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: previous_outputs
+                .iter()
+                .map(|previous_output| TxIn {
+                    previous_output: *previous_output,
+                    witness: Witness::from_slice(&[[1u8; 72]]),
+                    ..Default::default()
+                })
+                .collect(),
+            output: values
+                .iter()
+                .map(|value| TxOut {
+                    value: Amount::from_sat(*value),
+                    script_pubkey: ScriptBuf::new(),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_computes_fees_of_known_inputs() {
+        let mut cache = TxOutputCache::default();
+        let now = Utc::now();
+
+        // Spends a confirmed output, whose value is unknown
+        let parent = spending(&[OutPoint::default()], &[50_000, 20_000]);
+        let received = cache.received(1, "mainnet", &parent, now);
+        assert_eq!(received.fee, None);
+        assert_eq!(received.txid, parent.compute_txid().to_string());
+        assert_ne!(received.wtxid, received.txid);
+
+        let child = spending(&[OutPoint::new(parent.compute_txid(), 1)], &[19_000]);
+        let received = cache.received(1, "mainnet", &child, now);
+        assert_eq!(received.fee, Some(1_000));
+        assert_eq!(received.vsize, child.vsize() as u64);
+        assert!(received.fee_rate().unwrap() > 1.0);
+
+        // An output the parent doesn't have
+        let invalid = spending(&[OutPoint::new(parent.compute_txid(), 2)], &[1_000]);
+        assert_eq!(cache.received(1, "mainnet", &invalid, now).fee, None);
+    }
+
+    #[test]
+    fn test_forgets_oldest_transactions() {
+        let mut cache = TxOutputCache::default();
+        let now = Utc::now();
+
+        let parents: Vec<_> = (0..=MAX_CACHED_TRANSACTIONS as u64)
+            .map(|value| spending(&[OutPoint::default()], &[10_000 + value]))
+            .collect();
+        for parent in &parents {
+            cache.received(1, "mainnet", parent, now);
+        }
+
+        let spend =
+            |parent: &Transaction| spending(&[OutPoint::new(parent.compute_txid(), 0)], &[1_000]);
+        // The child is remembered too, evicting the next parent after its fee is known
+        assert_eq!(
+            cache.received(1, "mainnet", &spend(&parents[1]), now).fee,
+            Some(9_001)
+        );
+        assert_eq!(
+            cache.received(1, "mainnet", &spend(&parents[0]), now).fee,
+            None
+        );
+    }
+
+    #[test]
+    fn test_overflowing_values_leave_fee_unknown() {
+        let mut cache = TxOutputCache::default();
+        let now = Utc::now();
+
+        let parent = spending(&[OutPoint::default()], &[u64::MAX, 1]);
+        let received = cache.received(1, "mainnet", &parent, now);
+        assert_eq!(received.fee, None);
+
+        let inputs = [0, 1].map(|vout| OutPoint::new(parent.compute_txid(), vout));
+        let child = spending(&inputs, &[1_000]);
+        assert_eq!(cache.received(1, "mainnet", &child, now).fee, None);
+
+        let child = spending(&inputs[..1], &[u64::MAX, 1]);
+        assert_eq!(cache.received(1, "mainnet", &child, now).fee, None);
+    }
+}
@@ -1,175 +1,604 @@
-use anyhow::{anyhow, Context, Result};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{Context, Result, anyhow};
+use app::ConnectError;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tracing::{debug, info};
 
+use crate::config::AuthConfig;
+
 /// SOCKS5 protocol constants
-const SOCKS5_VERSION: u8 = 0x05;
-const SOCKS5_NO_AUTH: u8 = 0x00;
+pub(crate) const SOCKS5_VERSION: u8 = 0x05;
+pub(crate) const SOCKS5_NO_AUTH: u8 = 0x00;
+pub(crate) const SOCKS5_USER_PASS: u8 = 0x02;
+const SOCKS5_NO_ACCEPTABLE_METHODS: u8 = 0xff;
 const SOCKS5_CMD_CONNECT: u8 = 0x01;
 const SOCKS5_ATYP_IPV4: u8 = 0x01;
 const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
 const SOCKS5_ATYP_IPV6: u8 = 0x04;
-const SOCKS5_SUCCESS: u8 = 0x00;
-const SOCKS5_GENERAL_FAILURE: u8 = 0x01;
+
+/// Username/password subnegotiation constants (RFC 1929)
+pub(crate) const USER_PASS_VERSION: u8 = 0x01;
+pub(crate) const USER_PASS_SUCCESS: u8 = 0x00;
+const USER_PASS_FAILURE: u8 = 0x01;
 
 /// Represents a parsed SOCKS5 connection request
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Socks5Request {
     pub target_addr: String,
     pub target_port: u16,
+    /// Username the client picked itself, Bitcoin Core randomizes it per
+    /// connection with `-proxyrandomize` to isolate its Tor circuits
+    ///
+    /// Only recorded when the proxy has no credentials configured: the client
+    /// then has to send the configured ones, which isolate nothing.
+    pub stream_isolation: Option<String>,
 }
 
-impl std::fmt::Display for Socks5Request {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}", self.target_addr, self.target_port)
+impl fmt::Display for Socks5Request {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.target_addr.contains(':') {
+            write!(f, "[{}]:{}", self.target_addr, self.target_port)
+        } else {
+            write!(f, "{}:{}", self.target_addr, self.target_port)
+        }
     }
 }
 
-/// Handle SOCKS5 handshake and return the target address
-pub async fn handle_socks5_handshake(
-    stream: &mut TcpStream,
-    connection_id: u64,
-) -> Result<Socks5Request> {
-    // Step 1: Read client greeting
-    let mut buf = [0u8; 257];
-    let n = stream
-        .read(&mut buf)
-        .await
-        .context("Failed to read SOCKS5 greeting")?;
+/// Username and password sent by the client (RFC 1929)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub username: Vec<u8>,
+    pub password: Vec<u8>,
+}
+
+/// Reply codes of a SOCKS5 request (RFC 1928)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reply {
+    Succeeded = 0x00,
+    GeneralFailure = 0x01,
+    NotAllowed = 0x02,
+    NetworkUnreachable = 0x03,
+    HostUnreachable = 0x04,
+    ConnectionRefused = 0x05,
+    TtlExpired = 0x06,
+    CommandNotSupported = 0x07,
+    AddressTypeNotSupported = 0x08,
+}
 
-    if n < 2 {
-        return Err(anyhow!("Invalid SOCKS5 greeting: too short"));
+impl Reply {
+    /// The reply with this code, None for codes RFC 1928 doesn't define
+    pub fn from_code(code: u8) -> Option<Self> {
+        Some(match code {
+            0x00 => Reply::Succeeded,
+            0x01 => Reply::GeneralFailure,
+            0x02 => Reply::NotAllowed,
+            0x03 => Reply::NetworkUnreachable,
+            0x04 => Reply::HostUnreachable,
+            0x05 => Reply::ConnectionRefused,
+            0x06 => Reply::TtlExpired,
+            0x07 => Reply::CommandNotSupported,
+            0x08 => Reply::AddressTypeNotSupported,
+            _ => return None,
+        })
+    }
+}
+
+impl From<ConnectError> for Reply {
+    fn from(error: ConnectError) -> Self {
+        match error {
+            ConnectError::Refused => Reply::ConnectionRefused,
+            ConnectError::HostUnreachable => Reply::HostUnreachable,
+            ConnectError::NetworkUnreachable => Reply::NetworkUnreachable,
+            ConnectError::TimedOut => Reply::TtlExpired,
+            ConnectError::Other => Reply::GeneralFailure,
+        }
     }
+}
 
-    let version = buf[0];
-    let nmethods = buf[1] as usize;
+/// A client frame that doesn't follow the protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    UnsupportedVersion(u8),
+    UnsupportedAuthVersion(u8),
+    CommandNotSupported(u8),
+    AddressTypeNotSupported(u8),
+    InvalidDomain,
+}
 
-    if version != SOCKS5_VERSION {
-        return Err(anyhow!("Unsupported SOCKS version: {}", version));
+impl DecodeError {
+    /// Reply telling the client why its request was rejected, if the protocol has one
+    pub fn reply(&self) -> Option<Reply> {
+        match self {
+            DecodeError::UnsupportedVersion(_) | DecodeError::UnsupportedAuthVersion(_) => None,
+            DecodeError::CommandNotSupported(_) => Some(Reply::CommandNotSupported),
+            DecodeError::AddressTypeNotSupported(_) => Some(Reply::AddressTypeNotSupported),
+            DecodeError::InvalidDomain => Some(Reply::GeneralFailure),
+        }
     }
+}
 
-    if n < 2 + nmethods {
-        return Err(anyhow!("Invalid SOCKS5 greeting: incomplete methods"));
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnsupportedVersion(v) => write!(f, "Unsupported SOCKS version: {}", v),
+            DecodeError::UnsupportedAuthVersion(v) => {
+                write!(f, "Unsupported SOCKS5 auth version: {}", v)
+            }
+            DecodeError::CommandNotSupported(cmd) => {
+                write!(f, "Unsupported SOCKS5 command: {}", cmd)
+            }
+            DecodeError::AddressTypeNotSupported(atyp) => {
+                write!(f, "Unsupported SOCKS5 address type: {}", atyp)
+            }
+            DecodeError::InvalidDomain => write!(f, "Invalid domain name"),
+        }
     }
+}
 
-    debug!("[conn:{}] SOCKS5 greeting: {} methods", connection_id, nmethods);
+impl std::error::Error for DecodeError {}
 
-    // Step 2: Respond with no authentication required
-    stream
-        .write_all(&[SOCKS5_VERSION, SOCKS5_NO_AUTH])
-        .await
-        .context("Failed to write SOCKS5 auth response")?;
-    stream.flush().await.context("Failed to flush stream")?;
+/// Result of decoding a frame from the bytes read so far
+#[derive(Debug, PartialEq, Eq)]
+pub enum Decoded<T> {
+    Frame(T),
+    /// At least this many more bytes are needed, and reading exactly that
+    /// many never consumes anything past the frame
+    Incomplete(usize),
+}
 
-    // Step 3: Read connection request
-    let n = stream
-        .read(&mut buf)
-        .await
-        .context("Failed to read SOCKS5 request")?;
+/// Decode the client greeting, returning the offered auth methods
+pub fn decode_greeting(buf: &[u8]) -> Result<Decoded<Vec<u8>>, DecodeError> {
+    if buf.len() < 2 {
+        return Ok(Decoded::Incomplete(2 - buf.len()));
+    }
+    if buf[0] != SOCKS5_VERSION {
+        return Err(DecodeError::UnsupportedVersion(buf[0]));
+    }
 
-    if n < 4 {
-        return Err(anyhow!("Invalid SOCKS5 request: too short"));
+    let len = 2 + buf[1] as usize;
+    if buf.len() < len {
+        return Ok(Decoded::Incomplete(len - buf.len()));
     }
+    Ok(Decoded::Frame(buf[2..len].to_vec()))
+}
 
-    let version = buf[0];
-    let cmd = buf[1];
-    let _rsv = buf[2];
-    let atyp = buf[3];
+/// Decode the username/password subnegotiation
+pub fn decode_credentials(buf: &[u8]) -> Result<Decoded<Credentials>, DecodeError> {
+    if buf.len() < 2 {
+        return Ok(Decoded::Incomplete(2 - buf.len()));
+    }
+    if buf[0] != USER_PASS_VERSION {
+        return Err(DecodeError::UnsupportedAuthVersion(buf[0]));
+    }
 
-    if version != SOCKS5_VERSION {
-        return Err(anyhow!("Invalid SOCKS5 version in request: {}", version));
+    let username_end = 2 + buf[1] as usize;
+    // The password length follows the username
+    if buf.len() < username_end + 1 {
+        return Ok(Decoded::Incomplete(username_end + 1 - buf.len()));
     }
+    let len = username_end + 1 + buf[username_end] as usize;
+    if buf.len() < len {
+        return Ok(Decoded::Incomplete(len - buf.len()));
+    }
+    Ok(Decoded::Frame(Credentials {
+        username: buf[2..username_end].to_vec(),
+        password: buf[username_end + 1..len].to_vec(),
+    }))
+}
 
-    if cmd != SOCKS5_CMD_CONNECT {
-        send_socks5_error(stream, SOCKS5_GENERAL_FAILURE).await?;
-        return Err(anyhow!("Unsupported SOCKS5 command: {}", cmd));
+/// Decode a connection request
+pub fn decode_request(buf: &[u8]) -> Result<Decoded<Socks5Request>, DecodeError> {
+    if buf.len() < 4 {
+        return Ok(Decoded::Incomplete(4 - buf.len()));
+    }
+    if buf[0] != SOCKS5_VERSION {
+        return Err(DecodeError::UnsupportedVersion(buf[0]));
+    }
+    if buf[1] != SOCKS5_CMD_CONNECT {
+        return Err(DecodeError::CommandNotSupported(buf[1]));
     }
 
-    // Step 4: Parse target address
-    let (target_addr, target_port) = match atyp {
-        SOCKS5_ATYP_IPV4 => {
-            if n < 10 {
-                return Err(anyhow!("Invalid SOCKS5 IPv4 request"));
-            }
-            let addr = format!("{}.{}.{}.{}", buf[4], buf[5], buf[6], buf[7]);
-            let port = u16::from_be_bytes([buf[8], buf[9]]);
-            (addr, port)
+    let addr_len = match address_len(buf)? {
+        Decoded::Frame(len) => len,
+        Decoded::Incomplete(needed) => return Ok(Decoded::Incomplete(needed)),
+    };
+    let len = 4 + addr_len + 2;
+    if buf.len() < len {
+        return Ok(Decoded::Incomplete(len - buf.len()));
+    }
+
+    let addr = &buf[4..4 + addr_len];
+    let target_addr = match buf[3] {
+        SOCKS5_ATYP_IPV4 => Ipv4Addr::from(<[u8; 4]>::try_from(addr).unwrap()).to_string(),
+        SOCKS5_ATYP_IPV6 => Ipv6Addr::from(<[u8; 16]>::try_from(addr).unwrap()).to_string(),
+        _ => String::from_utf8(addr[1..].to_vec()).map_err(|_| DecodeError::InvalidDomain)?,
+    };
+    Ok(Decoded::Frame(Socks5Request {
+        target_addr,
+        target_port: u16::from_be_bytes([buf[len - 2], buf[len - 1]]),
+        stream_isolation: None,
+    }))
+}
+
+/// Decode the reply to a connection request sent to an upstream proxy, returning its code
+pub fn decode_reply(buf: &[u8]) -> Result<Decoded<u8>, DecodeError> {
+    if buf.len() < 4 {
+        return Ok(Decoded::Incomplete(4 - buf.len()));
+    }
+    if buf[0] != SOCKS5_VERSION {
+        return Err(DecodeError::UnsupportedVersion(buf[0]));
+    }
+
+    let addr_len = match address_len(buf)? {
+        Decoded::Frame(len) => len,
+        Decoded::Incomplete(needed) => return Ok(Decoded::Incomplete(needed)),
+    };
+    let len = 4 + addr_len + 2;
+    if buf.len() < len {
+        return Ok(Decoded::Incomplete(len - buf.len()));
+    }
+    Ok(Decoded::Frame(buf[1]))
+}
+
+/// Length of the address of a request or reply, whose type is in its fourth byte
+fn address_len(buf: &[u8]) -> Result<Decoded<usize>, DecodeError> {
+    match buf[3] {
+        SOCKS5_ATYP_IPV4 => Ok(Decoded::Frame(4)),
+        SOCKS5_ATYP_IPV6 => Ok(Decoded::Frame(16)),
+        // Domains are prefixed with their length
+        SOCKS5_ATYP_DOMAIN => match buf.get(4) {
+            Some(len) => Ok(Decoded::Frame(1 + *len as usize)),
+            None => Ok(Decoded::Incomplete(1)),
+        },
+        atyp => Err(DecodeError::AddressTypeNotSupported(atyp)),
+    }
+}
+
+/// Encode a greeting offering a single auth method
+pub fn encode_greeting(method: u8) -> Vec<u8> {
+    vec![SOCKS5_VERSION, 1, method]
+}
+
+/// Encode the username/password subnegotiation, None if either doesn't fit
+pub fn encode_credentials(username: &[u8], password: &[u8]) -> Option<Vec<u8>> {
+    let mut frame = vec![USER_PASS_VERSION, u8::try_from(username.len()).ok()?];
+    frame.extend_from_slice(username);
+    frame.push(u8::try_from(password.len()).ok()?);
+    frame.extend_from_slice(password);
+    Some(frame)
+}
+
+/// Encode a connection request, None if the target host is too long
+pub fn encode_request(request: &Socks5Request) -> Option<Vec<u8>> {
+    let mut frame = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0x00];
+    match request.target_addr.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            frame.push(SOCKS5_ATYP_IPV4);
+            frame.extend_from_slice(&ip.octets());
         }
-        SOCKS5_ATYP_DOMAIN => {
-            let domain_len = buf[4] as usize;
-            if n < 5 + domain_len + 2 {
-                return Err(anyhow!("Invalid SOCKS5 domain request"));
-            }
-            let domain = String::from_utf8(buf[5..5 + domain_len].to_vec())
-                .context("Invalid domain name")?;
-            let port = u16::from_be_bytes([buf[5 + domain_len], buf[5 + domain_len + 1]]);
-            (domain, port)
+        Ok(IpAddr::V6(ip)) => {
+            frame.push(SOCKS5_ATYP_IPV6);
+            frame.extend_from_slice(&ip.octets());
         }
-        SOCKS5_ATYP_IPV6 => {
-            if n < 22 {
-                return Err(anyhow!("Invalid SOCKS5 IPv6 request"));
-            }
-            let addr = format!(
-                "{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}:{:02x}{:02x}",
-                buf[4], buf[5], buf[6], buf[7], buf[8], buf[9], buf[10], buf[11],
-                buf[12], buf[13], buf[14], buf[15], buf[16], buf[17], buf[18], buf[19]
-            );
-            let port = u16::from_be_bytes([buf[20], buf[21]]);
-            (addr, port)
+        Err(_) => {
+            frame.push(SOCKS5_ATYP_DOMAIN);
+            frame.push(u8::try_from(request.target_addr.len()).ok()?);
+            frame.extend_from_slice(request.target_addr.as_bytes());
         }
-        _ => {
-            send_socks5_error(stream, SOCKS5_GENERAL_FAILURE).await?;
-            return Err(anyhow!("Unsupported SOCKS5 address type: {}", atyp));
+    }
+    frame.extend_from_slice(&request.target_port.to_be_bytes());
+    Some(frame)
+}
+
+/// Encode the reply to a connection request
+pub fn encode_reply(reply: Reply, bound_addr: Option<SocketAddr>) -> Vec<u8> {
+    let bound_addr = bound_addr.unwrap_or(SocketAddr::from(([0, 0, 0, 0], 0)));
+    let mut response = vec![SOCKS5_VERSION, reply as u8, 0x00];
+    match bound_addr {
+        SocketAddr::V4(addr) => {
+            response.push(SOCKS5_ATYP_IPV4);
+            response.extend_from_slice(&addr.ip().octets());
         }
-    };
+        SocketAddr::V6(addr) => {
+            response.push(SOCKS5_ATYP_IPV6);
+            response.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    response.extend_from_slice(&bound_addr.port().to_be_bytes());
+    response
+}
 
-    info!(
-        "[conn:{}] SOCKS5 request: {}:{}",
-        connection_id, target_addr, target_port
-    );
+/// Read exactly one frame, never consuming bytes that follow it
+pub(crate) async fn read_frame<T>(
+    stream: &mut TcpStream,
+    decode: fn(&[u8]) -> Result<Decoded<T>, DecodeError>,
+) -> Result<T> {
+    let mut buf = Vec::new();
+    loop {
+        match decode(&buf)? {
+            Decoded::Frame(frame) => return Ok(frame),
+            Decoded::Incomplete(needed) => {
+                let start = buf.len();
+                buf.resize(start + needed, 0);
+                stream.read_exact(&mut buf[start..]).await?;
+            }
+        }
+    }
+}
 
-    // Step 5: Send success response
-    // Response format: [version, status, reserved, atyp, bind_addr, bind_port]
-    // We'll use 0.0.0.0:0 as the bind address
-    let response = [
-        SOCKS5_VERSION,
-        SOCKS5_SUCCESS,
-        0x00, // reserved
-        SOCKS5_ATYP_IPV4,
-        0, 0, 0, 0, // bind addr: 0.0.0.0
-        0, 0, // bind port: 0
-    ];
+/// Negotiate auth and read the connection request of a SOCKS5 client
+///
+/// Rejected requests are answered here, the caller replies to accepted ones
+/// with [`send_reply`] once it knows whether the target could be reached.
+pub async fn handle_socks5_handshake(
+    stream: &mut TcpStream,
+    connection_id: u64,
+    auth: Option<&AuthConfig>,
+) -> Result<Socks5Request> {
+    // Step 1: Read client greeting
+    let methods = read_frame(stream, decode_greeting)
+        .await
+        .context("Failed to read SOCKS5 greeting")?;
+    debug!(
+        "[conn:{}] SOCKS5 greeting: methods {:?}",
+        connection_id, methods
+    );
 
+    // Step 2: Pick an authentication method the client offered
+    let method = select_method(&methods, auth);
     stream
-        .write_all(&response)
+        .write_all(&[SOCKS5_VERSION, method])
         .await
-        .context("Failed to write SOCKS5 success response")?;
+        .context("Failed to write SOCKS5 auth response")?;
     stream.flush().await.context("Failed to flush stream")?;
 
+    let stream_isolation = match method {
+        SOCKS5_NO_AUTH => None,
+        SOCKS5_USER_PASS => authenticate(stream, connection_id, auth).await?,
+        _ => return Err(anyhow!("No acceptable SOCKS5 auth method in {:?}", methods)),
+    };
+
+    // Step 3: Read connection request
+    let request = match read_frame(stream, decode_request).await {
+        Ok(request) => request,
+        Err(e) => {
+            if let Some(reply) = e.downcast_ref::<DecodeError>().and_then(DecodeError::reply) {
+                send_reply(stream, reply, None).await?;
+            }
+            return Err(e.context("Failed to read SOCKS5 request"));
+        }
+    };
+
+    info!("[conn:{}] SOCKS5 request: {}", connection_id, request);
+
     Ok(Socks5Request {
-        target_addr,
-        target_port,
+        stream_isolation,
+        ..request
     })
 }
 
-/// Send a SOCKS5 error response
-async fn send_socks5_error(stream: &mut TcpStream, error_code: u8) -> Result<()> {
-    let response = [
-        SOCKS5_VERSION,
-        error_code,
-        0x00, // reserved
-        SOCKS5_ATYP_IPV4,
-        0, 0, 0, 0, // bind addr: 0.0.0.0
-        0, 0, // bind port: 0
-    ];
+/// Choose the auth method to use from the ones offered by the client
+///
+/// Username/password is preferred even when no credentials are configured, so
+/// the random ones Bitcoin Core sends can be recorded.
+fn select_method(methods: &[u8], auth: Option<&AuthConfig>) -> u8 {
+    if methods.contains(&SOCKS5_USER_PASS) {
+        SOCKS5_USER_PASS
+    } else if auth.is_none() && methods.contains(&SOCKS5_NO_AUTH) {
+        SOCKS5_NO_AUTH
+    } else {
+        SOCKS5_NO_ACCEPTABLE_METHODS
+    }
+}
+
+/// Run the username/password subnegotiation, returning the stream isolation username
+///
+/// Any credentials are accepted when none are configured, and only then is the
+/// username returned, as configured credentials are the same for every client.
+async fn authenticate(
+    stream: &mut TcpStream,
+    connection_id: u64,
+    auth: Option<&AuthConfig>,
+) -> Result<Option<String>> {
+    let credentials = read_frame(stream, decode_credentials)
+        .await
+        .context("Failed to read SOCKS5 credentials")?;
+
+    let username = String::from_utf8_lossy(&credentials.username).into_owned();
+    let accepted = auth.is_none_or(|auth| {
+        auth.username.as_bytes() == credentials.username
+            && auth.password.as_bytes() == credentials.password
+    });
+
+    let status = if accepted {
+        USER_PASS_SUCCESS
+    } else {
+        USER_PASS_FAILURE
+    };
+    stream
+        .write_all(&[USER_PASS_VERSION, status])
+        .await
+        .context("Failed to write SOCKS5 auth status")?;
+    stream.flush().await.context("Failed to flush stream")?;
+
+    if !accepted {
+        return Err(anyhow!(
+            "SOCKS5 authentication failed for user {:?}",
+            username
+        ));
+    }
+    debug!(
+        "[conn:{}] SOCKS5 authenticated as {:?}",
+        connection_id, username
+    );
 
+    Ok(auth.is_none().then_some(username))
+}
+
+/// Send the reply to a connection request, with the address the proxy connected from
+pub async fn send_reply(
+    stream: &mut TcpStream,
+    reply: Reply,
+    bound_addr: Option<SocketAddr>,
+) -> Result<()> {
     stream
-        .write_all(&response)
+        .write_all(&encode_reply(reply, bound_addr))
         .await
-        .context("Failed to write SOCKS5 error response")?;
+        .context("Failed to write SOCKS5 reply")?;
     stream.flush().await.context("Failed to flush stream")?;
 
     Ok(())
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// Run the subnegotiation against credentials sent by a client, returning
+    /// its result and the status the client got
+    async fn subnegotiate(
+        auth: Option<&AuthConfig>,
+        username: &str,
+        password: &str,
+    ) -> (Result<Option<String>>, u8) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut server, _) = listener.accept().await.unwrap();
+
+        let mut credentials = vec![USER_PASS_VERSION, username.len() as u8];
+        credentials.extend_from_slice(username.as_bytes());
+        credentials.push(password.len() as u8);
+        credentials.extend_from_slice(password.as_bytes());
+        client.write_all(&credentials).await.unwrap();
+
+        let result = authenticate(&mut server, 1, auth).await;
+        let mut status = [0; 2];
+        client.read_exact(&mut status).await.unwrap();
+        assert_eq!(status[0], USER_PASS_VERSION);
+        (result, status[1])
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let auth = AuthConfig {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        };
+
+        // Without configured credentials the username isolates the stream
+        let (result, status) = subnegotiate(None, "4e8a1c", "0").await;
+        assert_eq!(status, USER_PASS_SUCCESS);
+        assert_eq!(result.unwrap().as_deref(), Some("4e8a1c"));
+
+        let (result, status) = subnegotiate(Some(&auth), "user", "pass").await;
+        assert_eq!(status, USER_PASS_SUCCESS);
+        assert_eq!(result.unwrap(), None);
+
+        let (result, status) = subnegotiate(Some(&auth), "user", "wrong").await;
+        assert_eq!(status, USER_PASS_FAILURE);
+        assert!(result.is_err());
+
+        let (result, status) = subnegotiate(Some(&auth), "other", "pass").await;
+        assert_eq!(status, USER_PASS_FAILURE);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_select_method() {
+        let auth = AuthConfig {
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        };
+
+        // Bitcoin Core offers both when it sends stream isolation credentials
+        let core = [SOCKS5_NO_AUTH, SOCKS5_USER_PASS];
+        assert_eq!(select_method(&core, None), SOCKS5_USER_PASS);
+        assert_eq!(select_method(&core, Some(&auth)), SOCKS5_USER_PASS);
+
+        assert_eq!(select_method(&[SOCKS5_NO_AUTH], None), SOCKS5_NO_AUTH);
+        assert_eq!(
+            select_method(&[SOCKS5_NO_AUTH], Some(&auth)),
+            SOCKS5_NO_ACCEPTABLE_METHODS
+        );
+        assert_eq!(select_method(&[0x01], None), SOCKS5_NO_ACCEPTABLE_METHODS);
+    }
+
+    #[test]
+    fn test_decodes_fragmented_frames_exactly() {
+        let greeting = [SOCKS5_VERSION, 2, SOCKS5_NO_AUTH, SOCKS5_USER_PASS];
+        assert_eq!(decode_greeting(&greeting[..1]), Ok(Decoded::Incomplete(1)));
+        assert_eq!(decode_greeting(&greeting[..2]), Ok(Decoded::Incomplete(2)));
+        assert_eq!(
+            decode_greeting(&greeting),
+            Ok(Decoded::Frame(vec![SOCKS5_NO_AUTH, SOCKS5_USER_PASS]))
+        );
+
+        let credentials = [USER_PASS_VERSION, 1, b'0', 1, b'0'];
+        assert_eq!(
+            decode_credentials(&credentials[..3]),
+            Ok(Decoded::Incomplete(1))
+        );
+        assert_eq!(
+            decode_credentials(&credentials[..4]),
+            Ok(Decoded::Incomplete(1))
+        );
+
+        let mut request = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0, SOCKS5_ATYP_DOMAIN];
+        assert_eq!(decode_request(&request), Ok(Decoded::Incomplete(1)));
+        request.push(11);
+        request.extend_from_slice(b"example.com");
+        assert_eq!(decode_request(&request), Ok(Decoded::Incomplete(2)));
+        request.extend_from_slice(&8333u16.to_be_bytes());
+        let Ok(Decoded::Frame(request)) = decode_request(&request) else {
+            panic!("request not decoded");
+        };
+        assert_eq!(request.to_string(), "example.com:8333");
+    }
+
+    #[test]
+    fn test_rejects_unsupported_requests() {
+        let bind = [SOCKS5_VERSION, 0x02, 0, SOCKS5_ATYP_IPV4];
+        let error = decode_request(&bind).unwrap_err();
+        assert_eq!(error.reply(), Some(Reply::CommandNotSupported));
+
+        let unknown_atyp = [SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0, 0x02];
+        let error = decode_request(&unknown_atyp).unwrap_err();
+        assert_eq!(error.reply(), Some(Reply::AddressTypeNotSupported));
+
+        let mut ipv6 = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0, SOCKS5_ATYP_IPV6];
+        ipv6.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        ipv6.extend_from_slice(&8333u16.to_be_bytes());
+        let Ok(Decoded::Frame(request)) = decode_request(&ipv6) else {
+            panic!("request not decoded");
+        };
+        assert_eq!(request.to_string(), "[::1]:8333");
+    }
+
+    #[test]
+    fn test_replies_to_connect_errors() {
+        assert_eq!(Reply::from(ConnectError::Refused), Reply::ConnectionRefused);
+        assert_eq!(Reply::from(ConnectError::TimedOut), Reply::TtlExpired);
+        // Unclassified errors say nothing about the host
+        assert_eq!(Reply::from(ConnectError::Other), Reply::GeneralFailure);
+    }
+
+    #[test]
+    fn test_encodes_bound_address() {
+        let bound = "[2001:db8::1]:40000".parse().unwrap();
+        let reply = encode_reply(Reply::Succeeded, Some(bound));
+        assert_eq!(reply.len(), 4 + 16 + 2);
+        assert_eq!(reply[3], SOCKS5_ATYP_IPV6);
+        assert_eq!(reply[20..], 40000u16.to_be_bytes());
+
+        assert_eq!(
+            encode_reply(Reply::ConnectionRefused, None),
+            [SOCKS5_VERSION, 0x05, 0, SOCKS5_ATYP_IPV4, 0, 0, 0, 0, 0, 0]
+        );
+    }
+}
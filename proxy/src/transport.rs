@@ -0,0 +1,69 @@
+use app::Transport;
+
+/// Bytes of a v1 `version` header that are known before its payload length
+const V1_PREFIX_SIZE: usize = 16;
+
+/// Size of the network magic that starts the prefix
+const MAGIC_SIZE: usize = 4;
+
+/// Tells v1 from BIP324 v2 connections by the first bytes the initiator sends
+///
+/// Like Bitcoin Core, a connection is v1 only when it starts with a network
+/// magic followed by the `version` command. Anything else is taken to be the
+/// initiator's ellswift-encoded key, which starts a v2 handshake. Any magic is
+/// accepted, so a connection on another network than configured is still
+/// parsed and its network reported.
+pub struct TransportDetector {
+    prefix: [u8; V1_PREFIX_SIZE],
+    seen: usize,
+}
+
+impl TransportDetector {
+    pub fn new() -> Self {
+        let mut prefix = [0u8; V1_PREFIX_SIZE];
+        prefix[MAGIC_SIZE..MAGIC_SIZE + 7].copy_from_slice(b"version");
+        Self { prefix, seen: 0 }
+    }
+
+    /// Feed the next bytes of the stream, returning the transport once it is known
+    pub fn push(&mut self, data: &[u8]) -> Option<Transport> {
+        for byte in data {
+            if self.seen >= MAGIC_SIZE && *byte != self.prefix[self.seen] {
+                return Some(Transport::V2Encrypted);
+            }
+            self.seen += 1;
+            if self.seen == V1_PREFIX_SIZE {
+                return Some(Transport::V1);
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_transport_from_first_bytes() {
+        let mut v1 = TransportDetector::new();
+        let header = [
+            0xf9, 0xbe, 0xb4, 0xd9, b'v', b'e', b'r', b's', b'i', b'o', b'n', 0, 0, 0, 0, 0,
+        ];
+        assert_eq!(v1.push(&header[..10]), None);
+        assert_eq!(v1.push(&header[10..]), Some(Transport::V1));
+
+        // A v2 key may start with the magic by chance, the command tells them apart
+        let mut v2 = TransportDetector::new();
+        assert_eq!(
+            v2.push(&[0xf9, 0xbe, 0xb4, 0xd9, b'x']),
+            Some(Transport::V2Encrypted)
+        );
+
+        // Any magic is accepted, the parser reports which network it belongs to
+        let mut signet = TransportDetector::new();
+        let mut header = header;
+        header[..4].copy_from_slice(&[0x0a, 0x03, 0xcf, 0x40]);
+        assert_eq!(signet.push(&header), Some(Transport::V1));
+    }
+}
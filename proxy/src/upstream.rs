@@ -0,0 +1,223 @@
+use std::io;
+
+use app::NetworkType;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+
+use crate::config::UpstreamConfig;
+use crate::socks5::{self, Reply, Socks5Request};
+
+/// Connect to a requested target, directly or through the upstream proxy of its network
+///
+/// Returns the stream together with the network the target is on. Errors carry
+/// an [`io::ErrorKind`] matching the failure, so they map to SOCKS5 replies.
+pub async fn connect(
+    upstream: &UpstreamConfig,
+    request: &Socks5Request,
+) -> io::Result<(TcpStream, NetworkType)> {
+    let target = request.to_string();
+    let network_type = NetworkType::of_host(&request.target_addr);
+
+    let proxy = match network_type {
+        Some(NetworkType::Onion) => Some(upstream.onion.as_ref()),
+        Some(NetworkType::I2p) => Some(upstream.i2p.as_ref()),
+        _ => None,
+    };
+    match proxy {
+        None => {
+            let stream = TcpStream::connect(&target).await?;
+            // DNS names are only classified once they resolved
+            let network_type = match network_type {
+                Some(network_type) => network_type,
+                None => NetworkType::of_ip(stream.peer_addr()?.ip()),
+            };
+            Ok((stream, network_type))
+        }
+        Some(Some(proxy)) => {
+            let stream = connect_via(proxy, request).await?;
+            Ok((stream, network_type.expect("routed by network type")))
+        }
+        Some(None) => Err(io::Error::new(
+            io::ErrorKind::NetworkUnreachable,
+            format!(
+                "no upstream proxy configured for {} peers",
+                network_type.expect("routed by network type").as_str()
+            ),
+        )),
+    }
+}
+
+/// Ask an upstream SOCKS5 proxy to connect to the target
+///
+/// The client's stream isolation credentials are passed on, so Tor keeps
+/// building separate circuits for the connections Bitcoin Core isolated.
+async fn connect_via(proxy: &str, request: &Socks5Request) -> io::Result<TcpStream> {
+    let mut stream = TcpStream::connect(proxy).await?;
+
+    let credentials = request.stream_isolation.as_ref().and_then(|isolation| {
+        socks5::encode_credentials(isolation.as_bytes(), isolation.as_bytes())
+    });
+    let method = match credentials {
+        Some(_) => socks5::SOCKS5_USER_PASS,
+        None => socks5::SOCKS5_NO_AUTH,
+    };
+    stream.write_all(&socks5::encode_greeting(method)).await?;
+    let selected = read_frame(&mut stream, decode_method_selection).await?;
+    if selected != method {
+        return Err(io::Error::other(format!(
+            "upstream proxy {} rejected auth method {}",
+            proxy, method
+        )));
+    }
+
+    if let Some(credentials) = credentials {
+        stream.write_all(&credentials).await?;
+        let status = read_frame(&mut stream, decode_auth_status).await?;
+        if status != socks5::USER_PASS_SUCCESS {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("upstream proxy {} rejected the credentials", proxy),
+            ));
+        }
+    }
+
+    let frame = socks5::encode_request(request)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "target host is too long"))?;
+    stream.write_all(&frame).await?;
+    let code = read_frame(&mut stream, socks5::decode_reply).await?;
+    match Reply::from_code(code) {
+        Some(Reply::Succeeded) => Ok(stream),
+        reply => Err(io::Error::new(
+            reply.map_or(io::ErrorKind::Other, reply_error_kind),
+            format!(
+                "upstream proxy {} replied {:?} (0x{:02x})",
+                proxy, reply, code
+            ),
+        )),
+    }
+}
+
+async fn read_frame<T>(
+    stream: &mut TcpStream,
+    decode: fn(&[u8]) -> Result<socks5::Decoded<T>, socks5::DecodeError>,
+) -> io::Result<T> {
+    socks5::read_frame(stream, decode)
+        .await
+        .map_err(|e| io::Error::other(format!("{:#}", e)))
+}
+
+/// Decode the auth method an upstream proxy selected
+fn decode_method_selection(buf: &[u8]) -> Result<socks5::Decoded<u8>, socks5::DecodeError> {
+    decode_pair(buf, socks5::SOCKS5_VERSION)
+}
+
+/// Decode the status of the username/password subnegotiation
+fn decode_auth_status(buf: &[u8]) -> Result<socks5::Decoded<u8>, socks5::DecodeError> {
+    decode_pair(buf, socks5::USER_PASS_VERSION)
+}
+
+/// Decode a two byte frame of a version and a value
+fn decode_pair(buf: &[u8], version: u8) -> Result<socks5::Decoded<u8>, socks5::DecodeError> {
+    match buf {
+        [v, ..] if *v != version => Err(socks5::DecodeError::UnsupportedVersion(*v)),
+        [_, value] => Ok(socks5::Decoded::Frame(*value)),
+        _ => Ok(socks5::Decoded::Incomplete(2 - buf.len())),
+    }
+}
+
+/// Error kind of a failure reply, the inverse of replying to a failed connect
+fn reply_error_kind(reply: Reply) -> io::ErrorKind {
+    match reply {
+        Reply::NetworkUnreachable => io::ErrorKind::NetworkUnreachable,
+        Reply::HostUnreachable => io::ErrorKind::HostUnreachable,
+        Reply::ConnectionRefused => io::ErrorKind::ConnectionRefused,
+        Reply::TtlExpired => io::ErrorKind::TimedOut,
+        _ => io::ErrorKind::Other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    const ONION: &str = "kpgvmscirrdqpekbqjsvw5teanhatztpp2gl6eee4zkowvwfxwenqaid.onion";
+
+    /// Accept one connection like Tor would, then send `pong` on the proxied stream
+    async fn stub_upstream(reply: Reply) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let methods = socks5::read_frame(&mut stream, socks5::decode_greeting)
+                .await
+                .unwrap();
+            assert_eq!(methods, [socks5::SOCKS5_USER_PASS]);
+            stream
+                .write_all(&[5, socks5::SOCKS5_USER_PASS])
+                .await
+                .unwrap();
+
+            let credentials = socks5::read_frame(&mut stream, socks5::decode_credentials)
+                .await
+                .unwrap();
+            assert_eq!(credentials.username, b"3");
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            let request = socks5::read_frame(&mut stream, socks5::decode_request)
+                .await
+                .unwrap();
+            assert_eq!(request.target_addr, ONION);
+            stream
+                .write_all(&socks5::encode_reply(reply, None))
+                .await
+                .unwrap();
+            let _ = stream.write_all(b"pong").await;
+        });
+
+        addr
+    }
+
+    fn onion_request() -> Socks5Request {
+        Socks5Request {
+            target_addr: ONION.to_string(),
+            target_port: 8333,
+            stream_isolation: Some("3".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_onion_through_upstream() {
+        let upstream = UpstreamConfig {
+            onion: Some(stub_upstream(Reply::Succeeded).await),
+            i2p: None,
+        };
+
+        let (mut stream, network_type) = connect(&upstream, &onion_request()).await.unwrap();
+        assert_eq!(network_type, NetworkType::Onion);
+        let mut pong = [0u8; 4];
+        stream.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+    }
+
+    #[tokio::test]
+    async fn test_reports_upstream_failures() {
+        let upstream = UpstreamConfig {
+            onion: Some(stub_upstream(Reply::HostUnreachable).await),
+            i2p: None,
+        };
+        let error = connect(&upstream, &onion_request()).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::HostUnreachable);
+
+        let i2p = Socks5Request {
+            target_addr: "c4gfnttsuwqomiygupdqqqyy5y5emnk5c73hrfvatri67prd7vyq.b32.i2p".to_string(),
+            target_port: 0,
+            stream_isolation: None,
+        };
+        let error = connect(&upstream, &i2p).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NetworkUnreachable);
+    }
+}
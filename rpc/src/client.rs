@@ -0,0 +1,298 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use anyhow::{Context, anyhow, bail};
+use base64::Engine;
+use base64::prelude::BASE64_STANDARD;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::config::RpcConfig;
+
+/// Credentials for the RPC interface of a node
+#[derive(Debug, Clone)]
+pub enum Credentials {
+    /// `__cookie__:<secret>` as written by bitcoind, read on every call
+    Cookie(PathBuf),
+    UserPass {
+        username: String,
+        password: String,
+    },
+}
+
+impl Credentials {
+    pub fn from_config(config: &RpcConfig) -> anyhow::Result<Self> {
+        match (&config.cookie_file, &config.auth) {
+            (Some(path), _) => Ok(Credentials::Cookie(path.clone())),
+            (None, Some(auth)) => Ok(Credentials::UserPass {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+            }),
+            (None, None) => bail!(
+                "RPC of node {:?} needs either a cookie_file or auth",
+                config.node
+            ),
+        }
+    }
+
+    /// Value of the HTTP basic `Authorization` header
+    async fn authorization(&self) -> anyhow::Result<String> {
+        let user_pass = match self {
+            Credentials::Cookie(path) => tokio::fs::read_to_string(path)
+                .await
+                .context(format!("Couldn't read RPC cookie file {:?}", path))?
+                .trim()
+                .to_string(),
+            Credentials::UserPass { username, password } => format!("{}:{}", username, password),
+        };
+        Ok(format!("Basic {}", BASE64_STANDARD.encode(user_pass)))
+    }
+}
+
+/// Minimal JSON-RPC client for Bitcoin Core, one HTTP/1.1 connection per call
+pub struct RpcClient {
+    address: String,
+    credentials: Credentials,
+    timeout: Duration,
+    next_id: AtomicU64,
+}
+
+#[derive(Deserialize)]
+struct Response<T> {
+    result: Option<T>,
+    error: Option<ResponseError>,
+}
+
+#[derive(Deserialize)]
+struct ResponseError {
+    code: i64,
+    message: String,
+}
+
+impl RpcClient {
+    pub fn new(address: String, credentials: Credentials, timeout: Duration) -> Self {
+        Self {
+            address,
+            credentials,
+            timeout,
+            next_id: AtomicU64::new(0),
+        }
+    }
+
+    /// Call an RPC method and decode its result
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<T> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request = serde_json::to_vec(&json!({
+            "jsonrpc": "1.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        }))?;
+
+        let (status, body) = tokio::time::timeout(self.timeout, self.post(&request))
+            .await
+            .map_err(|_| anyhow!("{} timed out after {}s", method, self.timeout.as_secs()))??;
+        if status == 401 || status == 403 {
+            bail!("RPC credentials were rejected (HTTP {})", status);
+        }
+
+        // Errors come with a JSON body too, only their HTTP status differs
+        let response: Response<T> = serde_json::from_slice(&body)
+            .context(format!("Invalid {} response (HTTP {})", method, status))?;
+        if let Some(error) = response.error {
+            bail!("{} failed: {} (code {})", method, error.message, error.code);
+        }
+        response
+            .result
+            .with_context(|| format!("{} returned no result", method))
+    }
+
+    /// Send a request body and read the response until the node closes the connection
+    async fn post(&self, body: &[u8]) -> anyhow::Result<(u16, Vec<u8>)> {
+        let authorization = self.credentials.authorization().await?;
+        let mut stream = TcpStream::connect(&self.address)
+            .await
+            .context(format!("Couldn't connect to RPC at {}", self.address))?;
+
+        let head = format!(
+            "POST / HTTP/1.1\r\nHost: {}\r\nAuthorization: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            self.address,
+            authorization,
+            body.len()
+        );
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(body).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        parse_response(&response)
+    }
+}
+
+/// Split a complete HTTP response into its status code and body
+fn parse_response(response: &[u8]) -> anyhow::Result<(u16, Vec<u8>)> {
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut parsed = httparse::Response::new(&mut headers);
+    let httparse::Status::Complete(head_len) = parsed.parse(response)? else {
+        bail!("Incomplete HTTP response");
+    };
+    let status = parsed.code.unwrap_or_default();
+    let header = |name: &str| {
+        parsed
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value)
+    };
+
+    let body = &response[head_len..];
+    if header("transfer-encoding").is_some_and(|value| value.eq_ignore_ascii_case(b"chunked")) {
+        return Ok((status, decode_chunked(body)?));
+    }
+    let body = match header("content-length") {
+        Some(len) => {
+            let len: usize = std::str::from_utf8(len)?.trim().parse()?;
+            body.get(..len).context("Truncated HTTP body")?
+        }
+        None => body,
+    };
+    Ok((status, body.to_vec()))
+}
+
+fn decode_chunked(mut body: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    loop {
+        let (start, size) = match httparse::parse_chunk_size(body) {
+            Ok(httparse::Status::Complete((start, size))) => (start, size as usize),
+            _ => bail!("Invalid chunked HTTP body"),
+        };
+        if size == 0 {
+            return Ok(decoded);
+        }
+        let chunk = body
+            .get(start..start + size)
+            .context("Truncated HTTP chunk")?;
+        decoded.extend_from_slice(chunk);
+        // Each chunk ends with CRLF
+        body = body
+            .get(start + size + 2..)
+            .context("Truncated HTTP chunk")?;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// JSON-RPC server answering every method from `results`, like bitcoind
+    ///
+    /// Requests without the expected basic auth get a 401 with an empty body.
+    pub(crate) async fn mock_node(user_pass: &'static str, results: Value) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let authorization = format!("Basic {}", BASE64_STANDARD.encode(user_pass));
+
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buffer = [0u8; 4096];
+                let (head_len, content_length, authorized) = loop {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let mut headers = [httparse::EMPTY_HEADER; 16];
+                    let mut parsed = httparse::Request::new(&mut headers);
+                    if let httparse::Status::Complete(head_len) = parsed.parse(&request).unwrap() {
+                        let value = |name: &str| {
+                            parsed
+                                .headers
+                                .iter()
+                                .find(|header| header.name.eq_ignore_ascii_case(name))
+                                .map(|header| String::from_utf8_lossy(header.value).to_string())
+                        };
+                        let content_length: usize =
+                            value("content-length").unwrap().parse().unwrap();
+                        let authorized = value("authorization") == Some(authorization.clone());
+                        break (head_len, content_length, authorized);
+                    }
+                };
+                while request.len() < head_len + content_length {
+                    let n = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                }
+
+                let response = if authorized {
+                    let call: Value = serde_json::from_slice(&request[head_len..]).unwrap();
+                    let method = call["method"].as_str().unwrap();
+                    let body = match results.get(method) {
+                        Some(result) => json!({"result": result, "error": null, "id": call["id"]}),
+                        None => json!({
+                            "result": null,
+                            "error": {"code": -32601, "message": "Method not found"},
+                            "id": call["id"],
+                        }),
+                    }
+                    .to_string();
+                    // Large results are sent in chunks
+                    let (a, b) = body.split_at(body.len() / 2);
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                         Transfer-Encoding: chunked\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                        a.len(),
+                        a,
+                        b.len(),
+                        b
+                    )
+                } else {
+                    "HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n".to_string()
+                };
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn test_calls_mock_node() {
+        let address = mock_node("alice:secret", json!({"getblockcount": 850_000})).await;
+        let client = |username: &str| {
+            RpcClient::new(
+                address.clone(),
+                Credentials::UserPass {
+                    username: username.to_string(),
+                    password: "secret".to_string(),
+                },
+                Duration::from_secs(5),
+            )
+        };
+
+        let blocks: u64 = client("alice")
+            .call("getblockcount", json!([]))
+            .await
+            .unwrap();
+        assert_eq!(blocks, 850_000);
+
+        let error = client("alice")
+            .call::<Value>("getbestblockhash", json!([]))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("Method not found"));
+
+        let error = client("mallory")
+            .call::<u64>("getblockcount", json!([]))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("rejected"));
+    }
+}
@@ -0,0 +1,396 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use app::{
+    ConnectionId, ConnectionRecord, CoreConnectionType, CorePeer, LiveConnection, NodeScopeApp,
+    NodeStatus, ShutdownPhase,
+};
+use chrono::{DateTime, Utc};
+use serde_json::json;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+use crate::client::{Credentials, RpcClient};
+use crate::config::RpcConfig;
+use crate::types::{BlockchainInfo, MempoolInfo, NetTotals, NetworkInfo, PeerInfo};
+
+/// How long after the proxy closed a connection Bitcoin Core may still list its peer
+const CLOSE_SLACK: chrono::Duration = chrono::Duration::seconds(5);
+
+/// Polls the RPC interface of one node and joins its peers to proxied connections
+pub struct Collector {
+    node: String,
+    poll_interval: Duration,
+    client: RpcClient,
+    app: NodeScopeApp,
+}
+
+impl Collector {
+    pub fn new(config: RpcConfig, app: NodeScopeApp) -> anyhow::Result<Self> {
+        let credentials = Credentials::from_config(&config)?;
+        Ok(Self {
+            client: RpcClient::new(
+                config.address,
+                credentials,
+                Duration::from_secs(config.timeout_secs),
+            ),
+            node: config.node,
+            poll_interval: Duration::from_secs(config.poll_interval_secs.max(1)),
+            app,
+        })
+    }
+
+    /// Poll on schedule until the app starts shutting down
+    ///
+    /// A failed poll is logged and retried on the next tick, the node may just be restarting.
+    pub async fn run(&self) {
+        let mut interval = tokio::time::interval(self.poll_interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = self.app.shutdown().reached(ShutdownPhase::Draining) => break,
+            }
+            match self.poll().await {
+                Ok(matched) => debug!("[node:{}] Polled RPC, matched {} peers", self.node, matched),
+                Err(e) => warn!("[node:{}] RPC poll failed: {:#}", self.node, e),
+            }
+        }
+    }
+
+    /// Collect everything once, returning the number of peers matched to connections
+    pub async fn poll(&self) -> anyhow::Result<usize> {
+        let (peers, network, chain, totals, mempool) = tokio::try_join!(
+            self.client.call::<Vec<PeerInfo>>("getpeerinfo", json!([])),
+            self.client.call::<NetworkInfo>("getnetworkinfo", json!([])),
+            self.client
+                .call::<BlockchainInfo>("getblockchaininfo", json!([])),
+            self.client.call::<NetTotals>("getnettotals", json!([])),
+            self.client.call::<MempoolInfo>("getmempoolinfo", json!([])),
+        )?;
+        let now = Utc::now();
+
+        // Bitcoin Core may not have noticed yet that the proxy closed a connection
+        let mut connections: Vec<ProxiedConnection> = self
+            .app
+            .connections()
+            .list()
+            .into_iter()
+            .filter(|connection| connection.node == self.node)
+            .map(Into::into)
+            .collect();
+        let closed = self
+            .app
+            .store()
+            .connections_closed_since(&self.node, now - CLOSE_SLACK)
+            .await?;
+        connections.extend(closed.into_iter().map(ProxiedConnection::from));
+
+        let matched = match_peers(&self.node, &peers, &connections, now);
+        self.app.store().upsert_core_peers(&matched).await?;
+
+        let connections_in = network
+            .connections_in
+            .unwrap_or_else(|| peers.iter().filter(|peer| peer.inbound).count() as i64);
+        let status = NodeStatus {
+            node: self.node.clone(),
+            version: network.version,
+            subversion: network.subversion,
+            chain: chain.chain,
+            blocks: chain.blocks,
+            headers: chain.headers,
+            verification_progress: chain.verificationprogress,
+            initial_block_download: chain.initialblockdownload,
+            connections_in,
+            connections_out: network
+                .connections_out
+                .unwrap_or(network.connections - connections_in),
+            total_bytes_recv: totals.totalbytesrecv,
+            total_bytes_sent: totals.totalbytessent,
+            mempool_size: mempool.size,
+            mempool_bytes: mempool.bytes,
+            updated_at: now,
+        };
+        self.app.store().upsert_node_status(&status).await?;
+
+        Ok(matched.len())
+    }
+}
+
+/// A connection of a node, open or recently closed, to join Bitcoin Core's peers to
+#[derive(Debug, Clone)]
+pub struct ProxiedConnection {
+    pub connection_id: ConnectionId,
+    pub client_addr: String,
+    pub target_addr: String,
+    pub local_addr: Option<String>,
+    pub inbound: bool,
+}
+
+impl From<LiveConnection> for ProxiedConnection {
+    fn from(connection: LiveConnection) -> Self {
+        Self {
+            connection_id: connection.connection_id,
+            client_addr: connection.client_addr,
+            target_addr: connection.target_addr,
+            local_addr: connection.local_addr,
+            inbound: connection.inbound,
+        }
+    }
+}
+
+impl From<ConnectionRecord> for ProxiedConnection {
+    fn from(connection: ConnectionRecord) -> Self {
+        Self {
+            connection_id: connection.connection_id(),
+            client_addr: connection.client_addr,
+            target_addr: connection.target_addr,
+            local_addr: connection.local_addr,
+            inbound: connection.inbound,
+        }
+    }
+}
+
+impl ProxiedConnection {
+    /// Whether Bitcoin Core's peer is this connection
+    ///
+    /// For outbound connections Bitcoin Core reports the target it asked the proxy for as
+    /// `addr` and its end of the socket to the proxy as `addrbind`, which is the client
+    /// address the proxy saw. Inbound peers forwarded by the transparent proxy show up with
+    /// the address the proxy connected to the node from, and the node's listening address
+    /// as `addrbind`.
+    fn is(&self, peer: &PeerInfo) -> bool {
+        let addr = if self.inbound {
+            match &self.local_addr {
+                Some(local_addr) => local_addr,
+                None => return false,
+            }
+        } else {
+            &self.target_addr
+        };
+        peer.inbound == self.inbound
+            && peer.addr == *addr
+            && peer
+                .addrbind
+                .as_ref()
+                .is_none_or(|addrbind| *addrbind == self.client_addr)
+    }
+}
+
+/// Join Bitcoin Core's peers to a node's proxied connections
+///
+/// Each connection appears once, and each peer is joined to the first connection
+/// it matches, so open connections should come before closed ones.
+pub fn match_peers(
+    node: &str,
+    peers: &[PeerInfo],
+    connections: &[ProxiedConnection],
+    now: DateTime<Utc>,
+) -> Vec<CorePeer> {
+    let mut seen = HashSet::new();
+    let mut joined = HashSet::new();
+    connections
+        .iter()
+        .filter(|connection| seen.insert(connection.connection_id))
+        .filter_map(|connection| {
+            let peer = peers
+                .iter()
+                .find(|peer| !joined.contains(&peer.id) && connection.is(peer))?;
+            joined.insert(peer.id);
+            Some(CorePeer {
+                connection_id: connection.connection_id,
+                node: node.to_string(),
+                peer_id: peer.id,
+                connection_type: peer
+                    .connection_type
+                    .as_deref()
+                    .and_then(CoreConnectionType::from_name),
+                ban_score: peer.banscore,
+                starting_height: peer.startingheight,
+                synced_headers: peer.synced_headers,
+                synced_blocks: peer.synced_blocks,
+                updated_at: now,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::mock_node;
+    use crate::config::RpcAuthConfig;
+    use app::{
+        ConnectionClosed, ConnectionOpened, ConnectionStats, LiveStats, NetworkType, P2pEvent,
+        StorageConfig,
+    };
+    use std::sync::Arc;
+
+    fn connection(connection_id: u64, client_addr: &str, target_addr: &str) -> LiveConnection {
+        LiveConnection {
+            connection_id,
+            node: "mainnet".to_string(),
+            client_addr: client_addr.to_string(),
+            target_addr: target_addr.to_string(),
+            local_addr: None,
+            network: Some(bitcoin::Network::Bitcoin),
+            inbound: false,
+            opened_at: Utc::now(),
+            stats: Arc::new(LiveStats::default()),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_polls_mock_node() {
+        let address = mock_node(
+            "__cookie__:abc123",
+            json!({
+                "getpeerinfo": [
+                    {
+                        "id": 7, "addr": "203.0.113.5:8333", "addrbind": "127.0.0.1:50001",
+                        "inbound": false, "connection_type": "outbound-full-relay",
+                        "startingheight": 850000, "synced_headers": 850010, "synced_blocks": 850009,
+                    },
+                    {
+                        "id": 8, "addr": "203.0.113.5:8333", "addrbind": "127.0.0.1:50002",
+                        "inbound": false, "connection_type": "block-relay-only",
+                        "startingheight": 850001, "synced_headers": -1, "synced_blocks": -1,
+                    },
+                    {
+                        "id": 9, "addr": "198.51.100.1:8333", "addrbind": "127.0.0.1:50003",
+                        "inbound": false, "connection_type": "feeler",
+                    },
+                    {
+                        "id": 10, "addr": "127.0.0.1:41000", "addrbind": "127.0.0.1:8334",
+                        "inbound": true, "connection_type": "inbound",
+                    },
+                    {
+                        "id": 11, "addr": "127.0.0.1:41001", "addrbind": "127.0.0.1:8334",
+                        "inbound": true, "connection_type": "inbound",
+                    },
+                ],
+                "getnetworkinfo": {
+                    "version": 270000, "subversion": "/Satoshi:27.0.0/", "connections": 4,
+                    "connections_in": 1, "connections_out": 3,
+                },
+                "getblockchaininfo": {
+                    "chain": "main", "blocks": 850009, "headers": 850010,
+                    "verificationprogress": 0.9999, "initialblockdownload": false,
+                },
+                "getnettotals": {"totalbytesrecv": 1000, "totalbytessent": 2000},
+                "getmempoolinfo": {"size": 3000, "bytes": 4000},
+            }),
+        )
+        .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let cookie_file = dir.path().join(".cookie");
+        std::fs::write(&cookie_file, "__cookie__:abc123\n").unwrap();
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+
+        // The proxy already closed the connection of peer 9
+        let opened_at = Utc::now();
+        app.publish(P2pEvent::ConnectionOpened(ConnectionOpened {
+            connection_id: 4,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:50003".to_string(),
+            target_addr: "198.51.100.1:8333".to_string(),
+            peer_port: None,
+            local_addr: None,
+            network: Some(bitcoin::Network::Bitcoin),
+            network_type: NetworkType::Ipv4,
+            inbound: false,
+            timestamp: opened_at,
+        }));
+        app.publish(P2pEvent::ConnectionClosed(ConnectionClosed {
+            connection_id: 4,
+            node: "mainnet".to_string(),
+            stats: ConnectionStats::default(),
+            timestamp: Utc::now(),
+        }));
+        while app
+            .store()
+            .connection(4)
+            .await
+            .unwrap()
+            .is_none_or(|connection| connection.closed_at.is_none())
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let inbound = LiveConnection {
+            client_addr: "127.0.0.1:8334".to_string(),
+            local_addr: Some("127.0.0.1:41000".to_string()),
+            inbound: true,
+            ..connection(5, "", "192.0.2.7")
+        };
+        let _registrations = [
+            app.connections().register(inbound),
+            app.connections()
+                .register(connection(1, "127.0.0.1:50001", "203.0.113.5:8333")),
+            app.connections()
+                .register(connection(2, "127.0.0.1:50002", "203.0.113.5:8333")),
+            // Same target, but Bitcoin Core's socket belongs to another connection
+            app.connections()
+                .register(connection(3, "127.0.0.1:50009", "198.51.100.1:8333")),
+        ];
+
+        let config = RpcConfig {
+            node: "mainnet".to_string(),
+            address,
+            cookie_file: Some(cookie_file),
+            auth: None,
+            poll_interval_secs: 30,
+            timeout_secs: 5,
+        };
+        let collector = Collector::new(config.clone(), app.clone()).unwrap();
+        assert_eq!(collector.poll().await.unwrap(), 4);
+
+        let mut peers = app.store().core_peers(&[1, 2, 3, 4, 5]).await.unwrap();
+        peers.sort_by_key(|peer| peer.connection_id);
+        assert_eq!(peers.len(), 4);
+        assert_eq!(peers[0].peer_id, 7);
+        assert_eq!(
+            peers[0].connection_type,
+            Some(CoreConnectionType::OutboundFullRelay)
+        );
+        assert_eq!(peers[0].synced_blocks, Some(850_009));
+        assert_eq!(peers[1].peer_id, 8);
+        assert_eq!(
+            peers[1].connection_type,
+            Some(CoreConnectionType::BlockRelayOnly)
+        );
+        assert_eq!(peers[2].connection_id, 4);
+        assert_eq!(peers[2].peer_id, 9);
+        assert_eq!(peers[3].connection_id, 5);
+        assert_eq!(peers[3].peer_id, 10);
+
+        let status = app
+            .store()
+            .node_status(&["mainnet".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(status[0].subversion, "/Satoshi:27.0.0/");
+        assert_eq!(status[0].connections_out, 3);
+        assert_eq!(status[0].mempool_bytes, 4000);
+
+        // Wrong credentials fail the poll instead of recording anything
+        let collector = Collector::new(
+            RpcConfig {
+                cookie_file: None,
+                auth: Some(RpcAuthConfig {
+                    username: "__cookie__".to_string(),
+                    password: "stale".to_string(),
+                }),
+                ..config
+            },
+            app,
+        )
+        .unwrap();
+        assert!(collector.poll().await.is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// Polling of a node's RPC interface
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcConfig {
+    /// Label of the node, the same as for its proxy
+    pub node: String,
+
+    /// RPC address of the node, e.g. 127.0.0.1:8332
+    #[serde(default = "default_address")]
+    pub address: String,
+
+    /// Cookie file written by bitcoind, read again on every poll as it changes on restart
+    #[serde(default)]
+    pub cookie_file: Option<PathBuf>,
+
+    /// The node's rpcuser and rpcpassword, used when no cookie file is set
+    #[serde(default)]
+    pub auth: Option<RpcAuthConfig>,
+
+    /// Seconds between polls
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+
+    /// Seconds a single RPC call may take
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcAuthConfig {
+    pub username: String,
+    pub password: String,
+}
+
+fn default_address() -> String {
+    "127.0.0.1:8332".to_string()
+}
+
+fn default_poll_interval_secs() -> u64 {
+    30
+}
+
+fn default_timeout_secs() -> u64 {
+    10
+}
@@ -0,0 +1,33 @@
+mod client;
+mod collector;
+mod config;
+mod types;
+
+pub use client::{Credentials, RpcClient};
+pub use collector::{Collector, ProxiedConnection, match_peers};
+pub use config::{RpcAuthConfig, RpcConfig};
+pub use types::*;
+
+use app::{NodeScopeApp, ShutdownPhase};
+use tokio::task::JoinSet;
+use tracing::info;
+
+/// Poll the RPC interface of every configured node until the app starts shutting down
+pub async fn run(configs: Vec<RpcConfig>, app: NodeScopeApp) -> anyhow::Result<()> {
+    let mut collectors = JoinSet::new();
+    for config in configs {
+        info!(
+            "[node:{}] Polling Bitcoin Core RPC at {} every {}s",
+            config.node, config.address, config.poll_interval_secs
+        );
+        let collector = Collector::new(config, app.clone())?;
+        collectors.spawn(async move { collector.run().await });
+    }
+
+    // Without any node to poll this still waits, returning would shut the app down
+    app.shutdown().reached(ShutdownPhase::Draining).await;
+    while let Some(result) = collectors.join_next().await {
+        result?;
+    }
+    Ok(())
+}
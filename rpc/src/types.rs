@@ -0,0 +1,58 @@
+use serde::Deserialize;
+
+/// Entry of `getpeerinfo`, only the fields NodeScope records
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerInfo {
+    pub id: i64,
+    /// Address of the peer, for proxied peers the target requested from the proxy
+    pub addr: String,
+    /// Local address of the socket, for proxied peers the end connected to the proxy
+    #[serde(default)]
+    pub addrbind: Option<String>,
+    pub inbound: bool,
+    /// Since Bitcoin Core v0.21
+    #[serde(default)]
+    pub connection_type: Option<String>,
+    /// Legacy, only reported by Bitcoin Core before v22 dropped ban scores
+    #[serde(default)]
+    pub banscore: Option<i64>,
+    #[serde(default)]
+    pub startingheight: Option<i64>,
+    #[serde(default)]
+    pub synced_headers: Option<i64>,
+    #[serde(default)]
+    pub synced_blocks: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetworkInfo {
+    pub version: i64,
+    pub subversion: String,
+    pub connections: i64,
+    /// Since Bitcoin Core v0.21
+    #[serde(default)]
+    pub connections_in: Option<i64>,
+    #[serde(default)]
+    pub connections_out: Option<i64>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct BlockchainInfo {
+    pub chain: String,
+    pub blocks: i64,
+    pub headers: i64,
+    pub verificationprogress: f64,
+    pub initialblockdownload: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct NetTotals {
+    pub totalbytesrecv: i64,
+    pub totalbytessent: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MempoolInfo {
+    pub size: i64,
+    pub bytes: i64,
+}
@@ -0,0 +1,554 @@
+use std::collections::HashMap;
+
+use async_graphql::dataloader::Loader;
+
+use app::{
+    AnnouncementRecord, ConnectFailureRecord, ConnectionId, ConnectionRecord, ConnectionRelayStats,
+    CorePeer, FeeFilterRecord, Handshake, InventoryStats, LogEventRecord, MessageRecord,
+    NodeStatus, PacketStats, ParseHealth, PeerRecord, PeerTransportCount, PingRttRecord,
+    PropagationStats, Store,
+};
+
+/// Batches nested lookups into single queries against the store
+pub struct StoreLoader {
+    store: Store,
+}
+
+impl StoreLoader {
+    pub fn new(store: Store) -> Self {
+        Self { store }
+    }
+}
+
+/// Load a peer by its address, as seen by a node or by all nodes
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PeerByAddress {
+    pub address: String,
+    pub node: Option<String>,
+}
+
+/// Load a page of the connections made to a peer, newest first
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConnectionsByPeer {
+    pub address: String,
+    /// Only the connections of this node, when given
+    pub node: Option<String>,
+    pub before: Option<ConnectionId>,
+    pub limit: usize,
+}
+
+/// Load the number of connections per transport to a peer
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransportsByPeer {
+    pub address: String,
+    /// Only the connections of this node, when given
+    pub node: Option<String>,
+}
+
+/// Load the handshake of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HandshakeByConnection(pub ConnectionId);
+
+/// Load the failed connect of a connection id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectFailureByConnection(pub ConnectionId);
+
+/// Load the last `limit` messages of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RecentMessages {
+    pub connection_id: ConnectionId,
+    pub limit: usize,
+}
+
+/// Load the last `limit` ping round trips of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RecentPingRtts {
+    pub connection_id: ConnectionId,
+    pub limit: usize,
+}
+
+/// Load the parse health of both directions of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ParseHealthByConnection(pub ConnectionId);
+
+/// Load the final packet statistics of both directions of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PacketStatsByConnection(pub ConnectionId);
+
+/// Load Bitcoin Core's view of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CorePeerByConnection(pub ConnectionId);
+
+/// Load the latest RPC status of a node
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NodeStatusByNode(pub String);
+
+/// Load the debug.log events matched to a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LogEventsByConnection(pub ConnectionId);
+
+/// Load the peers' announcements of an item the node accepted, by the acceptance's id
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AnnouncementsByAcceptance(pub i64);
+
+/// Load how early a connection's peer announced the items the node accepted
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PropagationByConnection(pub ConnectionId);
+
+/// Load what a connection's peer announced and was asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct InventoryByConnection(pub ConnectionId);
+
+/// Load the transaction relay statistics of a connection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RelayStatsByConnection(pub ConnectionId);
+
+/// Load the fee filters a connection's peer sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FeeFiltersByConnection(pub ConnectionId);
+
+fn to_error(e: anyhow::Error) -> async_graphql::Error {
+    async_graphql::Error::new(format!("{:#}", e))
+}
+
+impl Loader<PeerByAddress> for StoreLoader {
+    type Value = PeerRecord;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[PeerByAddress],
+    ) -> Result<HashMap<PeerByAddress, Self::Value>, Self::Error> {
+        let mut by_node: HashMap<Option<String>, Vec<String>> = HashMap::new();
+        for key in keys {
+            by_node
+                .entry(key.node.clone())
+                .or_default()
+                .push(key.address.clone());
+        }
+
+        let mut result = HashMap::new();
+        for (node, addresses) in by_node {
+            let peers = self
+                .store
+                .peers_by_address(&addresses, node.as_deref())
+                .await
+                .map_err(to_error)?;
+            for peer in peers {
+                let key = PeerByAddress {
+                    address: peer.address.clone(),
+                    node: node.clone(),
+                };
+                result.insert(key, peer);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<ConnectionsByPeer> for StoreLoader {
+    type Value = Vec<ConnectionRecord>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[ConnectionsByPeer],
+    ) -> Result<HashMap<ConnectionsByPeer, Self::Value>, Self::Error> {
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+
+        // Keys are grouped by node and page so each distinct page needs a single query
+        type Page = (Option<String>, Option<ConnectionId>, usize);
+        let mut by_page: HashMap<Page, Vec<String>> = HashMap::new();
+        for key in keys {
+            by_page
+                .entry((key.node.clone(), key.before, key.limit))
+                .or_default()
+                .push(key.address.clone());
+        }
+
+        for ((node, before, limit), addresses) in by_page {
+            let connections = self
+                .store
+                .connections_by_peer(&addresses, node.as_deref(), before, limit)
+                .await
+                .map_err(to_error)?;
+            for connection in connections {
+                result
+                    .entry(ConnectionsByPeer {
+                        address: connection.target_addr.clone(),
+                        node: node.clone(),
+                        before,
+                        limit,
+                    })
+                    .or_default()
+                    .push(connection);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<TransportsByPeer> for StoreLoader {
+    type Value = Vec<PeerTransportCount>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[TransportsByPeer],
+    ) -> Result<HashMap<TransportsByPeer, Self::Value>, Self::Error> {
+        let mut by_node: HashMap<Option<String>, Vec<String>> = HashMap::new();
+        for key in keys {
+            by_node
+                .entry(key.node.clone())
+                .or_default()
+                .push(key.address.clone());
+        }
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for (node, addresses) in by_node {
+            let counts = self
+                .store
+                .peer_transports(&addresses, node.as_deref())
+                .await
+                .map_err(to_error)?;
+            for count in counts {
+                result
+                    .entry(TransportsByPeer {
+                        address: count.address.clone(),
+                        node: node.clone(),
+                    })
+                    .or_default()
+                    .push(count);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<HandshakeByConnection> for StoreLoader {
+    type Value = Handshake;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[HandshakeByConnection],
+    ) -> Result<HashMap<HandshakeByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let handshakes = self.store.handshakes(&ids).await.map_err(to_error)?;
+
+        Ok(handshakes
+            .into_iter()
+            .map(|handshake| (HandshakeByConnection(handshake.connection_id), handshake))
+            .collect())
+    }
+}
+
+impl Loader<ConnectFailureByConnection> for StoreLoader {
+    type Value = ConnectFailureRecord;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[ConnectFailureByConnection],
+    ) -> Result<HashMap<ConnectFailureByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let failures = self.store.connect_failures(&ids).await.map_err(to_error)?;
+
+        Ok(failures
+            .into_iter()
+            .map(|failure| {
+                let id = failure.connection_id as ConnectionId;
+                (ConnectFailureByConnection(id), failure)
+            })
+            .collect())
+    }
+}
+
+impl Loader<RecentMessages> for StoreLoader {
+    type Value = Vec<MessageRecord>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[RecentMessages],
+    ) -> Result<HashMap<RecentMessages, Self::Value>, Self::Error> {
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+
+        // Keys are grouped by limit so each distinct limit needs a single query
+        let mut by_limit: HashMap<usize, Vec<ConnectionId>> = HashMap::new();
+        for key in keys {
+            by_limit
+                .entry(key.limit)
+                .or_default()
+                .push(key.connection_id);
+        }
+
+        for (limit, ids) in by_limit {
+            let messages = self
+                .store
+                .recent_messages(&ids, limit)
+                .await
+                .map_err(to_error)?;
+            for message in messages {
+                result
+                    .entry(RecentMessages {
+                        connection_id: message.connection_id as ConnectionId,
+                        limit,
+                    })
+                    .or_default()
+                    .push(message);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<RecentPingRtts> for StoreLoader {
+    type Value = Vec<PingRttRecord>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[RecentPingRtts],
+    ) -> Result<HashMap<RecentPingRtts, Self::Value>, Self::Error> {
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+
+        // Keys are grouped by limit so each distinct limit needs a single query
+        let mut by_limit: HashMap<usize, Vec<ConnectionId>> = HashMap::new();
+        for key in keys {
+            by_limit
+                .entry(key.limit)
+                .or_default()
+                .push(key.connection_id);
+        }
+
+        for (limit, ids) in by_limit {
+            let pings = self.store.ping_rtts(&ids, limit).await.map_err(to_error)?;
+            for ping in pings {
+                result
+                    .entry(RecentPingRtts {
+                        connection_id: ping.connection_id as ConnectionId,
+                        limit,
+                    })
+                    .or_default()
+                    .push(ping);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<ParseHealthByConnection> for StoreLoader {
+    type Value = Vec<ParseHealth>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[ParseHealthByConnection],
+    ) -> Result<HashMap<ParseHealthByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let health = self.store.parse_health(&ids).await.map_err(to_error)?;
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for health in health {
+            result
+                .entry(ParseHealthByConnection(health.connection_id))
+                .or_default()
+                .push(health);
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<PacketStatsByConnection> for StoreLoader {
+    type Value = Vec<PacketStats>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[PacketStatsByConnection],
+    ) -> Result<HashMap<PacketStatsByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let stats = self.store.packet_stats(&ids).await.map_err(to_error)?;
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for stats in stats {
+            result
+                .entry(PacketStatsByConnection(stats.connection_id))
+                .or_default()
+                .push(stats);
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<CorePeerByConnection> for StoreLoader {
+    type Value = CorePeer;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[CorePeerByConnection],
+    ) -> Result<HashMap<CorePeerByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let peers = self.store.core_peers(&ids).await.map_err(to_error)?;
+
+        Ok(peers
+            .into_iter()
+            .map(|peer| (CorePeerByConnection(peer.connection_id), peer))
+            .collect())
+    }
+}
+
+impl Loader<NodeStatusByNode> for StoreLoader {
+    type Value = NodeStatus;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[NodeStatusByNode],
+    ) -> Result<HashMap<NodeStatusByNode, Self::Value>, Self::Error> {
+        let nodes: Vec<String> = keys.iter().map(|key| key.0.clone()).collect();
+        let status = self.store.node_status(&nodes).await.map_err(to_error)?;
+
+        Ok(status
+            .into_iter()
+            .map(|status| (NodeStatusByNode(status.node.clone()), status))
+            .collect())
+    }
+}
+
+impl Loader<LogEventsByConnection> for StoreLoader {
+    type Value = Vec<LogEventRecord>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[LogEventsByConnection],
+    ) -> Result<HashMap<LogEventsByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let events = self.store.log_events(&ids).await.map_err(to_error)?;
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for event in events {
+            if let Some(connection_id) = event.connection_id {
+                result
+                    .entry(LogEventsByConnection(connection_id as ConnectionId))
+                    .or_default()
+                    .push(event);
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<AnnouncementsByAcceptance> for StoreLoader {
+    type Value = Vec<AnnouncementRecord>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[AnnouncementsByAcceptance],
+    ) -> Result<HashMap<AnnouncementsByAcceptance, Self::Value>, Self::Error> {
+        let ids: Vec<i64> = keys.iter().map(|key| key.0).collect();
+        let announcements = self
+            .store
+            .acceptance_announcements(&ids)
+            .await
+            .map_err(to_error)?;
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for announcement in announcements {
+            result
+                .entry(AnnouncementsByAcceptance(announcement.acceptance_id))
+                .or_default()
+                .push(announcement);
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<PropagationByConnection> for StoreLoader {
+    type Value = Vec<PropagationStats>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[PropagationByConnection],
+    ) -> Result<HashMap<PropagationByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let stats = self.store.propagation_stats(&ids).await.map_err(to_error)?;
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for stats in stats {
+            result
+                .entry(PropagationByConnection(stats.connection_id as ConnectionId))
+                .or_default()
+                .push(stats);
+        }
+        Ok(result)
+    }
+}
+
+impl Loader<InventoryByConnection> for StoreLoader {
+    type Value = InventoryStats;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[InventoryByConnection],
+    ) -> Result<HashMap<InventoryByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let stats = self.store.inventory_stats(&ids).await.map_err(to_error)?;
+
+        Ok(stats
+            .into_iter()
+            .map(|stats| {
+                (
+                    InventoryByConnection(stats.connection_id as ConnectionId),
+                    stats,
+                )
+            })
+            .collect())
+    }
+}
+
+impl Loader<RelayStatsByConnection> for StoreLoader {
+    type Value = ConnectionRelayStats;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[RelayStatsByConnection],
+    ) -> Result<HashMap<RelayStatsByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let stats = self.store.relay_stats(&ids).await.map_err(to_error)?;
+
+        Ok(stats
+            .into_iter()
+            .map(|stats| (RelayStatsByConnection(stats.connection_id), stats))
+            .collect())
+    }
+}
+
+impl Loader<FeeFiltersByConnection> for StoreLoader {
+    type Value = Vec<FeeFilterRecord>;
+    type Error = async_graphql::Error;
+
+    async fn load(
+        &self,
+        keys: &[FeeFiltersByConnection],
+    ) -> Result<HashMap<FeeFiltersByConnection, Self::Value>, Self::Error> {
+        let ids: Vec<ConnectionId> = keys.iter().map(|key| key.0).collect();
+        let filters = self.store.fee_filters(&ids).await.map_err(to_error)?;
+
+        let mut result: HashMap<_, Vec<_>> = HashMap::new();
+        for filter in filters {
+            result
+                .entry(FeeFiltersByConnection(filter.connection_id as ConnectionId))
+                .or_default()
+                .push(filter);
+        }
+        Ok(result)
+    }
+}
@@ -1,14 +1,28 @@
-use async_graphql::{EmptyMutation, EmptySubscription, Schema};
+use async_graphql::dataloader::DataLoader;
+use async_graphql::{EmptyMutation, Schema};
 
+mod loader;
+mod pagination;
 mod schema;
+mod subscription;
+mod types;
+
 pub use schema::*;
+pub use subscription::*;
 
 use app::NodeScopeApp;
+use loader::StoreLoader;
+
+pub type NodeScopeSchema = Schema<Query, EmptyMutation, Subscription>;
 
-pub fn schema(_app: Option<NodeScopeApp>) -> Schema<Query, EmptyMutation, EmptySubscription> {
-    let schema_builder = Schema::build(Query, EmptyMutation, EmptySubscription);
+pub fn schema(app: Option<NodeScopeApp>) -> NodeScopeSchema {
+    let mut schema_builder = Schema::build(Query, EmptyMutation, Subscription);
 
-    // TODO: Use dataloader
+    if let Some(app) = app {
+        // Batch nested peer -> connections -> messages lookups
+        let loader = DataLoader::new(StoreLoader::new(app.store().clone()), tokio::spawn);
+        schema_builder = schema_builder.data(loader).data(app);
+    }
 
     schema_builder.finish()
 }
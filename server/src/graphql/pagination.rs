@@ -0,0 +1,55 @@
+use std::future::Future;
+
+use async_graphql::connection::{self, CursorType, Edge};
+use async_graphql::{Error, OutputType, Result};
+
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Page forward through records, newest or oldest first as `fetch` returns them
+///
+/// `fetch` is given the cursor of the last record of the previous page and how
+/// many records to return at most. Asking for one more than the page size tells
+/// whether there's a next page.
+pub(super) async fn paginate<K, R, N, F, Fut>(
+    after: Option<String>,
+    first: Option<i32>,
+    fetch: F,
+    cursor: impl Fn(&R) -> K,
+    node: impl Fn(R) -> N,
+) -> Result<connection::Connection<K, N>>
+where
+    K: CursorType + Send + Sync,
+    K::Error: Send + Sync + 'static,
+    N: OutputType,
+    F: FnOnce(Option<K>, usize) -> Fut,
+    Fut: Future<Output = Result<Vec<R>>>,
+{
+    connection::query(
+        after,
+        None,
+        first,
+        None,
+        |after: Option<K>, _, first, _| async move {
+            let limit = page_size(first);
+            let has_previous_page = after.is_some();
+            let mut records = fetch(after, limit + 1).await?;
+
+            let has_next_page = records.len() > limit;
+            records.truncate(limit);
+
+            let mut page = connection::Connection::new(has_previous_page, has_next_page);
+            page.edges.extend(
+                records
+                    .into_iter()
+                    .map(|record| Edge::new(cursor(&record), node(record))),
+            );
+            Ok::<_, Error>(page)
+        },
+    )
+    .await
+}
+
+fn page_size(first: Option<usize>) -> usize {
+    first.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE)
+}
@@ -1,10 +1,373 @@
+use async_graphql::connection;
 use async_graphql::*;
 
+use app::NodeScopeApp;
+
+use super::pagination::paginate;
+use super::types::*;
+
 pub struct Query;
 
 #[Object]
 impl Query {
-    async fn hi(&self, _ctx: &Context<'_>) -> &str {
-        "Hello, World!"
+    /// Connections, newest first
+    async fn connections(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+        filter: Option<ConnectionFilter>,
+    ) -> Result<connection::Connection<u64, Connection>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter: app::ConnectionFilter = filter.unwrap_or_default().into();
+
+        paginate(
+            after,
+            first,
+            |after, limit| async move {
+                Ok(app.store().list_connections(&filter, after, limit).await?)
+            },
+            |record| record.connection_id(),
+            Connection,
+        )
+        .await
+    }
+
+    async fn connection(&self, ctx: &Context<'_>, id: u64) -> Result<Option<Connection>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        Ok(app.store().connection(id).await?.map(Connection))
+    }
+
+    /// Nodes that had connections proxied, ordered by label
+    async fn nodes(&self, ctx: &Context<'_>) -> Result<Vec<Node>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        Ok(app.store().nodes().await?.into_iter().map(Node).collect())
+    }
+
+    /// Peers, ordered by address, only those a node had connections with when given
+    async fn peers(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+        node: Option<String>,
+    ) -> Result<connection::Connection<String, Peer>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+
+        paginate(
+            after,
+            first,
+            |after, limit| async move {
+                Ok(app
+                    .store()
+                    .list_peers(node.as_deref(), after.as_deref(), limit)
+                    .await?)
+            },
+            |record| record.address.clone(),
+            Peer,
+        )
+        .await
+    }
+
+    /// A peer, as seen by a node when given
+    async fn peer(
+        &self,
+        ctx: &Context<'_>,
+        address: String,
+        node: Option<String>,
+    ) -> Result<Option<Peer>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let peers = app
+            .store()
+            .peers_by_address(&[address], node.as_deref())
+            .await?;
+        Ok(peers.into_iter().next().map(Peer))
+    }
+
+    /// Handshakes, including those of connections that never got established, newest first
+    async fn handshakes(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+        filter: Option<HandshakeFilter>,
+    ) -> Result<connection::Connection<u64, Handshake>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter: app::HandshakeFilter = filter.unwrap_or_default().into();
+
+        paginate(
+            after,
+            first,
+            |after, limit| async move {
+                Ok(app.store().list_handshakes(&filter, after, limit).await?)
+            },
+            |record| record.connection_id,
+            Handshake,
+        )
+        .await
+    }
+
+    async fn handshake(&self, ctx: &Context<'_>, connection_id: u64) -> Result<Option<Handshake>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let handshakes = app.store().handshakes(&[connection_id]).await?;
+        Ok(handshakes.into_iter().next().map(Handshake))
+    }
+
+    /// Messages of a connection, oldest first
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        connection_id: u64,
+        after: Option<String>,
+        first: Option<i32>,
+        filter: Option<MessageFilter>,
+    ) -> Result<connection::Connection<i64, Message>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter: app::MessageFilter = filter.unwrap_or_default().into();
+
+        paginate(
+            after,
+            first,
+            |after, limit| async move {
+                Ok(app
+                    .store()
+                    .list_messages(connection_id, &filter, after, limit)
+                    .await?)
+            },
+            |record| record.id,
+            Message::from,
+        )
+        .await
+    }
+
+    /// Events from the nodes' debug.log, newest first
+    async fn log_events(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+        filter: Option<LogEventFilter>,
+    ) -> Result<connection::Connection<i64, LogEvent>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter: app::LogEventFilter = filter.unwrap_or_default().into();
+
+        paginate(
+            after,
+            first,
+            |after, limit| async move {
+                Ok(app.store().list_log_events(&filter, after, limit).await?)
+            },
+            |record| record.id,
+            LogEvent::from,
+        )
+        .await
+    }
+
+    /// Transaction relay summed over the connections of a node, or of all nodes
+    async fn tx_relay(&self, ctx: &Context<'_>, node: Option<String>) -> Result<TxRelaySummary> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let summary = app.store().relay_summary(node.as_deref()).await?;
+        Ok(summary.into())
+    }
+
+    /// Which peers announced a block or transaction and which it was requested from
+    async fn inventory_item(&self, hash: String, node: Option<String>) -> InventoryItem {
+        InventoryItem { node, hash }
+    }
+
+    /// Blocks and transactions the nodes accepted, newest first
+    async fn acceptances(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+        filter: Option<AcceptanceFilter>,
+    ) -> Result<connection::Connection<i64, Acceptance>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter: app::AcceptanceFilter = filter.unwrap_or_default().into();
+
+        paginate(
+            after,
+            first,
+            |after, limit| async move {
+                Ok(app.store().list_acceptances(&filter, after, limit).await?)
+            },
+            |record| record.id,
+            Acceptance,
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use std::time::Duration;
+
+    use app::{ConnectionOpened, NetworkType, P2pEvent, StorageConfig};
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::super::{NodeScopeSchema, schema};
+    use super::*;
+
+    /// App with a connection to each of the targets, recorded in order
+    pub(crate) async fn app_with_connections(
+        dir: &tempfile::TempDir,
+        targets: &[&str],
+    ) -> NodeScopeApp {
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+
+        for target in targets {
+            app.publish(P2pEvent::ConnectionOpened(ConnectionOpened {
+                connection_id: app.next_connection_id(),
+                node: "mainnet".to_string(),
+                client_addr: "127.0.0.1:50000".to_string(),
+                target_addr: target.to_string(),
+                peer_port: None,
+                local_addr: None,
+                network: None,
+                network_type: NetworkType::Ipv4,
+                inbound: false,
+                timestamp: Utc::now(),
+            }));
+        }
+
+        let filter = app::ConnectionFilter::default();
+        for _ in 0..100 {
+            let recorded = app
+                .store()
+                .list_connections(&filter, None, targets.len())
+                .await
+                .unwrap();
+            if recorded.len() == targets.len() {
+                return app;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("connections weren't recorded");
+    }
+
+    async fn execute(schema: &NodeScopeSchema, query: &str) -> serde_json::Value {
+        let response = schema.execute(query).await;
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_pages_through_connections() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_connections(&dir, &["1.2.3.4:8333"; 3]).await;
+        let schema = schema(Some(app));
+
+        let first = execute(
+            &schema,
+            "{ connections(first: 2) {
+                 edges { cursor node { id } }
+                 pageInfo { hasNextPage }
+               } }",
+        )
+        .await;
+        assert_eq!(first["connections"]["edges"][0]["node"]["id"], 2);
+        assert_eq!(first["connections"]["edges"][1]["node"]["id"], 1);
+        assert_eq!(first["connections"]["pageInfo"]["hasNextPage"], true);
+
+        let cursor = first["connections"]["edges"][1]["cursor"].as_str().unwrap();
+        let rest = execute(
+            &schema,
+            &format!(
+                "{{ connections(first: 2, after: \"{}\") {{
+                     edges {{ node {{ id }} }}
+                     pageInfo {{ hasNextPage hasPreviousPage }}
+                   }} }}",
+                cursor
+            ),
+        )
+        .await;
+        assert_eq!(
+            rest["connections"],
+            json!({
+                "edges": [{ "node": { "id": 0 } }],
+                "pageInfo": { "hasNextPage": false, "hasPreviousPage": true },
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_loads_connections_of_each_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_connections(
+            &dir,
+            &[
+                "1.2.3.4:8333",
+                "5.6.7.8:8333",
+                "1.2.3.4:8333",
+                "5.6.7.8:8333",
+            ],
+        )
+        .await;
+        let schema = schema(Some(app));
+
+        // Every peer's page is loaded in one batch, limited per peer
+        let peers = execute(
+            &schema,
+            "{ peers { edges { node {
+                 address
+                 connections(first: 1) { edges { node { id } } pageInfo { hasNextPage } }
+               } } } }",
+        )
+        .await;
+        assert_eq!(
+            peers["peers"]["edges"],
+            json!([
+                { "node": {
+                    "address": "1.2.3.4:8333",
+                    "connections": {
+                        "edges": [{ "node": { "id": 2 } }],
+                        "pageInfo": { "hasNextPage": true },
+                    },
+                } },
+                { "node": {
+                    "address": "5.6.7.8:8333",
+                    "connections": {
+                        "edges": [{ "node": { "id": 3 } }],
+                        "pageInfo": { "hasNextPage": true },
+                    },
+                } },
+            ])
+        );
+
+        let cursor = execute(
+            &schema,
+            "{ peer(address: \"5.6.7.8:8333\") { connections(first: 1) { edges { cursor } } } }",
+        )
+        .await["peer"]["connections"]["edges"][0]["cursor"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let rest = execute(
+            &schema,
+            &format!(
+                "{{ peer(address: \"5.6.7.8:8333\") {{
+                     connections(after: \"{}\") {{ edges {{ node {{ id targetAddr }} }} }}
+                   }} }}",
+                cursor
+            ),
+        )
+        .await;
+        assert_eq!(
+            rest["peer"]["connections"]["edges"],
+            json!([{ "node": { "id": 1, "targetAddr": "5.6.7.8:8333" } }])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_queries_without_app_fail() {
+        let schema = schema(None);
+        let response = schema.execute("{ nodes { label } }").await;
+        assert_eq!(response.errors.len(), 1);
     }
 }
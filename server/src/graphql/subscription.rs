@@ -0,0 +1,429 @@
+use std::collections::HashMap;
+
+use async_graphql::futures_util::Stream;
+use async_graphql::*;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::warn;
+
+use app::{ConnectionId, NodeScopeApp, P2pEvent};
+
+use super::types::*;
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Live stream of parsed P2P messages
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<MessageStreamFilter>,
+    ) -> Result<impl Stream<Item = Message>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter = filter.unwrap_or_default();
+        let mut events = app.subscribe();
+        let mut peers = PeerAddresses::new(app.clone());
+
+        Ok(async_stream::stream! {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Message subscription fell behind, skipped {} events", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                match event {
+                    P2pEvent::ConnectionOpened(e) => peers.insert(e.connection_id, e.target_addr),
+                    P2pEvent::MessageSeen(e) => {
+                        if !filter.matches_connection(e.connection_id)
+                            || filter.node.as_ref().is_some_and(|node| *node != e.node)
+                            || filter.direction.is_some_and(|d| app::Direction::from(d) != e.direction)
+                            || !filter.commands.as_ref().is_none_or(|c| c.contains(&e.command))
+                        {
+                            continue;
+                        }
+                        if let Some(peer_addr) = &filter.peer_addr
+                            && peers.get(e.connection_id).await.as_ref() != Some(peer_addr)
+                        {
+                            continue;
+                        }
+                        yield Message::from(&e);
+                    }
+                    P2pEvent::ConnectionClosed(e) => peers.remove(e.connection_id),
+                    P2pEvent::ConnectionFailed(_)
+                    | P2pEvent::HandshakeUpdated(_)
+                    | P2pEvent::PingMeasured(_)
+                    | P2pEvent::ParseHealthUpdated(_)
+                    | P2pEvent::TransportDetected(_)
+                    | P2pEvent::NetworkDetected(_)
+                    | P2pEvent::PacketStatsRecorded(_)
+                    | P2pEvent::ItemsAnnounced(_)
+                    | P2pEvent::ItemsRequested(_)
+                    | P2pEvent::ItemsNotFound(_)
+                    | P2pEvent::TxReceived(_)
+                    | P2pEvent::FeeFilterReceived(_) => {}
+                }
+            }
+        })
+    }
+
+    /// Live stream of connections being opened, failing to open and closed
+    async fn connection_events(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<ConnectionEventFilter>,
+    ) -> Result<impl Stream<Item = ConnectionEvent>> {
+        let app = ctx.data::<NodeScopeApp>()?;
+        let filter = filter.unwrap_or_default();
+        let mut events = app.subscribe();
+        let mut peers = PeerAddresses::new(app.clone());
+
+        Ok(async_stream::stream! {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        warn!("Connection subscription fell behind, skipped {} events", n);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let event = match event {
+                    P2pEvent::ConnectionOpened(e) => {
+                        peers.insert(e.connection_id, e.target_addr.clone());
+                        ConnectionEvent {
+                            kind: ConnectionEventKind::Opened,
+                            connection_id: e.connection_id,
+                            node: e.node,
+                            target_addr: Some(e.target_addr),
+                            client_addr: Some(e.client_addr),
+                            connect_error: None,
+                            stats: None,
+                            timestamp: e.timestamp,
+                        }
+                    }
+                    P2pEvent::ConnectionFailed(e) => ConnectionEvent {
+                        kind: ConnectionEventKind::Failed,
+                        connection_id: e.connection_id,
+                        node: e.node,
+                        target_addr: Some(e.target_addr),
+                        client_addr: Some(e.client_addr),
+                        connect_error: Some(e.error.into()),
+                        stats: None,
+                        timestamp: e.timestamp,
+                    },
+                    P2pEvent::ConnectionClosed(e) => {
+                        // Connections opened before subscribing are only looked up to filter them
+                        let target_addr = if filter.peer_addr.is_some() {
+                            peers.get(e.connection_id).await
+                        } else {
+                            peers.cached(e.connection_id)
+                        };
+                        peers.remove(e.connection_id);
+                        ConnectionEvent {
+                            kind: ConnectionEventKind::Closed,
+                            connection_id: e.connection_id,
+                            node: e.node,
+                            target_addr,
+                            client_addr: None,
+                            connect_error: None,
+                            stats: Some(e.stats.into()),
+                            timestamp: e.timestamp,
+                        }
+                    }
+                    P2pEvent::MessageSeen(_)
+                    | P2pEvent::HandshakeUpdated(_)
+                    | P2pEvent::PingMeasured(_)
+                    | P2pEvent::ParseHealthUpdated(_)
+                    | P2pEvent::TransportDetected(_)
+                    | P2pEvent::NetworkDetected(_)
+                    | P2pEvent::PacketStatsRecorded(_)
+                    | P2pEvent::ItemsAnnounced(_)
+                    | P2pEvent::ItemsRequested(_)
+                    | P2pEvent::ItemsNotFound(_)
+                    | P2pEvent::TxReceived(_)
+                    | P2pEvent::FeeFilterReceived(_) => continue,
+                };
+
+                if filter.connection_id.is_some_and(|id| id != event.connection_id)
+                    || filter.node.as_ref().is_some_and(|node| *node != event.node)
+                    || filter.peer_addr.as_ref().is_some_and(|addr| event.target_addr.as_ref() != Some(addr))
+                {
+                    continue;
+                }
+                yield event;
+            }
+        })
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct MessageStreamFilter {
+    /// Label of the node, all nodes when unset
+    pub node: Option<String>,
+    pub connection_id: Option<u64>,
+    /// Address of the target peer, e.g. "1.2.3.4:8333"
+    pub peer_addr: Option<String>,
+    pub direction: Option<Direction>,
+    /// Only these commands, e.g. ["inv", "tx"]
+    pub commands: Option<Vec<String>>,
+}
+
+impl MessageStreamFilter {
+    fn matches_connection(&self, connection_id: ConnectionId) -> bool {
+        self.connection_id.is_none_or(|id| id == connection_id)
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct ConnectionEventFilter {
+    /// Label of the node, all nodes when unset
+    pub node: Option<String>,
+    pub connection_id: Option<u64>,
+    pub peer_addr: Option<String>,
+}
+
+/// Target addresses of connections, falling back to the open connections and
+/// then the store for connections opened before the subscription started
+struct PeerAddresses {
+    app: NodeScopeApp,
+    addresses: HashMap<ConnectionId, String>,
+}
+
+impl PeerAddresses {
+    fn new(app: NodeScopeApp) -> Self {
+        Self {
+            app,
+            addresses: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, connection_id: ConnectionId, target_addr: String) {
+        self.addresses.insert(connection_id, target_addr);
+    }
+
+    fn remove(&mut self, connection_id: ConnectionId) {
+        self.addresses.remove(&connection_id);
+    }
+
+    /// The address if the connection was already seen, without asking the store
+    fn cached(&self, connection_id: ConnectionId) -> Option<String> {
+        self.addresses.get(&connection_id).cloned()
+    }
+
+    /// The address, looked up once per connection
+    ///
+    /// Misses aren't remembered: the store writes connections in batches, so one
+    /// that is open but not stored yet may be found on the next lookup.
+    async fn get(&mut self, connection_id: ConnectionId) -> Option<String> {
+        if let Some(addr) = self.addresses.get(&connection_id) {
+            return Some(addr.clone());
+        }
+
+        let addr = match self.app.connections().get(connection_id) {
+            Some(connection) => connection.target_addr,
+            None => match self.app.store().connection(connection_id).await {
+                Ok(record) => record?.target_addr,
+                Err(e) => {
+                    warn!("[conn:{}] Failed to look up peer: {:#}", connection_id, e);
+                    return None;
+                }
+            },
+        };
+        self.addresses.insert(connection_id, addr.clone());
+        Some(addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use app::{ConnectionClosed, ConnectionStats, LiveConnection, LiveStats, MessageSeen};
+    use async_graphql::futures_util::StreamExt;
+    use bitcoin::p2p::message::NetworkMessage;
+    use chrono::Utc;
+    use serde_json::json;
+
+    use super::super::schema::tests::app_with_connections;
+    use super::super::{NodeScopeSchema, schema};
+    use super::*;
+
+    /// Start a subscription, waiting until it listens for events
+    async fn subscribe<'a>(
+        schema: &'a NodeScopeSchema,
+        query: &str,
+    ) -> impl Stream<Item = Response> + Unpin + 'a {
+        let mut stream = schema.execute_stream(query.to_string());
+        assert!(
+            tokio::time::timeout(Duration::from_millis(50), stream.next())
+                .await
+                .is_err()
+        );
+        stream
+    }
+
+    async fn next(stream: &mut (impl Stream<Item = Response> + Unpin)) -> serde_json::Value {
+        let response = tokio::time::timeout(Duration::from_secs(5), stream.next())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        response.data.into_json().unwrap()
+    }
+
+    fn closed(connection_id: ConnectionId, node: &str) -> P2pEvent {
+        P2pEvent::ConnectionClosed(ConnectionClosed {
+            connection_id,
+            node: node.to_string(),
+            stats: ConnectionStats::default(),
+            timestamp: Utc::now(),
+        })
+    }
+
+    fn verack(connection_id: ConnectionId, node: &str) -> P2pEvent {
+        P2pEvent::MessageSeen(MessageSeen {
+            connection_id,
+            node: node.to_string(),
+            direction: app::Direction::Outbound,
+            command: "verack".to_string(),
+            payload_len: 0,
+            description: "verack".to_string(),
+            message: Arc::new(NetworkMessage::Verack),
+            received_at: Utc::now(),
+            forwarded_at: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_streams_closed_connections_opened_before_subscribing() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_connections(&dir, &["1.2.3.4:8333", "5.6.7.8:8333"]).await;
+        let schema = schema(Some(app.clone()));
+        let mut all = subscribe(
+            &schema,
+            "subscription { connectionEvents { kind connectionId targetAddr } }",
+        )
+        .await;
+        let mut filtered = subscribe(
+            &schema,
+            "subscription { connectionEvents(filter: { peerAddr: \"5.6.7.8:8333\" }) {
+               kind connectionId targetAddr
+             } }",
+        )
+        .await;
+
+        app.publish(closed(0, "mainnet"));
+        app.publish(closed(1, "mainnet"));
+
+        // Without a filter the store isn't asked for the address
+        assert_eq!(
+            next(&mut all).await["connectionEvents"],
+            json!({ "kind": "CLOSED", "connectionId": 0, "targetAddr": null })
+        );
+        assert_eq!(
+            next(&mut all).await["connectionEvents"],
+            json!({ "kind": "CLOSED", "connectionId": 1, "targetAddr": null })
+        );
+        assert_eq!(
+            next(&mut filtered).await["connectionEvents"],
+            json!({ "kind": "CLOSED", "connectionId": 1, "targetAddr": "5.6.7.8:8333" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streams_messages_of_a_peer() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_connections(&dir, &["1.2.3.4:8333", "5.6.7.8:8333"]).await;
+        let schema = schema(Some(app.clone()));
+        let mut messages = subscribe(
+            &schema,
+            "subscription { messages(filter: { peerAddr: \"5.6.7.8:8333\" }) {
+               id connectionId command
+             } }",
+        )
+        .await;
+
+        for connection_id in [0, 1] {
+            app.publish(verack(connection_id, "mainnet"));
+        }
+
+        // Live messages aren't stored yet, so they have no id
+        assert_eq!(
+            next(&mut messages).await["messages"],
+            json!({ "id": null, "connectionId": 1, "command": "verack" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streams_messages_of_open_connections_not_stored_yet() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_connections(&dir, &["1.2.3.4:8333"]).await;
+        let schema = schema(Some(app.clone()));
+        let mut messages = subscribe(
+            &schema,
+            "subscription { messages(filter: { peerAddr: \"5.6.7.8:8333\" }) {
+               connectionId command
+             } }",
+        )
+        .await;
+
+        // Neither open nor stored yet, e.g. as its ConnectionOpened was skipped
+        app.publish(verack(7, "mainnet"));
+        let _registration = app.connections().register(LiveConnection {
+            connection_id: 7,
+            node: "mainnet".to_string(),
+            client_addr: "127.0.0.1:50000".to_string(),
+            target_addr: "5.6.7.8:8333".to_string(),
+            local_addr: None,
+            network: None,
+            inbound: false,
+            opened_at: Utc::now(),
+            stats: Arc::new(LiveStats::default()),
+        });
+        app.publish(verack(7, "mainnet"));
+
+        assert_eq!(
+            next(&mut messages).await["messages"],
+            json!({ "connectionId": 7, "command": "verack" })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streams_events_of_a_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = app_with_connections(&dir, &[]).await;
+        let schema = schema(Some(app.clone()));
+        let mut messages = subscribe(
+            &schema,
+            "subscription { messages(filter: { node: \"signet\" }) { connectionId node } }",
+        )
+        .await;
+        let mut events = subscribe(
+            &schema,
+            "subscription { connectionEvents(filter: { node: \"signet\" }) {
+               kind connectionId node
+             } }",
+        )
+        .await;
+
+        app.publish(verack(0, "mainnet"));
+        app.publish(verack(1, "signet"));
+        app.publish(closed(0, "mainnet"));
+        app.publish(closed(1, "signet"));
+
+        assert_eq!(
+            next(&mut messages).await["messages"],
+            json!({ "connectionId": 1, "node": "signet" })
+        );
+        assert_eq!(
+            next(&mut events).await["connectionEvents"],
+            json!({ "kind": "CLOSED", "connectionId": 1, "node": "signet" })
+        );
+    }
+}
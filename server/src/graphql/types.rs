@@ -0,0 +1,1342 @@
+use async_graphql::connection;
+use async_graphql::dataloader::DataLoader;
+use async_graphql::*;
+use chrono::{DateTime, Utc};
+
+use super::loader::{
+    AnnouncementsByAcceptance, ConnectFailureByConnection, ConnectionsByPeer, CorePeerByConnection,
+    FeeFiltersByConnection, HandshakeByConnection, InventoryByConnection, LogEventsByConnection,
+    NodeStatusByNode, PacketStatsByConnection, ParseHealthByConnection, PeerByAddress,
+    PropagationByConnection, RecentMessages, RecentPingRtts, RelayStatsByConnection, StoreLoader,
+    TransportsByPeer,
+};
+use super::pagination::paginate;
+
+/// Direction of a message, relative to the proxy client
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::Direction")]
+pub enum Direction {
+    /// Client -> target
+    Inbound,
+    /// Target -> client
+    Outbound,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::ParserState")]
+pub enum ParserState {
+    Synced,
+    Desynced,
+    Resynced,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::HandshakeOutcome")]
+pub enum HandshakeOutcome {
+    Pending,
+    Completed,
+    Failed,
+    /// The handshake happened inside a BIP324 encrypted transport
+    Encrypted,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::ConnectError")]
+pub enum ConnectError {
+    Refused,
+    HostUnreachable,
+    NetworkUnreachable,
+    /// No connection within the connect timeout
+    TimedOut,
+    Other,
+}
+
+/// Network a peer is reached on
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::NetworkType")]
+pub enum NetworkType {
+    Ipv4,
+    Ipv6,
+    Onion,
+    I2p,
+    Cjdns,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::Transport")]
+pub enum Transport {
+    /// Plaintext v1 messages
+    V1,
+    /// BIP324 encrypted transport
+    V2Encrypted,
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::HandshakeFailureReason")]
+pub enum HandshakeFailureReason {
+    /// The client's SOCKS5 negotiation failed
+    #[graphql(name = "SOCKS5_ERROR")]
+    Socks5Error,
+    /// The proxy couldn't connect to the requested target
+    ConnectFailed,
+    /// No verack was exchanged within the handshake timeout
+    Timeout,
+    /// One side closed the connection before the handshake completed
+    DisconnectedBeforeVerack,
+}
+
+/// How Bitcoin Core uses a connection
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::CoreConnectionType")]
+pub enum CoreConnectionType {
+    Inbound,
+    OutboundFullRelay,
+    BlockRelayOnly,
+    /// Added with addnode or -connect
+    Manual,
+    /// Short-lived connection to fetch addresses
+    AddrFetch,
+    /// Short-lived connection to test that a peer is reachable
+    Feeler,
+}
+
+/// What a line of a node's debug.log reports
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::LogEventKind")]
+pub enum LogEventKind {
+    PeerConnected,
+    /// The peer's version message, with its user agent
+    PeerVersion,
+    PeerDisconnected,
+    Misbehaving,
+    /// The node chose the peer to make room for others
+    Evicted,
+    UpdateTip,
+    LeftInitialBlockDownload,
+    MempoolAccepted,
+    MempoolRejected,
+    AddrmanAdded,
+}
+
+/// Whether an announced or accepted item is a block or a transaction
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::ItemKind")]
+pub enum ItemKind {
+    Block,
+    Tx,
+}
+
+/// The message a peer announced an item with
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+#[graphql(remote = "app::AnnouncedVia")]
+pub enum AnnouncedVia {
+    Inv,
+    Headers,
+    Cmpctblock,
+}
+
+#[derive(SimpleObject)]
+pub struct ConnectionStats {
+    pub bytes_inbound: u64,
+    pub bytes_outbound: u64,
+    pub messages_inbound: u64,
+    pub messages_outbound: u64,
+}
+
+impl From<app::ConnectionStats> for ConnectionStats {
+    fn from(stats: app::ConnectionStats) -> Self {
+        Self {
+            bytes_inbound: stats.bytes_inbound,
+            bytes_outbound: stats.bytes_outbound,
+            messages_inbound: stats.messages_inbound,
+            messages_outbound: stats.messages_outbound,
+        }
+    }
+}
+
+/// A node whose connections are proxied
+pub struct Node(pub app::NodeRecord);
+
+#[Object]
+impl Node {
+    /// Label of the node in the proxy config
+    async fn label(&self) -> &str {
+        &self.0.node
+    }
+
+    async fn network(&self) -> &str {
+        &self.0.network
+    }
+
+    /// Connections made so far, including closed ones
+    async fn connection_count(&self) -> u64 {
+        self.0.connections as u64
+    }
+
+    /// Connections that are currently open
+    async fn active_connections(&self, ctx: &Context<'_>) -> Result<u64> {
+        let app = ctx.data::<app::NodeScopeApp>()?;
+        Ok(app
+            .connections()
+            .list()
+            .iter()
+            .filter(|connection| connection.node == self.0.node)
+            .count() as u64)
+    }
+
+    async fn last_opened_at(&self) -> DateTime<Utc> {
+        self.0.last_opened_at
+    }
+
+    /// Latest state reported by the node's RPC interface, if it is polled
+    async fn status(&self, ctx: &Context<'_>) -> Result<Option<NodeStatus>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let status = loader
+            .load_one(NodeStatusByNode(self.0.node.clone()))
+            .await?;
+        Ok(status.map(NodeStatus::from))
+    }
+}
+
+/// State of a node from its latest RPC poll
+#[derive(SimpleObject)]
+pub struct NodeStatus {
+    pub version: i64,
+    pub subversion: String,
+    pub chain: String,
+    pub blocks: i64,
+    pub headers: i64,
+    pub verification_progress: f64,
+    pub initial_block_download: bool,
+    pub connections_in: i64,
+    pub connections_out: i64,
+    pub total_bytes_recv: i64,
+    pub total_bytes_sent: i64,
+    /// Transactions in the mempool
+    pub mempool_size: i64,
+    pub mempool_bytes: i64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<app::NodeStatus> for NodeStatus {
+    fn from(status: app::NodeStatus) -> Self {
+        Self {
+            version: status.version,
+            subversion: status.subversion,
+            chain: status.chain,
+            blocks: status.blocks,
+            headers: status.headers,
+            verification_progress: status.verification_progress,
+            initial_block_download: status.initial_block_download,
+            connections_in: status.connections_in,
+            connections_out: status.connections_out,
+            total_bytes_recv: status.total_bytes_recv,
+            total_bytes_sent: status.total_bytes_sent,
+            mempool_size: status.mempool_size,
+            mempool_bytes: status.mempool_bytes,
+            updated_at: status.updated_at,
+        }
+    }
+}
+
+/// Bitcoin Core's view of a proxied connection, from its latest `getpeerinfo`
+#[derive(SimpleObject)]
+pub struct CorePeer {
+    /// Peer id in Bitcoin Core
+    pub peer_id: i64,
+    pub connection_type: Option<CoreConnectionType>,
+    /// Misbehavior score, only reported by legacy nodes before Bitcoin Core v22
+    #[graphql(deprecation = "Bitcoin Core v22 dropped ban scores, null for newer nodes")]
+    pub ban_score: Option<i64>,
+    pub starting_height: Option<i64>,
+    pub synced_headers: Option<i64>,
+    pub synced_blocks: Option<i64>,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<app::CorePeer> for CorePeer {
+    fn from(peer: app::CorePeer) -> Self {
+        Self {
+            peer_id: peer.peer_id,
+            connection_type: peer.connection_type.map(Into::into),
+            ban_score: peer.ban_score,
+            starting_height: peer.starting_height,
+            synced_headers: peer.synced_headers,
+            synced_blocks: peer.synced_blocks,
+            updated_at: peer.updated_at,
+        }
+    }
+}
+
+/// A remote peer the node has connected to
+pub struct Peer(pub app::PeerRecord);
+
+#[Object]
+impl Peer {
+    async fn address(&self) -> &str {
+        &self.0.address
+    }
+
+    async fn first_seen(&self) -> DateTime<Utc> {
+        self.0.first_seen
+    }
+
+    async fn last_seen(&self) -> DateTime<Utc> {
+        self.0.last_seen
+    }
+
+    /// Node the times, connections and transports are limited to, null when
+    /// they cover all nodes
+    async fn node(&self) -> Option<&str> {
+        self.0.node.as_deref()
+    }
+
+    /// Not known for peers recorded before network types were tracked
+    async fn network_type(&self) -> Option<NetworkType> {
+        self.0.network_type.map(Into::into)
+    }
+
+    /// Connections made to this peer, newest first
+    async fn connections(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        first: Option<i32>,
+    ) -> Result<connection::Connection<u64, Connection>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+
+        paginate(
+            after,
+            first,
+            |before, limit| async move {
+                let records = loader
+                    .load_one(ConnectionsByPeer {
+                        address: self.0.address.clone(),
+                        node: self.0.node.clone(),
+                        before,
+                        limit,
+                    })
+                    .await?;
+                Ok(records.unwrap_or_default())
+            },
+            |record| record.connection_id(),
+            Connection,
+        )
+        .await
+    }
+
+    /// How many connections to this peer used each transport
+    async fn transports(&self, ctx: &Context<'_>) -> Result<TransportSplit> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let counts = loader
+            .load_one(TransportsByPeer {
+                address: self.0.address.clone(),
+                node: self.0.node.clone(),
+            })
+            .await?
+            .unwrap_or_default();
+
+        let mut split = TransportSplit::default();
+        for count in counts {
+            let connections = count.connections as u64;
+            match count.transport {
+                Some(app::Transport::V1) => split.v1 += connections,
+                Some(app::Transport::V2Encrypted) => split.v2_encrypted += connections,
+                None => split.undetected += connections,
+            }
+        }
+        Ok(split)
+    }
+}
+
+/// Number of connections to a peer per transport
+#[derive(SimpleObject, Default)]
+pub struct TransportSplit {
+    pub v1: u64,
+    pub v2_encrypted: u64,
+    /// Connections that closed before the client sent enough bytes to tell
+    pub undetected: u64,
+}
+
+/// A connection proxied between a client and its target peer
+pub struct Connection(pub app::ConnectionRecord);
+
+#[Object]
+impl Connection {
+    async fn id(&self) -> u64 {
+        self.0.connection_id()
+    }
+
+    /// Label of the node whose proxy saw the connection
+    async fn node(&self) -> &str {
+        &self.0.node
+    }
+
+    async fn client_addr(&self) -> &str {
+        &self.0.client_addr
+    }
+
+    async fn target_addr(&self) -> &str {
+        &self.0.target_addr
+    }
+
+    async fn network(&self) -> &str {
+        &self.0.network
+    }
+
+    /// True when the peer connected to the node through the transparent
+    /// listener, the client is the node either way
+    async fn inbound(&self) -> bool {
+        self.0.inbound
+    }
+
+    /// Port an inbound peer connected from, the target address is only its IP
+    async fn peer_port(&self) -> Option<u16> {
+        self.0.peer_port
+    }
+
+    /// Address the proxy connected to the node from for an inbound peer, which
+    /// is what the node reports as the peer's address
+    async fn local_addr(&self) -> Option<&str> {
+        self.0.local_addr.as_deref()
+    }
+
+    async fn opened_at(&self) -> DateTime<Utc> {
+        self.0.opened_at
+    }
+
+    async fn closed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.closed_at
+    }
+
+    /// Not known until the side that connected sent its first bytes
+    async fn transport(&self) -> Option<Transport> {
+        self.0.transport.map(Into::into)
+    }
+
+    /// Traffic statistics, live while the connection is open
+    async fn stats(&self, ctx: &Context<'_>) -> ConnectionStats {
+        let live = match (self.0.closed_at, ctx.data_opt::<app::NodeScopeApp>()) {
+            (None, Some(app)) => app.connections().stats(self.0.connection_id()),
+            _ => None,
+        };
+        live.unwrap_or_else(|| self.0.stats()).into()
+    }
+
+    /// The peer as seen by the connection's node
+    async fn peer(&self, ctx: &Context<'_>) -> Result<Option<Peer>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let peer = loader
+            .load_one(PeerByAddress {
+                address: self.0.target_addr.clone(),
+                node: Some(self.0.node.clone()),
+            })
+            .await?;
+        Ok(peer.map(Peer))
+    }
+
+    async fn handshake(&self, ctx: &Context<'_>) -> Result<Option<Handshake>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let handshake = loader
+            .load_one(HandshakeByConnection(self.0.connection_id()))
+            .await?;
+        Ok(handshake.map(Handshake))
+    }
+
+    /// Bitcoin Core's view of this connection, once the node's RPC was polled while it was open
+    async fn core_peer(&self, ctx: &Context<'_>) -> Result<Option<CorePeer>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let peer = loader
+            .load_one(CorePeerByConnection(self.0.connection_id()))
+            .await?;
+        Ok(peer.map(CorePeer::from))
+    }
+
+    /// The most recent messages of this connection, oldest first
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 50, validator(minimum = 1, maximum = 1000))] last: usize,
+    ) -> Result<Vec<Message>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let messages = loader
+            .load_one(RecentMessages {
+                connection_id: self.0.connection_id(),
+                limit: last,
+            })
+            .await?
+            .unwrap_or_default();
+        Ok(messages.into_iter().map(Message::from).collect())
+    }
+
+    /// Ping round trips measured on this connection, oldest first
+    async fn ping_rtts(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 100, validator(minimum = 1, maximum = 1000))] last: usize,
+    ) -> Result<Vec<PingRtt>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let pings = loader
+            .load_one(RecentPingRtts {
+                connection_id: self.0.connection_id(),
+                limit: last,
+            })
+            .await?
+            .unwrap_or_default();
+        Ok(pings.into_iter().map(PingRtt::from).collect())
+    }
+
+    /// Sizes and timing of the reads in each direction, live while the connection is open
+    async fn packet_stats(&self, ctx: &Context<'_>) -> Result<Vec<PacketStats>> {
+        let live = match (self.0.closed_at, ctx.data_opt::<app::NodeScopeApp>()) {
+            (None, Some(app)) => app.connections().packet_stats(self.0.connection_id()),
+            _ => None,
+        };
+        let stats = match live {
+            Some(stats) => stats,
+            None => {
+                let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+                loader
+                    .load_one(PacketStatsByConnection(self.0.connection_id()))
+                    .await?
+                    .unwrap_or_default()
+            }
+        };
+        Ok(stats.into_iter().map(PacketStats::from).collect())
+    }
+
+    /// Events the node logged about this connection's peer, oldest first
+    async fn log_events(&self, ctx: &Context<'_>) -> Result<Vec<LogEvent>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let events = loader
+            .load_one(LogEventsByConnection(self.0.connection_id()))
+            .await?
+            .unwrap_or_default();
+        Ok(events.into_iter().map(LogEvent::from).collect())
+    }
+
+    /// How early the peer announced the blocks and transactions the node accepted
+    async fn propagation(&self, ctx: &Context<'_>) -> Result<Vec<PropagationStats>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let stats = loader
+            .load_one(PropagationByConnection(self.0.connection_id()))
+            .await?
+            .unwrap_or_default();
+        Ok(stats.into_iter().map(PropagationStats::from).collect())
+    }
+
+    /// How many items the peer announced, first or not, and how many it was asked for
+    async fn inventory(&self, ctx: &Context<'_>) -> Result<Option<InventoryStats>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let stats = loader
+            .load_one(InventoryByConnection(self.0.connection_id()))
+            .await?;
+        Ok(stats.map(InventoryStats::from))
+    }
+
+    /// Transactions the peer announced, was asked for and delivered
+    async fn tx_relay(&self, ctx: &Context<'_>) -> Result<Option<ConnectionTxRelay>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let relay = loader
+            .load_one(RelayStatsByConnection(self.0.connection_id()))
+            .await?;
+        Ok(relay.map(ConnectionTxRelay::from))
+    }
+
+    /// Fee filters the peer sent, oldest first
+    async fn fee_filters(&self, ctx: &Context<'_>) -> Result<Vec<FeeFilter>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let filters = loader
+            .load_one(FeeFiltersByConnection(self.0.connection_id()))
+            .await?
+            .unwrap_or_default();
+        Ok(filters.into_iter().map(FeeFilter::from).collect())
+    }
+
+    /// How well each direction could be parsed, empty if nothing went wrong
+    async fn parse_health(&self, ctx: &Context<'_>) -> Result<Vec<ParseHealth>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let health = loader
+            .load_one(ParseHealthByConnection(self.0.connection_id()))
+            .await?
+            .unwrap_or_default();
+        Ok(health.into_iter().map(ParseHealth::from).collect())
+    }
+}
+
+/// Version handshake of a connection, tracked from accept to verack or failure
+pub struct Handshake(pub app::Handshake);
+
+#[Object]
+impl Handshake {
+    async fn connection_id(&self) -> u64 {
+        self.0.connection_id
+    }
+
+    async fn node(&self) -> &str {
+        &self.0.node
+    }
+
+    async fn client_addr(&self) -> &str {
+        &self.0.client_addr
+    }
+
+    /// Not known when the SOCKS5 negotiation failed
+    async fn target_addr(&self) -> Option<&str> {
+        self.0.target_addr.as_deref()
+    }
+
+    /// SOCKS5 username the client authenticated with, which Bitcoin Core
+    /// randomizes per connection to isolate its Tor circuits
+    async fn stream_isolation(&self) -> Option<&str> {
+        self.0.stream_isolation.as_deref()
+    }
+
+    async fn outcome(&self) -> HandshakeOutcome {
+        self.0.outcome.into()
+    }
+
+    async fn failure_reason(&self) -> Option<HandshakeFailureReason> {
+        self.0.failure_reason.map(Into::into)
+    }
+
+    async fn failure_detail(&self) -> Option<&str> {
+        self.0.failure_detail.as_deref()
+    }
+
+    /// Why connecting to the target failed, for CONNECT_FAILED handshakes
+    async fn connect_error(&self, ctx: &Context<'_>) -> Result<Option<ConnectError>> {
+        if self.0.failure_reason != Some(app::HandshakeFailureReason::ConnectFailed) {
+            return Ok(None);
+        }
+
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let failure = loader
+            .load_one(ConnectFailureByConnection(self.0.connection_id))
+            .await?;
+        Ok(failure.map(|failure| failure.error.into()))
+    }
+
+    async fn started_at(&self) -> DateTime<Utc> {
+        self.0.started_at
+    }
+
+    async fn completed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.completed_at
+    }
+
+    async fn failed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.failed_at
+    }
+
+    /// Milliseconds from accept until the handshake completed
+    async fn duration_ms(&self) -> Option<i64> {
+        self.0.duration().map(|d| d.num_milliseconds())
+    }
+
+    /// What the proxy client (our node) sent
+    async fn local(&self) -> HandshakeSide {
+        (&self.0.local).into()
+    }
+
+    /// What the target peer sent
+    async fn remote(&self) -> HandshakeSide {
+        (&self.0.remote).into()
+    }
+}
+
+/// Everything one side of a connection sent during the handshake
+#[derive(SimpleObject)]
+pub struct HandshakeSide {
+    pub version: Option<VersionInfo>,
+    pub verack_at: Option<DateTime<Utc>>,
+    pub wtxid_relay: bool,
+    pub send_addr_v2: bool,
+    pub send_cmpct: Option<SendCmpct>,
+}
+
+impl From<&app::HandshakeSide> for HandshakeSide {
+    fn from(side: &app::HandshakeSide) -> Self {
+        Self {
+            version: side.version.clone().map(Into::into),
+            verack_at: side.verack_at,
+            wtxid_relay: side.wtxid_relay,
+            send_addr_v2: side.send_addr_v2,
+            send_cmpct: side.send_cmpct.map(|cmpct| SendCmpct {
+                announce: cmpct.announce,
+                version: cmpct.version,
+            }),
+        }
+    }
+}
+
+/// Fields of a `version` message
+#[derive(SimpleObject)]
+pub struct VersionInfo {
+    pub protocol_version: u32,
+    pub services: u64,
+    pub timestamp: i64,
+    pub user_agent: String,
+    pub start_height: i32,
+    pub relay: bool,
+    pub nonce: u64,
+    pub addr_from: String,
+    pub addr_recv: String,
+    pub seen_at: Option<DateTime<Utc>>,
+}
+
+impl From<app::VersionInfo> for VersionInfo {
+    fn from(version: app::VersionInfo) -> Self {
+        Self {
+            protocol_version: version.protocol_version,
+            services: version.services,
+            timestamp: version.timestamp,
+            user_agent: version.user_agent,
+            start_height: version.start_height,
+            relay: version.relay,
+            nonce: version.nonce,
+            addr_from: version.addr_from,
+            addr_recv: version.addr_recv,
+            seen_at: version.seen_at,
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct SendCmpct {
+    pub announce: bool,
+    pub version: u64,
+}
+
+/// A P2P message seen on a connection
+#[derive(SimpleObject)]
+pub struct Message {
+    /// Id of the stored message, not set on live messages that aren't stored yet
+    pub id: Option<i64>,
+    pub connection_id: u64,
+    /// Label of the node of the connection
+    pub node: String,
+    pub direction: Direction,
+    pub command: String,
+    pub payload_len: u64,
+    /// When the proxy read the message
+    pub received_at: DateTime<Utc>,
+    /// When the proxy finished forwarding the message
+    pub forwarded_at: Option<DateTime<Utc>>,
+    /// Microseconds the message spent inside the proxy
+    pub forward_latency_us: Option<i64>,
+    pub description: String,
+}
+
+impl From<app::MessageRecord> for Message {
+    fn from(record: app::MessageRecord) -> Self {
+        let forward_latency_us = record.forward_latency_us();
+        Self {
+            id: Some(record.id),
+            connection_id: record.connection_id as u64,
+            node: record.node,
+            direction: record.direction.into(),
+            command: record.command,
+            payload_len: record.payload_len as u64,
+            received_at: record.received_at,
+            forwarded_at: record.forwarded_at,
+            forward_latency_us,
+            description: record.description,
+        }
+    }
+}
+
+impl From<&app::MessageSeen> for Message {
+    fn from(event: &app::MessageSeen) -> Self {
+        Self {
+            id: None,
+            connection_id: event.connection_id,
+            node: event.node.clone(),
+            direction: event.direction.into(),
+            command: event.command.clone(),
+            payload_len: event.payload_len as u64,
+            received_at: event.received_at,
+            forwarded_at: event.forwarded_at,
+            forward_latency_us: event.forward_latency().and_then(|d| d.num_microseconds()),
+            description: event.description.clone(),
+        }
+    }
+}
+
+/// A ping matched with the pong carrying the same nonce
+#[derive(SimpleObject)]
+pub struct PingRtt {
+    /// Direction of the ping, INBOUND when the client pinged the target
+    pub direction: Direction,
+    pub nonce: u64,
+    /// When the proxy finished forwarding the ping
+    pub ping_at: DateTime<Utc>,
+    /// When the proxy received the pong
+    pub pong_at: DateTime<Utc>,
+    pub rtt_us: i64,
+}
+
+/// Parse health of one direction of a connection
+#[derive(SimpleObject)]
+pub struct ParseHealth {
+    pub direction: Direction,
+    pub state: ParserState,
+    pub desyncs: u64,
+    pub bytes_skipped: u64,
+    pub checksum_mismatches: u64,
+    pub oversize_payloads: u64,
+    /// Valid messages with a command the parser doesn't know, not a parse problem
+    pub unknown_commands: u64,
+    pub invalid_payloads: u64,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl From<app::ParseHealth> for ParseHealth {
+    fn from(health: app::ParseHealth) -> Self {
+        Self {
+            direction: health.direction.into(),
+            state: health.state.into(),
+            desyncs: health.desyncs,
+            bytes_skipped: health.bytes_skipped,
+            checksum_mismatches: health.checksum_mismatches,
+            oversize_payloads: health.oversize_payloads,
+            unknown_commands: health.unknown_commands,
+            invalid_payloads: health.invalid_payloads,
+            updated_at: health.updated_at,
+        }
+    }
+}
+
+/// An event from a node's debug.log
+#[derive(SimpleObject)]
+pub struct LogEvent {
+    pub id: i64,
+    pub node: String,
+    /// The proxied connection of the peer, when it could be matched
+    pub connection_id: Option<u64>,
+    /// Peer id in Bitcoin Core
+    pub peer_id: Option<i64>,
+    pub address: Option<String>,
+    pub kind: LogEventKind,
+    /// `net`, `validation`, `mempool` or `addrman`
+    pub category: String,
+    /// Height of the new tip for UpdateTip
+    pub height: Option<i64>,
+    pub message: String,
+    pub logged_at: DateTime<Utc>,
+}
+
+impl From<app::LogEventRecord> for LogEvent {
+    fn from(record: app::LogEventRecord) -> Self {
+        Self {
+            id: record.id,
+            node: record.node,
+            connection_id: record.connection_id.map(|id| id as u64),
+            peer_id: record.peer_id,
+            address: record.address,
+            kind: record.kind.into(),
+            category: record.kind.category().to_string(),
+            height: record.height,
+            message: record.message,
+            logged_at: record.logged_at,
+        }
+    }
+}
+
+/// A block or transaction the node accepted, as notified over ZMQ
+pub struct Acceptance(pub app::AcceptanceRecord);
+
+#[Object]
+impl Acceptance {
+    async fn id(&self) -> i64 {
+        self.0.id
+    }
+
+    async fn node(&self) -> &str {
+        &self.0.node
+    }
+
+    async fn kind(&self) -> ItemKind {
+        self.0.kind.into()
+    }
+
+    /// Block hash or txid
+    async fn hash(&self) -> &str {
+        &self.0.hash
+    }
+
+    /// Only known when the node notified the whole transaction
+    async fn wtxid(&self) -> Option<&str> {
+        self.0.wtxid.as_deref()
+    }
+
+    async fn accepted_at(&self) -> DateTime<Utc> {
+        self.0.accepted_at
+    }
+
+    /// Announcements of the item by the node's peers, earliest first
+    async fn announcements(&self, ctx: &Context<'_>) -> Result<Vec<Announcement>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let announcements = loader
+            .load_one(AnnouncementsByAcceptance(self.0.id))
+            .await?
+            .unwrap_or_default();
+        Ok(announcements.into_iter().map(Announcement::from).collect())
+    }
+
+    /// The peer that announced the item first
+    async fn first_announcement(&self, ctx: &Context<'_>) -> Result<Option<Announcement>> {
+        let loader = ctx.data_unchecked::<DataLoader<StoreLoader>>();
+        let announcements = loader
+            .load_one(AnnouncementsByAcceptance(self.0.id))
+            .await?
+            .unwrap_or_default();
+        Ok(announcements.into_iter().next().map(Announcement::from))
+    }
+}
+
+/// A peer announced an item the node accepted
+#[derive(SimpleObject)]
+pub struct Announcement {
+    pub connection_id: u64,
+    pub via: AnnouncedVia,
+    pub announced_at: DateTime<Utc>,
+    /// Milliseconds from the node accepting the item to the peer announcing it, negative
+    /// when the peer announced it first
+    pub delay_ms: f64,
+}
+
+impl From<app::AnnouncementRecord> for Announcement {
+    fn from(record: app::AnnouncementRecord) -> Self {
+        Self {
+            connection_id: record.connection_id as u64,
+            via: record.via.into(),
+            announced_at: record.announced_at,
+            delay_ms: record.delay_ms,
+        }
+    }
+}
+
+/// How early a peer announced the items of a kind the node accepted
+#[derive(SimpleObject)]
+pub struct PropagationStats {
+    pub kind: ItemKind,
+    /// Items the peer announced that the node accepted
+    pub announced: i64,
+    /// Of those, the ones no other peer announced earlier
+    pub first: i64,
+    /// Negative when the peer announces items before the node accepts them on average
+    pub avg_delay_ms: f64,
+}
+
+impl From<app::PropagationStats> for PropagationStats {
+    fn from(stats: app::PropagationStats) -> Self {
+        Self {
+            kind: stats.kind.into(),
+            announced: stats.announced,
+            first: stats.first,
+            avg_delay_ms: stats.avg_delay_ms,
+        }
+    }
+}
+
+/// What the peers of a node announced and were asked for of one block or transaction
+pub struct InventoryItem {
+    pub node: Option<String>,
+    pub hash: String,
+}
+
+#[Object]
+impl InventoryItem {
+    /// Block hash, txid or wtxid
+    async fn hash(&self) -> &str {
+        &self.hash
+    }
+
+    /// Announcements of the item by any peer, earliest first
+    async fn announcements(&self, ctx: &Context<'_>) -> Result<Vec<InventoryAnnouncement>> {
+        let app = ctx.data::<app::NodeScopeApp>()?;
+        let announcements = app
+            .store()
+            .item_announcements(self.node.as_deref(), &self.hash)
+            .await?;
+        Ok(announcements
+            .into_iter()
+            .map(InventoryAnnouncement::from)
+            .collect())
+    }
+
+    /// The peer that told the node about the item first
+    async fn first_announcement(&self, ctx: &Context<'_>) -> Result<Option<InventoryAnnouncement>> {
+        Ok(self.announcements(ctx).await?.into_iter().next())
+    }
+
+    /// Requests of the item with getdata, earliest first
+    async fn requests(&self, ctx: &Context<'_>) -> Result<Vec<InventoryRequest>> {
+        let app = ctx.data::<app::NodeScopeApp>()?;
+        let requests = app
+            .store()
+            .item_requests(self.node.as_deref(), &self.hash)
+            .await?;
+        Ok(requests.into_iter().map(InventoryRequest::from).collect())
+    }
+}
+
+/// A peer announced a block or transaction
+#[derive(SimpleObject)]
+pub struct InventoryAnnouncement {
+    pub connection_id: u64,
+    pub node: String,
+    pub kind: ItemKind,
+    /// The hash as announced, the wtxid for peers relaying by wtxid
+    pub hash: String,
+    pub via: AnnouncedVia,
+    pub announced_at: DateTime<Utc>,
+}
+
+impl From<app::InventoryAnnouncementRecord> for InventoryAnnouncement {
+    fn from(record: app::InventoryAnnouncementRecord) -> Self {
+        Self {
+            connection_id: record.connection_id as u64,
+            node: record.node,
+            kind: record.kind.into(),
+            hash: record.hash,
+            via: record.via.into(),
+            announced_at: record.announced_at,
+        }
+    }
+}
+
+/// The node requested a block or transaction from a peer with getdata
+#[derive(SimpleObject)]
+pub struct InventoryRequest {
+    pub connection_id: u64,
+    pub node: String,
+    pub kind: ItemKind,
+    pub hash: String,
+    /// Null when the peer answered a request that wasn't seen with notfound
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When the peer answered with notfound, null if it didn't
+    pub not_found_at: Option<DateTime<Utc>>,
+}
+
+impl From<app::InventoryRequestRecord> for InventoryRequest {
+    fn from(record: app::InventoryRequestRecord) -> Self {
+        Self {
+            connection_id: record.connection_id as u64,
+            node: record.node,
+            kind: record.kind.into(),
+            hash: record.hash,
+            requested_at: record.requested_at,
+            not_found_at: record.not_found_at,
+        }
+    }
+}
+
+/// What a peer announced and was asked for
+#[derive(SimpleObject)]
+pub struct InventoryStats {
+    pub announced: i64,
+    /// Announcements of items no other peer of the node announced earlier
+    pub first_announced: i64,
+    pub requested: i64,
+    /// Items the peer answered with notfound, also ones whose request wasn't seen
+    pub not_found: i64,
+}
+
+impl From<app::InventoryStats> for InventoryStats {
+    fn from(stats: app::InventoryStats) -> Self {
+        Self {
+            announced: stats.announced,
+            first_announced: stats.first_announced,
+            requested: stats.requested,
+            not_found: stats.not_found,
+        }
+    }
+}
+
+/// Transactions announced, requested and delivered, with the sizes and fee rates delivered
+#[derive(SimpleObject)]
+pub struct TxRelayStats {
+    pub announced: u64,
+    pub requested: u64,
+    pub delivered: u64,
+    /// Deliveries of transactions the node already got from this or another peer
+    pub duplicates: u64,
+    /// Deliveries whose fee is known, as the transactions of their inputs were relayed before
+    pub fee_known: u64,
+    pub size_buckets: Vec<TxSizeBucket>,
+    /// Only deliveries whose fee is known
+    pub fee_rate_buckets: Vec<FeeRateBucket>,
+}
+
+/// Number of transactions up to a size
+#[derive(SimpleObject)]
+pub struct TxSizeBucket {
+    /// Upper bound in vbytes, null for the bucket of larger transactions
+    pub max_vbytes: Option<u64>,
+    pub txs: u64,
+}
+
+/// Number of transactions up to a fee rate
+#[derive(SimpleObject)]
+pub struct FeeRateBucket {
+    /// Upper bound in sat/vB, null for the bucket of higher fee rates
+    pub max_sat_per_vbyte: Option<f64>,
+    pub txs: u64,
+}
+
+impl From<app::RelayStats> for TxRelayStats {
+    fn from(stats: app::RelayStats) -> Self {
+        let size_bounds = app::TX_SIZE_BUCKETS.iter().copied().map(Some);
+        let fee_rate_bounds = app::FEE_RATE_BUCKETS.iter().copied().map(Some);
+        Self {
+            announced: stats.announced,
+            requested: stats.requested,
+            delivered: stats.delivered,
+            duplicates: stats.duplicates,
+            fee_known: stats.fee_known,
+            size_buckets: size_bounds
+                .chain([None])
+                .zip(stats.size_buckets)
+                .map(|(max_vbytes, txs)| TxSizeBucket { max_vbytes, txs })
+                .collect(),
+            fee_rate_buckets: fee_rate_bounds
+                .chain([None])
+                .zip(stats.fee_rate_buckets)
+                .map(|(max_sat_per_vbyte, txs)| FeeRateBucket {
+                    max_sat_per_vbyte,
+                    txs,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Transaction relay of a connection's peer
+#[derive(SimpleObject)]
+pub struct ConnectionTxRelay {
+    /// Whether both sides sent wtxidrelay, so transactions are announced by wtxid
+    pub wtxid_relay: bool,
+    pub stats: TxRelayStats,
+}
+
+impl From<app::ConnectionRelayStats> for ConnectionTxRelay {
+    fn from(relay: app::ConnectionRelayStats) -> Self {
+        Self {
+            wtxid_relay: relay.wtxid_relay,
+            stats: relay.stats.into(),
+        }
+    }
+}
+
+/// Transaction relay over all connections of a node, or of all nodes
+#[derive(SimpleObject)]
+pub struct TxRelaySummary {
+    pub connections: u64,
+    /// Connections relaying transactions by wtxid instead of txid
+    pub wtxid_relay_connections: u64,
+    pub stats: TxRelayStats,
+}
+
+impl From<app::RelaySummary> for TxRelaySummary {
+    fn from(summary: app::RelaySummary) -> Self {
+        Self {
+            connections: summary.connections,
+            wtxid_relay_connections: summary.wtxid_relay_connections,
+            stats: summary.stats.into(),
+        }
+    }
+}
+
+/// The lowest fee rate of transactions a peer asked to be relayed
+#[derive(SimpleObject)]
+pub struct FeeFilter {
+    /// Fee rate in sat/kvB
+    pub fee_rate: i64,
+    pub received_at: DateTime<Utc>,
+}
+
+impl From<app::FeeFilterRecord> for FeeFilter {
+    fn from(record: app::FeeFilterRecord) -> Self {
+        Self {
+            fee_rate: record.fee_rate,
+            received_at: record.received_at,
+        }
+    }
+}
+
+/// Sizes and timing of the reads from one side of a connection
+#[derive(SimpleObject)]
+pub struct PacketStats {
+    pub direction: Direction,
+    pub packets: u64,
+    pub bytes: u64,
+    pub first_at: Option<DateTime<Utc>>,
+    pub last_at: Option<DateTime<Utc>>,
+    pub size_buckets: Vec<PacketSizeBucket>,
+}
+
+/// Number of packets up to a size
+#[derive(SimpleObject)]
+pub struct PacketSizeBucket {
+    /// Upper bound in bytes, null for the bucket of larger packets
+    pub max_bytes: Option<u64>,
+    pub packets: u64,
+}
+
+impl From<app::PacketStats> for PacketStats {
+    fn from(stats: app::PacketStats) -> Self {
+        let bounds = app::PACKET_SIZE_BUCKETS.iter().copied().map(Some);
+        Self {
+            direction: stats.direction.into(),
+            packets: stats.packets,
+            bytes: stats.bytes,
+            first_at: stats.first_at,
+            last_at: stats.last_at,
+            size_buckets: bounds
+                .chain([None])
+                .zip(stats.size_buckets)
+                .map(|(max_bytes, packets)| PacketSizeBucket { max_bytes, packets })
+                .collect(),
+        }
+    }
+}
+
+impl From<app::PingRttRecord> for PingRtt {
+    fn from(record: app::PingRttRecord) -> Self {
+        Self {
+            direction: record.direction.into(),
+            nonce: record.nonce as u64,
+            ping_at: record.ping_at,
+            pong_at: record.pong_at,
+            rtt_us: record.rtt_us,
+        }
+    }
+}
+
+#[derive(Enum, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionEventKind {
+    Opened,
+    /// Connecting to the target failed, the connection was never opened
+    Failed,
+    Closed,
+}
+
+/// A connection was opened, failed to open or was closed
+#[derive(SimpleObject)]
+pub struct ConnectionEvent {
+    pub kind: ConnectionEventKind,
+    pub connection_id: u64,
+    /// Label of the node whose proxy handled the connection
+    pub node: String,
+    /// Not known when a connection opened before the subscription started is closed
+    pub target_addr: Option<String>,
+    /// Only set when the connection was opened or failed
+    pub client_addr: Option<String>,
+    /// Only set when the connection failed
+    pub connect_error: Option<ConnectError>,
+    /// Only set when the connection was closed
+    pub stats: Option<ConnectionStats>,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(InputObject, Default)]
+pub struct ConnectionFilter {
+    /// Only open connections when true, only closed ones when false
+    pub active: Option<bool>,
+    /// Label of the node, all nodes when unset
+    pub node: Option<String>,
+    pub client_addr: Option<String>,
+    pub target_addr: Option<String>,
+    pub network: Option<String>,
+    pub inbound: Option<bool>,
+    pub transport: Option<Transport>,
+    pub opened_after: Option<DateTime<Utc>>,
+    pub opened_before: Option<DateTime<Utc>>,
+}
+
+impl From<ConnectionFilter> for app::ConnectionFilter {
+    fn from(filter: ConnectionFilter) -> Self {
+        Self {
+            active: filter.active,
+            node: filter.node,
+            client_addr: filter.client_addr,
+            target_addr: filter.target_addr,
+            network: filter.network,
+            inbound: filter.inbound,
+            transport: filter.transport.map(Into::into),
+            opened_after: filter.opened_after,
+            opened_before: filter.opened_before,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct HandshakeFilter {
+    pub node: Option<String>,
+    pub outcome: Option<HandshakeOutcome>,
+    pub failure_reason: Option<HandshakeFailureReason>,
+    pub target_addr: Option<String>,
+}
+
+impl From<HandshakeFilter> for app::HandshakeFilter {
+    fn from(filter: HandshakeFilter) -> Self {
+        Self {
+            node: filter.node,
+            outcome: filter.outcome.map(Into::into),
+            failure_reason: filter.failure_reason.map(Into::into),
+            target_addr: filter.target_addr,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct MessageFilter {
+    pub direction: Option<Direction>,
+    pub command: Option<String>,
+}
+
+impl From<MessageFilter> for app::MessageFilter {
+    fn from(filter: MessageFilter) -> Self {
+        Self {
+            direction: filter.direction.map(Into::into),
+            command: filter.command,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct LogEventFilter {
+    pub node: Option<String>,
+    pub kind: Option<LogEventKind>,
+    pub peer_id: Option<i64>,
+}
+
+impl From<LogEventFilter> for app::LogEventFilter {
+    fn from(filter: LogEventFilter) -> Self {
+        Self {
+            node: filter.node,
+            kind: filter.kind.map(Into::into),
+            peer_id: filter.peer_id,
+        }
+    }
+}
+
+#[derive(InputObject, Default)]
+pub struct AcceptanceFilter {
+    pub node: Option<String>,
+    pub kind: Option<ItemKind>,
+    /// Block hash, txid or wtxid
+    pub hash: Option<String>,
+}
+
+impl From<AcceptanceFilter> for app::AcceptanceFilter {
+    fn from(filter: AcceptanceFilter) -> Self {
+        Self {
+            node: filter.node,
+            kind: filter.kind.map(Into::into),
+            hash: filter.hash,
+        }
+    }
+}
@@ -3,8 +3,7 @@ pub use config::ServerConfig;
 
 mod graphql;
 
-use async_graphql::*;
-use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse, GraphQLSubscription};
 use axum::{
     Extension, Router, extract::Path, http::StatusCode, response::IntoResponse, routing::get,
 };
@@ -12,19 +11,23 @@ use mime_guess::from_path;
 use rust_embed::RustEmbed;
 use tracing::info;
 
-use app::NodeScopeApp;
+use app::{NodeScopeApp, ShutdownPhase};
 
+/// Serve the UI and API until the app starts shutting down
 pub async fn run(config: ServerConfig, app: NodeScopeApp) -> anyhow::Result<()> {
     let port = config.port;
+    let shutdown = app.shutdown().clone();
 
     let schema = graphql::schema(Some(app.clone()));
 
     let app = Router::new()
         .route("/health", get(health_check))
+        .route("/metrics", get(metrics))
         .route(
             "/graphql",
             get(playground).post(axum::routing::post(graphql_handler)),
         )
+        .route_service("/graphql/ws", GraphQLSubscription::new(schema.clone()))
         .route("/", get(index_handler))
         .route("/{*path}", get(static_handler))
         .layer(Extension(schema))
@@ -35,13 +38,16 @@ pub async fn run(config: ServerConfig, app: NodeScopeApp) -> anyhow::Result<()>
         tokio::net::TcpListener::bind(&std::net::SocketAddr::from(([0, 0, 0, 0], port))).await?;
 
     info!("UI and GraphQL server running on port {}", port);
-    axum::serve(listener, app.into_make_service()).await?;
+    axum::serve(listener, app.into_make_service())
+        .with_graceful_shutdown(async move { shutdown.reached(ShutdownPhase::Draining).await })
+        .await?;
+    info!("UI and GraphQL server stopped");
 
     Ok(())
 }
 
 pub async fn graphql_handler(
-    schema: Extension<Schema<graphql::Query, EmptyMutation, EmptySubscription>>,
+    schema: Extension<graphql::NodeScopeSchema>,
     req: GraphQLRequest,
 ) -> GraphQLResponse {
     let req = req.into_inner();
@@ -51,7 +57,8 @@ pub async fn graphql_handler(
 
 async fn playground() -> impl axum::response::IntoResponse {
     axum::response::Html(async_graphql::http::playground_source(
-        async_graphql::http::GraphQLPlaygroundConfig::new("/graphql"),
+        async_graphql::http::GraphQLPlaygroundConfig::new("/graphql")
+            .subscription_endpoint("/graphql/ws"),
     ))
 }
 
@@ -59,6 +66,17 @@ async fn health_check() -> &'static str {
     "OK"
 }
 
+async fn metrics(Extension(app): Extension<NodeScopeApp>) -> impl IntoResponse {
+    match app.metrics().render() {
+        Ok(body) => (
+            [(axum::http::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)],
+            body,
+        )
+            .into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, format!("{:#}", e)).into_response(),
+    }
+}
+
 #[derive(RustEmbed)]
 #[folder = "../dashboard/dist"]
 struct Assets;
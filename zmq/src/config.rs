@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+/// Subscription to a ZMQ endpoint a node publishes notifications on
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZmqConfig {
+    /// Label of the node, the same as for its proxy
+    pub node: String,
+
+    /// Address of the endpoint, e.g. 127.0.0.1:28332 for `-zmqpubrawblock=tcp://127.0.0.1:28332`
+    pub address: String,
+
+    /// Topics to subscribe to, the node only sends those it publishes on this endpoint
+    #[serde(default = "default_topics")]
+    pub topics: Vec<Topic>,
+
+    /// Seconds to wait before connecting again after the endpoint went away
+    #[serde(default = "default_reconnect_secs")]
+    pub reconnect_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Topic {
+    Hashblock,
+    Hashtx,
+    Rawblock,
+    Rawtx,
+    Sequence,
+}
+
+impl Topic {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Topic::Hashblock => "hashblock",
+            Topic::Hashtx => "hashtx",
+            Topic::Rawblock => "rawblock",
+            Topic::Rawtx => "rawtx",
+            Topic::Sequence => "sequence",
+        }
+    }
+}
+
+fn default_topics() -> Vec<Topic> {
+    vec![
+        Topic::Hashblock,
+        Topic::Rawblock,
+        Topic::Rawtx,
+        Topic::Sequence,
+    ]
+}
+
+fn default_reconnect_secs() -> u64 {
+    5
+}
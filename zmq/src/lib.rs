@@ -0,0 +1,31 @@
+mod config;
+mod listener;
+mod zmtp;
+
+pub use config::{Topic, ZmqConfig};
+pub use listener::{Listener, decode};
+pub use zmtp::Subscriber;
+
+use app::{NodeScopeApp, ShutdownPhase};
+use tokio::task::JoinSet;
+use tracing::info;
+
+/// Listen to the ZMQ notifications of every configured node until the app starts shutting down
+pub async fn run(configs: Vec<ZmqConfig>, app: NodeScopeApp) -> anyhow::Result<()> {
+    let mut listeners = JoinSet::new();
+    for config in configs {
+        info!(
+            "[node:{}] Listening to ZMQ notifications from {}",
+            config.node, config.address
+        );
+        let listener = Listener::new(config, app.clone());
+        listeners.spawn(async move { listener.run().await });
+    }
+
+    // Without any node to listen to this still waits, returning would shut the app down
+    app.shutdown().reached(ShutdownPhase::Draining).await;
+    while let Some(result) = listeners.join_next().await {
+        result?;
+    }
+    Ok(())
+}
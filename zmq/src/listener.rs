@@ -0,0 +1,210 @@
+use std::time::Duration;
+
+use app::{ItemAccepted, ItemKind, NodeScopeApp, ShutdownPhase};
+use bitcoin::hex::DisplayHex;
+use bitcoin::{Block, Transaction};
+use chrono::{DateTime, Utc};
+use tracing::{debug, info, warn};
+
+use crate::config::ZmqConfig;
+use crate::zmtp::Subscriber;
+
+/// Records the blocks and transactions one node accepts, as it notifies them over ZMQ
+pub struct Listener {
+    config: ZmqConfig,
+    app: NodeScopeApp,
+}
+
+impl Listener {
+    pub fn new(config: ZmqConfig, app: NodeScopeApp) -> Self {
+        Self { config, app }
+    }
+
+    /// Listen until the app starts shutting down
+    ///
+    /// When the endpoint goes away, e.g. because the node restarts, it is connected to again
+    /// after a while. Notifications sent in between are lost.
+    pub async fn run(&self) {
+        let reconnect = Duration::from_secs(self.config.reconnect_secs.max(1));
+        loop {
+            tokio::select! {
+                result = self.listen() => {
+                    if let Err(e) = result {
+                        warn!("[node:{}] ZMQ subscription to {} failed: {:#}", self.config.node, self.config.address, e);
+                    }
+                }
+                _ = self.app.shutdown().reached(ShutdownPhase::Draining) => break,
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(reconnect) => {}
+                _ = self.app.shutdown().reached(ShutdownPhase::Draining) => break,
+            }
+        }
+    }
+
+    /// Subscribe and record notifications until the connection fails
+    async fn listen(&self) -> anyhow::Result<()> {
+        let topics: Vec<_> = self
+            .config
+            .topics
+            .iter()
+            .map(|topic| topic.as_str())
+            .collect();
+        let mut subscriber = Subscriber::connect(&self.config.address, &topics).await?;
+        info!(
+            "[node:{}] Subscribed to {} over ZMQ",
+            self.config.node,
+            topics.join(", ")
+        );
+        loop {
+            let message = subscriber.next_message().await?;
+            let [topic, body, ..] = message.as_slice() else {
+                continue;
+            };
+            match decode(&self.config.node, topic, body, Utc::now()) {
+                Some(accepted) => self.app.store().upsert_acceptance(&accepted).await?,
+                None => debug!(
+                    "[node:{}] Ignored ZMQ notification on {}",
+                    self.config.node,
+                    String::from_utf8_lossy(topic)
+                ),
+            }
+        }
+    }
+}
+
+/// The item a ZMQ notification tells the node accepted, None for other notifications
+///
+/// `rawtx` is also sent for the transactions of connected blocks, those are only recorded
+/// as accepted then when they never entered the mempool.
+pub fn decode(node: &str, topic: &[u8], body: &[u8], now: DateTime<Utc>) -> Option<ItemAccepted> {
+    let accepted = |kind, hash: String, wtxid| ItemAccepted {
+        node: node.to_string(),
+        kind,
+        hash,
+        wtxid,
+        accepted_at: now,
+    };
+    match topic {
+        // Hashes are sent in the order they are displayed in
+        b"hashblock" if body.len() == 32 => {
+            Some(accepted(ItemKind::Block, body.to_lower_hex_string(), None))
+        }
+        b"hashtx" if body.len() == 32 => {
+            Some(accepted(ItemKind::Tx, body.to_lower_hex_string(), None))
+        }
+        b"rawblock" => {
+            let block: Block = bitcoin::consensus::deserialize(body).ok()?;
+            Some(accepted(
+                ItemKind::Block,
+                block.block_hash().to_string(),
+                None,
+            ))
+        }
+        b"rawtx" => {
+            let tx: Transaction = bitcoin::consensus::deserialize(body).ok()?;
+            Some(accepted(
+                ItemKind::Tx,
+                tx.compute_txid().to_string(),
+                Some(tx.compute_wtxid().to_string()),
+            ))
+        }
+        // A hash, a label and, for mempool changes, a sequence number
+        b"sequence" if body.len() >= 33 => {
+            let hash = body[..32].to_lower_hex_string();
+            match body[32] {
+                b'C' => Some(accepted(ItemKind::Block, hash, None)),
+                b'A' => Some(accepted(ItemKind::Tx, hash, None)),
+                // Disconnected blocks and transactions removed from the mempool
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Topic;
+    use crate::zmtp::tests::publisher;
+    use app::{AcceptanceFilter, StorageConfig};
+    use bitcoin::absolute::LockTime;
+    use bitcoin::transaction::Version;
+    use bitcoin::{Amount, ScriptBuf, TxIn, TxOut, Witness};
+
+    #[tokio::test]
+    async fn test_records_notified_acceptances() {
+        let dir = tempfile::tempdir().unwrap();
+        let app = NodeScopeApp::init(StorageConfig {
+            database_path: dir.path().join("nodescope.db"),
+        })
+        .await
+        .unwrap();
+
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                witness: Witness::from_slice(&[[1u8; 72]]),
+                ..Default::default()
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(10_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        let txid = tx.compute_txid().to_string();
+        let block_hash = [0xab; 32];
+        let mut sequence = [0xcd; 32].to_vec();
+        sequence.push(b'R');
+
+        let address = publisher(vec![
+            ("hashblock", block_hash.to_vec()),
+            ("rawtx", bitcoin::consensus::serialize(&tx)),
+            ("sequence", sequence),
+            // Not subscribed to
+            ("hashtx", [0xef; 32].to_vec()),
+        ])
+        .await;
+        let listener = Listener::new(
+            ZmqConfig {
+                node: "mainnet".to_string(),
+                address,
+                topics: vec![
+                    Topic::Hashblock,
+                    Topic::Rawblock,
+                    Topic::Rawtx,
+                    Topic::Sequence,
+                ],
+                reconnect_secs: 1,
+            },
+            app.clone(),
+        );
+        let listening = tokio::spawn(async move { listener.run().await });
+
+        let mut acceptances = Vec::new();
+        for _ in 0..100 {
+            acceptances = app
+                .store()
+                .list_acceptances(&AcceptanceFilter::default(), None, 10)
+                .await
+                .unwrap();
+            if acceptances.len() == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        app.shutdown().drain();
+        listening.await.unwrap();
+
+        acceptances.sort_by_key(|acceptance| acceptance.id);
+        assert_eq!(acceptances.len(), 2);
+        assert_eq!(acceptances[0].kind, ItemKind::Block);
+        assert_eq!(acceptances[0].hash, "ab".repeat(32));
+        assert_eq!(acceptances[1].kind, ItemKind::Tx);
+        assert_eq!(acceptances[1].hash, txid);
+        assert_eq!(acceptances[1].wtxid, Some(tx.compute_wtxid().to_string()));
+        assert_ne!(acceptances[1].wtxid.as_deref(), Some(txid.as_str()));
+    }
+}
@@ -0,0 +1,231 @@
+//! Just enough of ZMTP 3.0 to subscribe to a ZMQ publisher, such as bitcoind
+//!
+//! Only the NULL security mechanism is supported, which is all bitcoind offers.
+
+use anyhow::{Context, bail};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Frame flag: more frames of the same message follow
+const MORE: u8 = 0x01;
+/// Frame flag: the size takes 8 bytes instead of 1
+const LONG: u8 = 0x02;
+/// Frame flag: the frame is a command, not part of a message
+const COMMAND: u8 = 0x04;
+
+/// Largest frame accepted, a bit above bitcoind's largest blocks
+const MAX_FRAME_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Greeting announcing ZMTP 3.0 with the NULL mechanism
+///
+/// Version 3.0 is used as its subscriptions are plain messages, which newer
+/// publishers still accept.
+pub(crate) fn greeting() -> [u8; 64] {
+    let mut greeting = [0u8; 64];
+    greeting[0] = 0xff;
+    greeting[9] = 0x7f;
+    greeting[10] = 3;
+    greeting[12..16].copy_from_slice(b"NULL");
+    greeting
+}
+
+/// A frame, either part of a message or a command
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub flags: u8,
+    pub body: Vec<u8>,
+}
+
+impl Frame {
+    pub fn is_command(&self) -> bool {
+        self.flags & COMMAND != 0
+    }
+
+    pub fn has_more(&self) -> bool {
+        self.flags & MORE != 0
+    }
+}
+
+pub(crate) async fn read_frame(stream: &mut (impl AsyncRead + Unpin)) -> anyhow::Result<Frame> {
+    let flags = stream.read_u8().await?;
+    let size = if flags & LONG != 0 {
+        stream.read_u64().await?
+    } else {
+        stream.read_u8().await? as u64
+    };
+    if size > MAX_FRAME_SIZE {
+        bail!("ZMQ frame of {} bytes is too large", size);
+    }
+    let mut body = vec![0u8; size as usize];
+    stream.read_exact(&mut body).await?;
+    Ok(Frame { flags, body })
+}
+
+pub(crate) async fn write_frame(
+    stream: &mut (impl AsyncWrite + Unpin),
+    flags: u8,
+    body: &[u8],
+) -> anyhow::Result<()> {
+    let mut frame = Vec::with_capacity(body.len() + 9);
+    match u8::try_from(body.len()) {
+        Ok(size) => frame.extend_from_slice(&[flags, size]),
+        Err(_) => {
+            frame.push(flags | LONG);
+            frame.extend_from_slice(&(body.len() as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(body);
+    stream.write_all(&frame).await?;
+    Ok(())
+}
+
+/// Body of a READY command for the given socket type
+pub(crate) fn ready(socket_type: &str) -> Vec<u8> {
+    let mut body = vec![5];
+    body.extend_from_slice(b"READY");
+    body.push(11);
+    body.extend_from_slice(b"Socket-Type");
+    body.extend_from_slice(&(socket_type.len() as u32).to_be_bytes());
+    body.extend_from_slice(socket_type.as_bytes());
+    body
+}
+
+/// Name of a command frame, e.g. READY or ERROR
+fn command_name(body: &[u8]) -> &[u8] {
+    let len = body.first().copied().unwrap_or_default() as usize;
+    body.get(1..1 + len).unwrap_or_default()
+}
+
+/// A SUB socket connected to a single publisher
+pub struct Subscriber {
+    stream: TcpStream,
+}
+
+impl Subscriber {
+    /// Connect, shake hands and subscribe to the given topics
+    pub async fn connect(address: &str, topics: &[&str]) -> anyhow::Result<Self> {
+        let mut stream = TcpStream::connect(address)
+            .await
+            .context(format!("Couldn't connect to ZMQ publisher at {}", address))?;
+
+        stream.write_all(&greeting()).await?;
+        let mut peer = [0u8; 64];
+        stream.read_exact(&mut peer).await?;
+        if peer[0] != 0xff || peer[9] != 0x7f || peer[10] < 3 {
+            bail!("{} doesn't speak ZMTP 3", address);
+        }
+        if &peer[12..16] != b"NULL" {
+            bail!("{} requires a security mechanism other than NULL", address);
+        }
+
+        write_frame(&mut stream, COMMAND, &ready("SUB")).await?;
+        loop {
+            let frame = read_frame(&mut stream).await?;
+            if !frame.is_command() {
+                bail!("{} sent a message before its READY", address);
+            }
+            match command_name(&frame.body) {
+                b"READY" => break,
+                b"ERROR" => bail!("{} refused the connection", address),
+                _ => {}
+            }
+        }
+
+        for topic in topics {
+            let mut subscription = vec![1];
+            subscription.extend_from_slice(topic.as_bytes());
+            write_frame(&mut stream, 0, &subscription).await?;
+        }
+        Ok(Self { stream })
+    }
+
+    /// Wait for the next message, as its frames
+    pub async fn next_message(&mut self) -> anyhow::Result<Vec<Vec<u8>>> {
+        let mut parts = Vec::new();
+        loop {
+            let frame = read_frame(&mut self.stream).await?;
+            // Commands can't interrupt a message, and only heartbeats would be expected
+            if frame.is_command() {
+                continue;
+            }
+            let more = frame.has_more();
+            parts.push(frame.body);
+            if !more {
+                return Ok(parts);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// A ZMQ publisher like bitcoind's, sending each message to every subscriber of its topic
+    pub(crate) async fn publisher(messages: Vec<(&'static str, Vec<u8>)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut peer = [0u8; 64];
+            stream.read_exact(&mut peer).await.unwrap();
+            assert_eq!(&peer[12..16], b"NULL");
+            stream.write_all(&greeting()).await.unwrap();
+
+            let frame = read_frame(&mut stream).await.unwrap();
+            assert_eq!(frame.body, ready("SUB"));
+            write_frame(&mut stream, COMMAND, &ready("PUB"))
+                .await
+                .unwrap();
+
+            // Every subscription arrives before the first notification in practice too
+            let mut topics = Vec::new();
+            while topics.len() < 4 {
+                let frame = read_frame(&mut stream).await.unwrap();
+                assert_eq!(frame.body[0], 1);
+                topics.push(String::from_utf8(frame.body[1..].to_vec()).unwrap());
+            }
+
+            for (sequence, (topic, body)) in messages.into_iter().enumerate() {
+                if !topics.iter().any(|subscribed| subscribed == topic) {
+                    continue;
+                }
+                write_frame(&mut stream, MORE, topic.as_bytes())
+                    .await
+                    .unwrap();
+                write_frame(&mut stream, MORE, &body).await.unwrap();
+                write_frame(&mut stream, 0, &(sequence as u32).to_le_bytes())
+                    .await
+                    .unwrap();
+            }
+            // Keep the connection open until the subscriber is done
+            let _ = stream.read_u8().await;
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn test_subscribes_to_publisher() {
+        let address = publisher(vec![("hashblock", vec![7; 32]), ("rawtx", vec![1; 300])]).await;
+
+        let mut subscriber =
+            Subscriber::connect(&address, &["hashblock", "rawblock", "rawtx", "sequence"])
+                .await
+                .unwrap();
+        let message = subscriber.next_message().await.unwrap();
+        assert_eq!(
+            message,
+            [
+                b"hashblock".to_vec(),
+                vec![7; 32],
+                0u32.to_le_bytes().to_vec()
+            ]
+        );
+        // Larger than 255 bytes, so sent as a long frame
+        let message = subscriber.next_message().await.unwrap();
+        assert_eq!(message[1], vec![1; 300]);
+    }
+}